@@ -0,0 +1,108 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+// work spent compressing values, counted by the protocols which compress them and taken by the
+// stats receiver each window
+
+static COMPRESSED: AtomicUsize = ATOMIC_USIZE_INIT;
+static RAW_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+static COMPRESSED_BYTES: AtomicUsize = ATOMIC_USIZE_INIT;
+static COMPRESS_NS: AtomicUsize = ATOMIC_USIZE_INIT;
+static DECOMPRESSED: AtomicUsize = ATOMIC_USIZE_INIT;
+static DECOMPRESS_NS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Compression work done since the totals were last taken
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Totals {
+    /// values compressed, and their size in bytes before and after
+    pub compressed: u64,
+    pub raw_bytes: u64,
+    pub compressed_bytes: u64,
+    /// wall-clock nanoseconds spent compressing
+    pub compress_ns: u64,
+    /// values decompressed, and the wall-clock nanoseconds spent on it
+    pub decompressed: u64,
+    pub decompress_ns: u64,
+}
+
+impl Totals {
+    pub fn is_empty(&self) -> bool {
+        self.compressed == 0 && self.decompressed == 0
+    }
+
+    /// Bytes before compression for each byte after, zero if nothing was compressed
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 0.0;
+        }
+        self.raw_bytes as f64 / self.compressed_bytes as f64
+    }
+
+    pub fn add(&mut self, other: &Totals) {
+        self.compressed += other.compressed;
+        self.raw_bytes += other.raw_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+        self.compress_ns += other.compress_ns;
+        self.decompressed += other.decompressed;
+        self.decompress_ns += other.decompress_ns;
+    }
+}
+
+/// Count a value of `raw` bytes compressed to `compressed` bytes in `ns` nanoseconds
+pub fn compressed(raw: usize, compressed: usize, ns: u64) {
+    COMPRESSED.fetch_add(1, Ordering::Relaxed);
+    RAW_BYTES.fetch_add(raw, Ordering::Relaxed);
+    COMPRESSED_BYTES.fetch_add(compressed, Ordering::Relaxed);
+    COMPRESS_NS.fetch_add(ns as usize, Ordering::Relaxed);
+}
+
+/// Count a value decompressed in `ns` nanoseconds
+pub fn decompressed(ns: u64) {
+    DECOMPRESSED.fetch_add(1, Ordering::Relaxed);
+    DECOMPRESS_NS.fetch_add(ns as usize, Ordering::Relaxed);
+}
+
+/// The totals since they were last taken, starting them again from zero
+pub fn take() -> Totals {
+    let take = |counter: &AtomicUsize| counter.swap(0, Ordering::Relaxed) as u64;
+    Totals {
+        compressed: take(&COMPRESSED),
+        raw_bytes: take(&RAW_BYTES),
+        compressed_bytes: take(&COMPRESSED_BYTES),
+        compress_ns: take(&COMPRESS_NS),
+        decompressed: take(&DECOMPRESSED),
+        decompress_ns: take(&DECOMPRESS_NS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio() {
+        let totals = Totals {
+            compressed: 2,
+            raw_bytes: 3000,
+            compressed_bytes: 1000,
+            ..Totals::default()
+        };
+        assert_eq!(totals.ratio(), 3.0);
+        assert_eq!(Totals::default().ratio(), 0.0);
+        assert!(Totals::default().is_empty());
+    }
+}
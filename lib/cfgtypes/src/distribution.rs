@@ -0,0 +1,182 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use tools;
+
+/// How key ids are drawn from a `Keyspace`
+#[derive(Clone, Debug)]
+pub enum Distribution {
+    /// Every key id is equally likely
+    Uniform,
+    /// Low key ids are the most popular
+    Zipf(Zipf),
+    /// Key ids are used in order, wrapping around at the cardinality
+    Sequential(usize),
+}
+
+/// A range of `cardinality` key ids and the distribution they are drawn from
+#[derive(Clone, Debug)]
+pub struct Keyspace {
+    cardinality: usize,
+    distribution: Distribution,
+}
+
+impl Keyspace {
+    pub fn new(cardinality: usize, distribution: Distribution) -> Keyspace {
+        Keyspace {
+            cardinality: cardinality,
+            distribution: distribution,
+        }
+    }
+
+    pub fn cardinality(&self) -> usize {
+        self.cardinality
+    }
+
+    /// Draw the next key id, in the range [0, cardinality)
+    pub fn next(&mut self) -> usize {
+        match self.distribution {
+            Distribution::Uniform => tools::random_range(self.cardinality),
+            Distribution::Zipf(ref zipf) => zipf.sample() - 1,
+            Distribution::Sequential(ref mut next) => {
+                let id = *next;
+                *next = (id + 1) % self.cardinality;
+                id
+            }
+        }
+    }
+}
+
+/// Zipf sampler using rejection-inversion
+///
+/// Samples are drawn in constant time without a table of the cumulative distribution, see
+/// Hörmann and Derflinger, "Rejection-inversion to generate variates from monotone discrete
+/// distributions".
+#[derive(Clone, Debug)]
+pub struct Zipf {
+    n: f64,
+    exponent: f64,
+    h_integral_x1: f64,
+    h_integral_n: f64,
+    s: f64,
+}
+
+impl Zipf {
+    /// Create a sampler for the ranks [1, n], exponent must be positive
+    pub fn new(n: usize, exponent: f64) -> Zipf {
+        let mut zipf = Zipf {
+            n: n as f64,
+            exponent: exponent,
+            h_integral_x1: 0.0,
+            h_integral_n: 0.0,
+            s: 0.0,
+        };
+        zipf.h_integral_x1 = zipf.h_integral(1.5) - 1.0;
+        zipf.h_integral_n = zipf.h_integral(zipf.n + 0.5);
+        zipf.s = 2.0 - zipf.h_integral_inverse(zipf.h_integral(2.5) - zipf.h(2.0));
+        zipf
+    }
+
+    /// Draw a rank in [1, n]
+    pub fn sample(&self) -> usize {
+        loop {
+            let u = self.h_integral_n +
+                    tools::random::<f64>() * (self.h_integral_x1 - self.h_integral_n);
+            let x = self.h_integral_inverse(u);
+            let k = (x + 0.5).floor().max(1.0).min(self.n);
+            if k - x <= self.s || u >= self.h_integral(k + 0.5) - self.h(k) {
+                return k as usize;
+            }
+        }
+    }
+
+    fn h(&self, x: f64) -> f64 {
+        (-self.exponent * x.ln()).exp()
+    }
+
+    fn h_integral(&self, x: f64) -> f64 {
+        let log_x = x.ln();
+        helper2((1.0 - self.exponent) * log_x) * log_x
+    }
+
+    fn h_integral_inverse(&self, x: f64) -> f64 {
+        let t = (x * (1.0 - self.exponent)).max(-1.0);
+        (helper1(t) * x).exp()
+    }
+}
+
+// ln(1 + x) / x, accurate near zero
+fn helper1(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.ln_1p() / x
+    } else {
+        1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x))
+    }
+}
+
+// (exp(x) - 1) / x, accurate near zero
+fn helper2(x: f64) -> f64 {
+    if x.abs() > 1e-8 {
+        x.exp_m1() / x
+    } else {
+        1.0 + x * 0.5 * (1.0 + x / 3.0 * (1.0 + 0.25 * x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tools;
+
+    #[test]
+    fn sequential_wraps() {
+        let mut keys = Keyspace::new(3, Distribution::Sequential(0));
+        let ids: Vec<usize> = (0..7).map(|_| keys.next()).collect();
+        assert_eq!(ids, vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn uniform_in_range() {
+        let mut keys = Keyspace::new(10, Distribution::Uniform);
+        for _ in 0..1000 {
+            assert!(keys.next() < 10);
+        }
+    }
+
+    #[test]
+    fn zipf_skewed() {
+        tools::seed(1);
+        let mut keys = Keyspace::new(100, Distribution::Zipf(Zipf::new(100, 1.0)));
+        let mut counts = vec![0; 100];
+        for _ in 0..10000 {
+            counts[keys.next()] += 1;
+        }
+        // rank 1 of 100 at exponent 1 is drawn with probability ~0.19
+        assert!(counts[0] > 1500 && counts[0] < 2300);
+        assert!(counts[0] > counts[1]);
+        assert!(counts[1] > counts[50]);
+    }
+
+    #[test]
+    fn zipf_seeded() {
+        let zipf = Zipf::new(1000, 0.99);
+        tools::seed(7);
+        let a: Vec<usize> = (0..100).map(|_| zipf.sample()).collect();
+        tools::seed(7);
+        let b: Vec<usize> = (0..100).map(|_| zipf.sample()).collect();
+        assert_eq!(a, b);
+        assert!(a.iter().all(|&k| k >= 1 && k <= 1000));
+    }
+}
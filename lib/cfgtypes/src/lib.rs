@@ -21,8 +21,12 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 use toml::Value;
 
+pub mod compression;
+pub mod distribution;
 pub mod tools;
 
+use distribution::{Distribution, Keyspace, Zipf};
+
 pub type CResult<T> = Result<T, String>;
 
 pub struct BenchmarkWorkload {
@@ -47,6 +51,46 @@ pub struct ProtocolConfig {
     pub workloads: Vec<BenchmarkWorkload>,
 }
 
+/// A request for the work queue
+#[derive(Clone, Debug)]
+pub struct WorkItem {
+    /// bytes to send to the server
+    pub bytes: Vec<u8>,
+    /// key whose derived value a hit must carry, when validating responses
+    pub validate: Option<Vec<u8>>,
+    /// attempts already made at this request, zero until it is retried
+    pub attempts: usize,
+    /// when the first attempt was sent, the latency of a retried request is measured from here
+    pub started: Option<u64>,
+    /// index of the workload which generated the request
+    pub workload: Option<usize>,
+    /// position of the request in its sequence, for the ops of a sequence workload
+    pub op: Option<usize>,
+    /// the rest of the sequence the request starts, sent after it on the same connection
+    pub then: Vec<WorkItem>,
+    /// the next request of the sequence waits for this one's response, rather than being
+    /// pipelined behind it
+    pub serial: bool,
+    /// the request is completed from the response to the one before it in the sequence
+    pub follows: bool,
+}
+
+impl WorkItem {
+    pub fn new(bytes: Vec<u8>) -> WorkItem {
+        WorkItem {
+            bytes: bytes,
+            validate: None,
+            attempts: 0,
+            started: None,
+            workload: None,
+            op: None,
+            then: Vec::new(),
+            serial: false,
+            follows: false,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ParsedResponse {
     Error(String),
@@ -55,6 +99,8 @@ pub enum ParsedResponse {
     Invalid,
     Miss,
     Ok,
+    /// a response with a status code which isn't a success, eg: an http 404
+    Status(u16),
     Unknown,
     Version(String),
 }
@@ -64,8 +110,18 @@ pub trait ProtocolGen : Send {
     /// Generate the next buffer to send to the server
     fn generate_message(&mut self) -> Vec<u8>;
 
+    /// Generate the next request for the work queue
+    fn generate_work(&mut self) -> WorkItem {
+        WorkItem::new(self.generate_message())
+    }
+
     /// The method being called on the server
     fn method(&self) -> &str;
+
+    /// Names of the ops of a sequence, in order, whose latencies are recorded apart
+    fn ops(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 /// Factory for `ProtocolParse` instances
@@ -86,12 +142,67 @@ pub trait ProtocolParseFactory : Send + Sync {
 pub trait ProtocolParse {
     /// Parse the response buffer
     fn parse(&self, bytes: &[u8]) -> ParsedResponse;
+
+    /// Length of the first complete response in the buffer, if there is one
+    ///
+    /// Protocols which can't delimit their responses treat the whole buffer as a single
+    /// response, which is only correct without pipelining.
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        match self.parse(bytes) {
+            ParsedResponse::Incomplete => None,
+            _ => Some(bytes.len()),
+        }
+    }
+
+    /// Length of a response the server ended by closing the connection, for protocols whose
+    /// responses may be delimited that way
+    fn frame_closed(&self, _: &[u8]) -> Option<usize> {
+        None
+    }
+
+    /// Whether the server closes the connection after a complete response, which the
+    /// connection is then replaced for
+    fn closes(&self, _: &[u8]) -> bool {
+        false
+    }
+
+    /// Value carried by a complete response, for protocols which support validation
+    fn value<'a>(&self, _: &'a [u8]) -> Option<&'a [u8]> {
+        None
+    }
+
+    /// Whether a complete hit carries the value derived from the key
+    ///
+    /// Protocols which transform values, eg: by compressing them, compare the original value.
+    fn validate(&self, key: &[u8], bytes: &[u8]) -> bool {
+        self.value(bytes).map_or(false, |value| tools::is_valid_value(key, value))
+    }
+
+    /// Whether a complete hit carries the flags stored with the key, for protocols which
+    /// store flags alongside the value
+    fn validate_flags(&self, _: &[u8], _: &[u8]) -> bool {
+        true
+    }
+
+    /// Number of the sequence on the key which stored the value a complete hit carries, for
+    /// protocols which support sequences
+    fn sequence_number(&self, key: &[u8], bytes: &[u8]) -> Option<u64> {
+        self.value(bytes).and_then(|value| tools::sequence_number(key, value))
+    }
+
+    /// Complete a request of a sequence from the response to the request before it, eg: with
+    /// a token the server handed out
+    fn follow(&self, request: &[u8], _: &[u8]) -> Vec<u8> {
+        request.to_vec()
+    }
 }
 
 /// Reusable paramter type with parser
 pub trait Ptype: Sized {
     /// generate new state
     fn regen(&mut self);
+    /// generate the state for a key id
+    fn reseed(&mut self, seed: usize);
     /// parse a `Ptype` from a toml tree
     fn parse(seed: usize, size: usize, table: &BTreeMap<String, Value>) -> CResult<Self>;
 }
@@ -111,6 +222,8 @@ pub struct Parameter<T: Ptype> {
     pub style: Style,
     /// whether the parameter should be regenerated on each use
     pub regenerate: bool,
+    /// key ids random values are drawn from, instead of unbounded random values
+    pub keyspace: Option<Keyspace>,
     /// current value of the `Parameter`
     pub value: T,
 }
@@ -120,6 +233,7 @@ impl<T: Default + Ptype> Default for Parameter<T> {
         Parameter {
             style: Style::Static,
             regenerate: false,
+            keyspace: None,
             value: T::default(),
         }
     }
@@ -131,7 +245,14 @@ impl<T: Ptype> Parameter<T> {
     /// If the parameter is flagged to not regenerate or was seeded, it isn't regenerated.
     pub fn regen(&mut self) {
         if self.regenerate && self.style == Style::Random {
-            self.value.regen()
+            self.next_value()
+        }
+    }
+
+    fn next_value(&mut self) {
+        match self.keyspace {
+            Some(ref mut keyspace) => self.value.reseed(keyspace.next()),
+            None => self.value.regen(),
         }
     }
 }
@@ -161,16 +282,61 @@ pub fn extract_parameter<T: Ptype>(index: usize,
                               .and_then(|k| k.as_bool())
                               .unwrap_or(false);
 
-    let mut value = try!(T::parse(seed, size, parameter));
-
-    // initialize with a random value if that is what is needed
-    if style == Style::Random {
-        value.regen();
+    let keyspace = try!(extract_keyspace(parameter));
+    if keyspace.is_some() && style != Style::Random {
+        return Err("a parameter with a cardinality must have style random".to_owned());
     }
 
-    Ok(Parameter {
+    let value = try!(T::parse(seed, size, parameter));
+
+    let mut parameter = Parameter {
         style: style,
         regenerate: regenerate,
+        keyspace: keyspace,
         value: value,
-    })
+    };
+
+    // initialize with a random value if that is what is needed
+    if parameter.style == Style::Random {
+        parameter.next_value();
+    }
+
+    Ok(parameter)
+}
+
+/// Extract the optional `Keyspace` of a parameter from the toml tree
+fn extract_keyspace(parameter: &BTreeMap<String, Value>) -> CResult<Option<Keyspace>> {
+    let cardinality = parameter.get("cardinality")
+                               .and_then(|k| k.as_integer())
+                               .map(|i| i as usize);
+
+    let distribution = parameter.get("distribution")
+                                .and_then(|k| k.as_str())
+                                .unwrap_or("uniform");
+
+    let cardinality = match cardinality {
+        Some(0) => return Err("parameter cardinality must be at least 1".to_owned()),
+        Some(c) => c,
+        None if distribution == "uniform" => return Ok(None),
+        None => return Err(format!("{} distribution requires a cardinality", distribution)),
+    };
+
+    let distribution = match distribution {
+        "uniform" => Distribution::Uniform,
+        "sequential" => Distribution::Sequential(0),
+        "zipf" => {
+            let exponent = parameter.get("exponent")
+                                    .and_then(|k| {
+                                        k.as_float().or_else(|| k.as_integer().map(|i| i as f64))
+                                    })
+                                    .unwrap_or(1.0);
+            if exponent <= 0.0 {
+                return Err("zipf exponent must be positive".to_owned());
+            }
+            Distribution::Zipf(Zipf::new(cardinality, exponent))
+        }
+        other => return Err(format!("bad parameter distribution: {}", other)),
+    };
+
+    Ok(Some(Keyspace::new(cardinality, distribution)))
 }
@@ -14,11 +14,50 @@
 //  limitations under the License.
 
 use pad::{PadStr, Alignment};
-use rand::{thread_rng, Rng};
+use rand::{thread_rng, Rand, Rng, SeedableRng, XorShiftRng};
+use std::cell::RefCell;
+use std::str;
 
+// per-thread generator, randomly seeded unless `seed()` is called
+thread_local!(static RNG: RefCell<XorShiftRng> = RefCell::new(thread_rng().gen()));
+
+/// Seed the random number generator of the current thread
+///
+/// Values generated on a thread after seeding it with the same seed are identical.
+pub fn seed(seed: u64) {
+    let lo = seed as u32;
+    let hi = (seed >> 32) as u32;
+    // xorshift can't be seeded with all zeros, these words can't all be zero
+    let words = [lo, hi, lo ^ 0x9E37_79B9, hi ^ 0x7F4A_7C15];
+    RNG.with(|r| *r.borrow_mut() = XorShiftRng::from_seed(words));
+}
+
+/// Derive an independent seed for the numbered stream of a run with the given seed
+///
+/// Uses the splitmix64 finalizer, so neighboring seeds and indexes produce unrelated streams.
+pub fn derive_seed(seed: u64, index: usize) -> u64 {
+    let mut z = seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Random value from the current thread's generator, floats are in the range [0, 1)
+pub fn random<T: Rand>() -> T {
+    RNG.with(|r| r.borrow_mut().gen())
+}
+
+/// Random integer in the range [0, limit)
+pub fn random_range(limit: usize) -> usize {
+    RNG.with(|r| r.borrow_mut().gen_range(0, limit))
+}
 
 pub fn random_string(size: usize) -> String {
-    thread_rng().gen_ascii_chars().take(size).collect()
+    RNG.with(|r| {
+        let mut rng = r.borrow_mut();
+        let s: String = rng.gen_ascii_chars().take(size).collect();
+        s
+    })
 }
 
 pub fn random_bytes(size: usize) -> Vec<u8> {
@@ -29,3 +68,124 @@ pub fn seeded_string(size: usize, seed: usize) -> String {
     let s = format!("{}", seed);
     s.pad(size, '0', Alignment::Right, true)
 }
+
+/// Value of `size` bytes derived from the key, for validating responses
+///
+/// The FNV-1a hash of the key in hex, repeated to length. A value of any length can be checked
+/// against the key, so reads don't need to know the size that was written.
+pub fn validation_value(key: &[u8], size: usize) -> String {
+    format!("{:016x}", key_hash(key)).chars().cycle().take(size).collect()
+}
+
+/// FNV-1a hash of a key, for anything stored which must be derived again from the key alone
+pub fn key_hash(key: &[u8]) -> u64 {
+    key.iter().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Whether the value is the one derived from the key by `validation_value`
+pub fn is_valid_value(key: &[u8], value: &[u8]) -> bool {
+    !value.is_empty() && validation_value(key, value.len()).as_bytes() == value
+}
+
+/// Key the `number`th sequence on a key validates its reads with, eg: `key#7`
+pub fn sequence_key(key: &[u8], number: u64) -> Vec<u8> {
+    let mut derived = key.to_vec();
+    derived.extend_from_slice(format!("#{}", number).as_bytes());
+    derived
+}
+
+/// The key and the number of a sequence key
+pub fn split_sequence_key(derived: &[u8]) -> Option<(&[u8], u64)> {
+    let at = match derived.iter().rposition(|b| *b == b'#') {
+        Some(at) => at,
+        None => return None,
+    };
+    str::from_utf8(&derived[(at + 1)..])
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .map(|n| (&derived[..at], n))
+}
+
+/// Value of `size` bytes stored by the `number`th sequence on the key
+///
+/// The number in hex, then the value derived from the sequence key, so a read can tell which
+/// sequence stored a value it finds. Values shorter than the number can't be told apart.
+pub fn sequence_value(key: &[u8], number: u64, size: usize) -> String {
+    format!("{:016x}", number)
+        .chars()
+        .chain(validation_value(&sequence_key(key, number), size).chars())
+        .take(size)
+        .collect()
+}
+
+/// The number of the sequence on the key whose `sequence_value` the value is, if any
+pub fn sequence_number(key: &[u8], value: &[u8]) -> Option<u64> {
+    if value.len() < 16 {
+        return None;
+    }
+    let number = str::from_utf8(&value[..16])
+                     .ok()
+                     .and_then(|n| u64::from_str_radix(n, 16).ok());
+    match number {
+        Some(n) if sequence_value(key, n, value.len()).as_bytes() == value => Some(n),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_values_repeat() {
+        seed(42);
+        let a = (random_string(16), random_range(1000), random::<f64>());
+        seed(42);
+        let b = (random_string(16), random_range(1000), random::<f64>());
+        assert_eq!(a, b);
+
+        seed(43);
+        assert!(random_string(16) != a.0);
+    }
+
+    #[test]
+    fn validation_values() {
+        let value = validation_value(b"key", 40);
+        assert_eq!(value.len(), 40);
+        assert_eq!(&value[..16], &value[16..32]);
+        assert!(is_valid_value(b"key", value.as_bytes()));
+        assert!(is_valid_value(b"key", &value.as_bytes()[..5]));
+        assert!(!is_valid_value(b"kez", value.as_bytes()));
+        assert!(!is_valid_value(b"key", b"0000000000000000"));
+        assert!(!is_valid_value(b"key", b""));
+    }
+
+    #[test]
+    fn sequence_values() {
+        let derived = sequence_key(b"a#key", 7);
+        assert_eq!(derived, b"a#key#7".to_vec());
+        assert_eq!(split_sequence_key(&derived), Some((&b"a#key"[..], 7)));
+        assert_eq!(split_sequence_key(b"key"), None);
+        assert_eq!(split_sequence_key(b"key#x"), None);
+
+        let value = sequence_value(b"key", 7, 40);
+        assert_eq!(value.len(), 40);
+        assert_eq!(&value[..16], "0000000000000007");
+        assert_eq!(&value[16..], &validation_value(&sequence_key(b"key", 7), 24)[..]);
+        assert_eq!(sequence_number(b"key", value.as_bytes()), Some(7));
+        assert_eq!(sequence_number(b"key", sequence_value(b"key", 3, 20).as_bytes()), Some(3));
+        // a value another key's sequence stored, or no sequence did, isn't numbered
+        assert_eq!(sequence_number(b"kez", value.as_bytes()), None);
+        assert_eq!(sequence_number(b"key", validation_value(b"key", 40).as_bytes()), None);
+        assert_eq!(sequence_number(b"key", &value.as_bytes()[..15]), None);
+    }
+
+    #[test]
+    fn derived_seeds_differ() {
+        assert_eq!(derive_seed(42, 0), derive_seed(42, 0));
+        assert!(derive_seed(42, 0) != derive_seed(42, 1));
+        assert!(derive_seed(42, 1) != derive_seed(43, 0));
+    }
+}
@@ -54,6 +54,10 @@ impl Ptype for EchoData {
         self.bytes = tools::random_bytes(self.size);
     }
 
+    fn reseed(&mut self, seed: usize) {
+        self.bytes = (seed..(seed + self.size)).map(|i| i as u8).collect();
+    }
+
     fn parse(seed: usize, size: usize, _: &BTreeMap<String, Value>) -> CResult<Self> {
         let bts = (seed..(seed + size)).map(|i| i as u8).collect();
         Ok(EchoData {
@@ -0,0 +1,64 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+#[cfg(feature = "unstable")]
+extern crate test;
+
+/// HTTP/1.1 request, with a Content-Length when there is a body
+pub fn request(method: &str, path: &str, headers: &[(String, String)], body: &str) -> String {
+    let mut request = format!("{} {} HTTP/1.1\r\n", method, path);
+    for &(ref name, ref value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    request
+}
+
+#[test]
+fn test_request() {
+    let headers = vec![("Host".to_owned(), "localhost".to_owned())];
+    assert_eq!(request("GET", "/", &headers, ""),
+               "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert_eq!(request("POST", "/items", &headers, "abc"),
+               "POST /items HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nabc");
+}
+
+#[cfg(feature = "unstable")]
+#[bench]
+fn request_benchmark(b: &mut test::Bencher) {
+    let headers = vec![("Host".to_owned(), "localhost".to_owned())];
+    b.iter(|| request("GET", "/", &headers, ""));
+}
+
+/// Replace each `{N}` in the template with the Nth value
+pub fn substitute(template: &str, values: &[&str]) -> String {
+    let mut result = template.to_owned();
+    for (i, value) in values.iter().enumerate() {
+        result = result.replace(&format!("{{{}}}", i), value);
+    }
+    result
+}
+
+#[test]
+fn test_substitute() {
+    assert_eq!(substitute("/users/{0}/items/{1}", &["abc", "xyz"]),
+               "/users/abc/items/xyz");
+    assert_eq!(substitute("/users/{0}/{0}", &["abc"]), "/users/abc/abc");
+    assert_eq!(substitute("/static", &["abc"]), "/static");
+}
@@ -0,0 +1,245 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+#![cfg_attr(feature = "unstable", feature(test))]
+
+#[macro_use]
+extern crate log;
+extern crate rpcperf_cfgtypes as cfgtypes;
+extern crate toml;
+extern crate getopts;
+
+mod gen;
+mod parse;
+
+use cfgtypes::*;
+use getopts::Matches;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use toml::Value;
+
+#[derive(Clone, Debug)]
+struct HttpData {
+    size: usize,
+    string: String,
+}
+
+impl Ptype for HttpData {
+    fn regen(&mut self) {
+        self.string = tools::random_string(self.size);
+    }
+
+    fn reseed(&mut self, seed: usize) {
+        self.string = tools::seeded_string(self.size, seed);
+    }
+
+    fn parse(seed: usize, size: usize, _: &BTreeMap<String, Value>) -> CResult<Self> {
+        Ok(HttpData {
+            size: size,
+            string: tools::seeded_string(size, seed),
+        })
+    }
+}
+
+struct HttpGen {
+    method: String,
+    /// path and body may refer to the Nth parameter as `{N}`
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+    parameters: Vec<Parameter<HttpData>>,
+}
+
+struct HttpParse;
+
+impl ProtocolGen for HttpGen {
+    fn generate_message(&mut self) -> Vec<u8> {
+        for p in &mut self.parameters {
+            p.regen();
+        }
+        let values: Vec<&str> = self.parameters.iter().map(|p| p.value.string.as_str()).collect();
+        let path = gen::substitute(&self.path, &values);
+        let body = gen::substitute(&self.body, &values);
+        gen::request(&self.method, &path, &self.headers, &body).into_bytes()
+    }
+
+    fn method(&self) -> &str {
+        &self.method
+    }
+}
+
+impl ProtocolParse for HttpParse {
+    fn parse(&self, bytes: &[u8]) -> ParsedResponse {
+        parse::parse_response(bytes)
+    }
+
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_response(bytes)
+    }
+
+    fn frame_closed(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_closed(bytes)
+    }
+
+    fn closes(&self, bytes: &[u8]) -> bool {
+        parse::closes_connection(bytes)
+    }
+}
+
+impl ProtocolParseFactory for HttpParse {
+    fn new(&self) -> Box<ProtocolParse> {
+        Box::new(HttpParse)
+    }
+
+    fn name(&self) -> &str {
+        "http"
+    }
+}
+
+/// Load the http benchmark configuration from the config toml
+pub fn load_config(table: &BTreeMap<String, Value>, matches: &Matches) -> CResult<ProtocolConfig> {
+
+    let mut ws = Vec::new();
+
+    // requests are the same for every server, so they name the first
+    let hosts = server_hosts(matches);
+    let host = hosts.first().map_or("localhost", |h| h.as_str());
+    if hosts.iter().any(|h| h != host) {
+        warn!("http: requests to every server have Host: {}, unless a header is set", host);
+    }
+
+    if let Some(&Value::Array(ref workloads)) = table.get("workload") {
+        for workload in workloads {
+            if let Value::Table(ref workload) = *workload {
+                let w = try!(extract_workload(workload, host));
+                ws.push(w);
+            } else {
+                return Err("malformed config: workload must be a struct".to_owned());
+            }
+        }
+
+        Ok(ProtocolConfig {
+            protocol: Arc::new(HttpParse),
+            workloads: ws,
+        })
+    } else {
+        Err("http: no workloads specified".to_owned())
+    }
+}
+
+// HOST:PORT of each server, without the transport or weight
+fn server_hosts(matches: &Matches) -> Vec<String> {
+    matches.opt_strs("server")
+           .iter()
+           .map(|server| {
+               let server = server.trim_left_matches("udp://");
+               // IPv6 hosts are bracketed, only look for the weight after them
+               let host_end = server.rfind(']').unwrap_or(0);
+               match server[host_end..].split(':').count() {
+                   3 => server[..server.rfind(':').unwrap()].to_owned(),
+                   _ => server.to_owned(),
+               }
+           })
+           .collect()
+}
+
+fn extract_workload(workload: &BTreeMap<String, Value>, host: &str) -> CResult<BenchmarkWorkload> {
+    let rate = workload.get("rate")
+                       .and_then(|k| k.as_integer())
+                       .unwrap_or(0);
+
+    let method = workload.get("method")
+                         .and_then(|k| k.as_str())
+                         .unwrap_or("GET")
+                         .to_uppercase();
+
+    // the response to a HEAD has a Content-Length but no body, so it can't be framed
+    if method == "HEAD" {
+        return Err("http: method HEAD is not supported".to_owned());
+    }
+
+    let path = workload.get("path")
+                       .and_then(|k| k.as_str())
+                       .unwrap_or("/")
+                       .to_owned();
+
+    if !path.starts_with('/') {
+        return Err(format!("http: path must start with '/': {}", path));
+    }
+
+    let name = workload.get("name")
+                       .and_then(|k| k.as_str())
+                       .unwrap_or(method.as_str())
+                       .to_owned();
+
+    let body = workload.get("body")
+                       .and_then(|k| k.as_str())
+                       .unwrap_or("")
+                       .to_owned();
+
+    let mut headers = Vec::new();
+    match workload.get("headers") {
+        Some(&Value::Table(ref h)) => {
+            for (header, value) in h {
+                match value.as_str() {
+                    Some(v) => headers.push((header.clone(), v.to_owned())),
+                    None => return Err(format!("http: header {} must be a string", header)),
+                }
+            }
+        }
+        Some(_) => return Err("malformed config: 'headers' must be a table".to_owned()),
+        None => {}
+    }
+
+    // HTTP/1.1 requires a Host header
+    if !headers.iter().any(|&(ref h, _)| h.to_lowercase() == "host") {
+        headers.insert(0, ("Host".to_owned(), host.to_owned()));
+    }
+
+    let mut parameters = Vec::new();
+    match workload.get("parameter") {
+        Some(&Value::Array(ref params)) => {
+            for (i, param) in params.iter().enumerate() {
+                match *param {
+                    Value::Table(ref parameter) => {
+                        parameters.push(try!(extract_parameter(i, parameter)));
+                    }
+                    _ => {
+                        return Err("malformed config: a parameter must be a struct".to_owned());
+                    }
+                }
+            }
+        }
+        Some(_) => return Err("malformed config: 'parameter' must be an array".to_owned()),
+        None => {}
+    }
+
+    for i in 0..parameters.len() {
+        let placeholder = format!("{{{}}}", i);
+        if !path.contains(&placeholder) && !body.contains(&placeholder) {
+            warn!("http: parameter {} is not used by {} {}", i, method, path);
+        }
+    }
+
+    let gen = Box::new(HttpGen {
+        method: method,
+        path: path,
+        headers: headers,
+        body: body,
+        parameters: parameters,
+    });
+
+    Ok(BenchmarkWorkload::new(name, rate as usize, gen))
+}
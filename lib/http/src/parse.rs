@@ -0,0 +1,348 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+pub use cfgtypes::ParsedResponse;
+
+use std::str;
+
+// how the end of the body is found
+enum Body {
+    Empty,
+    Length(usize),
+    Chunked,
+    // delimited by the server closing the connection
+    Close,
+}
+
+fn find(bytes: &[u8], pattern: &[u8]) -> Option<usize> {
+    bytes.windows(pattern.len()).position(|w| w == pattern)
+}
+
+// status and headers of the response, with the length of the head and whether the server
+// closes the connection after it
+fn parse_head(response: &[u8]) -> Result<Option<(u16, Body, usize, bool)>, ()> {
+    let end = match find(response, b"\r\n\r\n") {
+        Some(i) => i,
+        None => {
+            // wait for the rest of the head, unless it can't be http
+            let n = response.len().min(5);
+            if response[..n] == b"HTTP/"[..n] {
+                return Ok(None);
+            }
+            return Err(());
+        }
+    };
+    let head = match str::from_utf8(&response[..end]) {
+        Ok(h) => h,
+        Err(_) => return Err(()),
+    };
+    let mut lines = head.split("\r\n");
+
+    // eg: `HTTP/1.1 200 OK`
+    let mut status_line = lines.next().unwrap_or("").split_whitespace();
+    let version = status_line.next().unwrap_or("");
+    if !version.starts_with("HTTP/1.") {
+        return Err(());
+    }
+    // connections are only kept alive by default from HTTP/1.1
+    let mut close = version == "HTTP/1.0";
+    let status = match status_line.next().and_then(|s| s.parse::<u16>().ok()) {
+        Some(s) if s >= 100 && s < 600 => s,
+        _ => return Err(()),
+    };
+
+    let mut length = None;
+    let mut chunked = false;
+    for line in lines {
+        let colon = match line.find(':') {
+            Some(i) => i,
+            None => return Err(()),
+        };
+        let (name, value) = line.split_at(colon);
+        let value = value[1..].trim();
+        match name.trim().to_lowercase().as_str() {
+            "content-length" => {
+                match value.parse::<usize>() {
+                    Ok(l) => length = Some(l),
+                    Err(_) => return Err(()),
+                }
+            }
+            "transfer-encoding" => {
+                chunked = value.to_lowercase().contains("chunked");
+            }
+            "connection" => {
+                for option in value.split(',').map(|o| o.trim().to_lowercase()) {
+                    match option.as_str() {
+                        "close" => close = true,
+                        "keep-alive" => close = false,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let body = if status < 200 || status == 204 || status == 304 {
+        Body::Empty
+    } else if chunked {
+        Body::Chunked
+    } else if let Some(l) = length {
+        Body::Length(l)
+    } else {
+        close = true;
+        Body::Close
+    };
+    Ok(Some((status, body, end + 4, close)))
+}
+
+// length of a chunked body, through the last chunk and any trailers
+fn chunked_length(body: &[u8]) -> Result<Option<usize>, ()> {
+    let mut position = 0;
+    loop {
+        let end = match find(&body[position..], b"\r\n") {
+            Some(i) => position + i,
+            None => return Ok(None),
+        };
+        // the size is hex, and may be followed by extensions
+        let size = str::from_utf8(&body[position..end])
+                       .ok()
+                       .and_then(|l| l.split(';').next())
+                       .and_then(|s| usize::from_str_radix(s.trim(), 16).ok());
+        match size {
+            Some(0) => {
+                position = end + 2;
+                loop {
+                    let end = match find(&body[position..], b"\r\n") {
+                        Some(i) => position + i,
+                        None => return Ok(None),
+                    };
+                    if end == position {
+                        return Ok(Some(end + 2));
+                    }
+                    position = end + 2;
+                }
+            }
+            Some(n) => {
+                position = end + 2 + n + 2;
+                if position > body.len() {
+                    return Ok(None);
+                }
+                if &body[(position - 2)..position] != b"\r\n" {
+                    return Err(());
+                }
+            }
+            None => return Err(()),
+        }
+    }
+}
+
+// status and total length of the first response in the buffer, and whether the server closes
+// the connection after it. a body delimited by the close is only complete once `closed`
+fn parse_message(response: &[u8], closed: bool) -> Result<Option<(u16, usize, bool)>, ()> {
+    let (status, body, head, close) = match try!(parse_head(response)) {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    // interim responses, eg: 100 Continue, come before the final response to the request
+    if status < 200 && status != 101 {
+        let last = try!(parse_message(&response[head..], closed));
+        return Ok(last.map(|(status, length, close)| (status, head + length, close)));
+    }
+    let length = match body {
+        Body::Empty => Some(0),
+        Body::Length(l) => {
+            if response.len() - head >= l {
+                Some(l)
+            } else {
+                None
+            }
+        }
+        Body::Chunked => try!(chunked_length(&response[head..])),
+        Body::Close => {
+            if closed {
+                Some(response.len() - head)
+            } else {
+                None
+            }
+        }
+    };
+    Ok(length.map(|l| (status, head + l, close)))
+}
+
+/// Parse a complete response
+pub fn parse_response(response: &[u8]) -> ParsedResponse {
+    match parse_message(response, true) {
+        Ok(Some((status, _, _))) => {
+            if status >= 200 && status < 300 {
+                ParsedResponse::Ok
+            } else {
+                ParsedResponse::Status(status)
+            }
+        }
+        Ok(None) => ParsedResponse::Incomplete,
+        Err(_) => ParsedResponse::Invalid,
+    }
+}
+
+/// Length of the first complete response in the buffer
+pub fn frame_response(response: &[u8]) -> Option<usize> {
+    match parse_message(response, false) {
+        Ok(Some((_, length, _))) => Some(length),
+        Ok(None) => None,
+        // leave the malformed response for the parser to reject
+        Err(_) => Some(response.len()),
+    }
+}
+
+/// Length of the response in the buffer once the server has closed the connection, which
+/// may be what ends its body
+pub fn frame_closed(response: &[u8]) -> Option<usize> {
+    match parse_message(response, true) {
+        Ok(Some((_, length, _))) => Some(length),
+        _ => None,
+    }
+}
+
+/// Whether the server closes the connection after the complete response
+pub fn closes_connection(response: &[u8]) -> bool {
+    match parse_message(response, true) {
+        Ok(Some((_, _, close))) => close,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "unstable")]
+    extern crate test;
+
+    use super::{closes_connection, frame_closed, frame_response, parse_response};
+    use cfgtypes::ParsedResponse;
+
+    #[test]
+    fn test_parse_incomplete() {
+        let r = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Incomplete);
+
+        let r = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nabc";
+        assert_eq!(parse_response(r), ParsedResponse::Incomplete);
+
+        let r = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Incomplete);
+
+        let r = b"HTT";
+        assert_eq!(parse_response(r), ParsedResponse::Incomplete);
+    }
+
+    #[test]
+    fn test_parse_ok() {
+        let r = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        assert_eq!(parse_response(r), ParsedResponse::Ok);
+
+        let r = b"HTTP/1.1 204 No Content\r\n\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Ok);
+
+        let r = b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\n\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Ok);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let r = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Status(404));
+
+        let r = b"HTTP/1.0 503 Service Unavailable\r\nContent-Length: 4\r\n\r\nbusy";
+        assert_eq!(parse_response(r), ParsedResponse::Status(503));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let r = b"-ERR unknown command\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Invalid);
+
+        let r = b"HTTP/1.1 abc OK\r\n\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Invalid);
+
+        let r = b"HTTP/1.1 200 OK\r\nContent-Length: five\r\n\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Invalid);
+
+        let r = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Invalid);
+    }
+
+    #[test]
+    fn test_frame_pipelined() {
+        let first = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let second = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n";
+        let mut r = first.to_vec();
+        r.extend_from_slice(second);
+        r.extend_from_slice(b"HTTP/1.1 200");
+        assert_eq!(frame_response(&r), Some(first.len()));
+        assert_eq!(frame_response(&r[first.len()..]), Some(second.len()));
+        assert_eq!(frame_response(&r[(first.len() + second.len())..]), None);
+    }
+
+    #[test]
+    fn test_frame_trailers() {
+        let r = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  2\r\nok\r\n0\r\nExpires: never\r\n\r\n";
+        assert_eq!(frame_response(r), Some(r.len()));
+    }
+
+    #[test]
+    fn test_frame_interim() {
+        // the interim responses are framed with the final one
+        let r = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\n\
+                  HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\nno";
+        assert_eq!(frame_response(r), Some(r.len()));
+        assert_eq!(parse_response(r), ParsedResponse::Status(404));
+        assert_eq!(frame_response(b"HTTP/1.1 100 Continue\r\n\r\n"), None);
+        assert_eq!(frame_response(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200"), None);
+    }
+
+    #[test]
+    fn test_frame_closed() {
+        // a body without a length ends when the server closes the connection
+        let r = b"HTTP/1.1 200 OK\r\n\r\npartial";
+        assert_eq!(frame_response(r), None);
+        assert_eq!(frame_closed(r), Some(r.len()));
+        assert_eq!(parse_response(r), ParsedResponse::Ok);
+        assert!(closes_connection(r));
+
+        assert_eq!(frame_closed(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nabc"), None);
+    }
+
+    #[test]
+    fn test_connection_close() {
+        assert!(!closes_connection(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(closes_connection(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\
+                                    Content-Length: 0\r\n\r\n"));
+        assert!(closes_connection(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(!closes_connection(b"HTTP/1.0 200 OK\r\nConnection: Keep-Alive\r\n\
+                                     Content-Length: 0\r\n\r\n"));
+        // only once the response is complete
+        assert!(!closes_connection(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\
+                                     Content-Length: 5\r\n\r\nabc"));
+    }
+
+    #[cfg(feature = "unstable")]
+    #[bench]
+    fn parse_ok_benchmark(b: &mut test::Bencher) {
+        let r = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        b.iter(|| parse_response(r));
+    }
+}
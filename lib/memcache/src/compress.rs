@@ -0,0 +1,183 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use cfgtypes::{CResult, compression};
+use lz4_compress;
+use snap;
+use std::collections::BTreeMap;
+use std::time::Instant;
+use toml::Value;
+
+// flag bit of compressed values when none is configured, as used by spymemcached
+const DEFAULT_FLAG_BIT: i64 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Lz4,
+    Snappy,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Lz4 => "lz4",
+            Algorithm::Snappy => "snappy",
+        }
+    }
+}
+
+/// Values compressed before they are stored, marked by a bit in their flags
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compression {
+    pub algorithm: Algorithm,
+    /// values smaller than this are stored as they are
+    pub min_size: usize,
+    /// flags of a compressed value, a single bit
+    pub flag: u32,
+}
+
+impl Compression {
+    /// Parse the `compress` table of a workload: `algo`, and optionally `min_size` and `flag_bit`
+    pub fn parse(table: &BTreeMap<String, Value>) -> CResult<Compression> {
+        let algorithm = match table.get("algo").and_then(|k| k.as_str()) {
+            Some("lz4") => Algorithm::Lz4,
+            Some("snappy") => Algorithm::Snappy,
+            Some(algo) => return Err(format!("compress: unknown algo: {}", algo)),
+            None => return Err("compress: algo must be lz4 or snappy".to_owned()),
+        };
+        let min_size = match table.get("min_size").and_then(|k| k.as_integer()) {
+            Some(size) if size < 0 => {
+                return Err(format!("compress: min_size must not be negative: {}", size))
+            }
+            Some(size) => size as usize,
+            None => 0,
+        };
+        let bit = table.get("flag_bit")
+                       .and_then(|k| k.as_integer())
+                       .unwrap_or(DEFAULT_FLAG_BIT);
+        if bit < 0 || bit > 31 {
+            return Err(format!("compress: flag_bit must be between 0 and 31: {}", bit));
+        }
+        Ok(Compression {
+            algorithm: algorithm,
+            min_size: min_size,
+            flag: 1 << bit,
+        })
+    }
+
+    /// Whether the flags of a value mark it compressed
+    pub fn flagged(&self, flags: u32) -> bool {
+        flags & self.flag != 0
+    }
+
+    /// The compressed value, or None if the value is too small to compress
+    pub fn compress(&self, value: &[u8]) -> Option<Vec<u8>> {
+        if value.len() < self.min_size {
+            return None;
+        }
+        let start = Instant::now();
+        let compressed = match self.algorithm {
+            Algorithm::Lz4 => lz4_compress::compress(value),
+            Algorithm::Snappy => {
+                match snap::Encoder::new().compress_vec(value) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        debug!("compress: snappy failed: {}", e);
+                        return None;
+                    }
+                }
+            }
+        };
+        compression::compressed(value.len(), compressed.len(), nanoseconds(start));
+        Some(compressed)
+    }
+
+    /// The original value, counting the time spent on it
+    pub fn decompress(&self, value: &[u8]) -> Option<Vec<u8>> {
+        let start = Instant::now();
+        let decompressed = self.decode(value);
+        if decompressed.is_some() {
+            compression::decompressed(nanoseconds(start));
+        }
+        decompressed
+    }
+
+    /// The original value, without counting it, for a value which was already counted
+    pub fn decode(&self, value: &[u8]) -> Option<Vec<u8>> {
+        match self.algorithm {
+            Algorithm::Lz4 => lz4_compress::decompress(value).ok(),
+            Algorithm::Snappy => snap::Decoder::new().decompress_vec(value).ok(),
+        }
+    }
+}
+
+fn nanoseconds(start: Instant) -> u64 {
+    let elapsed = start.elapsed();
+    elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use toml::Value;
+
+    fn table(algo: &str, extra: Vec<(&str, i64)>) -> BTreeMap<String, Value> {
+        let mut table = BTreeMap::new();
+        table.insert("algo".to_owned(), Value::String(algo.to_owned()));
+        for (key, value) in extra {
+            table.insert(key.to_owned(), Value::Integer(value));
+        }
+        table
+    }
+
+    #[test]
+    fn parse() {
+        let c = Compression::parse(&table("lz4", vec![])).unwrap();
+        assert_eq!(c.algorithm, Algorithm::Lz4);
+        assert_eq!(c.min_size, 0);
+        assert_eq!(c.flag, 2);
+
+        let c = Compression::parse(&table("snappy", vec![("min_size", 1024), ("flag_bit", 4)]))
+                    .unwrap();
+        assert_eq!(c.algorithm, Algorithm::Snappy);
+        assert_eq!(c.min_size, 1024);
+        assert_eq!(c.flag, 16);
+        assert!(c.flagged(17));
+        assert!(!c.flagged(2));
+
+        assert!(Compression::parse(&table("zstd", vec![])).is_err());
+        assert!(Compression::parse(&table("lz4", vec![("flag_bit", 32)])).is_err());
+        assert!(Compression::parse(&table("lz4", vec![("min_size", -1)])).is_err());
+        assert!(Compression::parse(&BTreeMap::new()).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = vec![b'a'; 4096];
+        for algo in &["lz4", "snappy"] {
+            let c = Compression::parse(&table(algo, vec![])).unwrap();
+            let compressed = c.compress(&value).unwrap();
+            assert!(compressed.len() < value.len());
+            assert_eq!(c.decode(&compressed), Some(value.clone()));
+        }
+    }
+
+    #[test]
+    fn min_size() {
+        let c = Compression::parse(&table("lz4", vec![("min_size", 100)])).unwrap();
+        assert_eq!(c.compress(b"short"), None);
+    }
+}
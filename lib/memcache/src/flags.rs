@@ -0,0 +1,116 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use cfgtypes::{CResult, tools};
+use toml::Value;
+
+/// Flags stored with each value, derived from its key so reads can check them without
+/// remembering what was written
+#[derive(Clone, Debug, PartialEq)]
+pub enum Flags {
+    Fixed(u32),
+    /// any 32-bit value
+    Random,
+    /// one of a few values
+    Choice(Vec<u32>),
+}
+
+impl Flags {
+    /// Parse the `flags` of a workload: an integer, "random", or an array of integers
+    pub fn parse(value: &Value) -> CResult<Flags> {
+        match *value {
+            Value::Integer(f) => Ok(Flags::Fixed(try!(flag(f)))),
+            Value::String(ref s) if s == "random" => Ok(Flags::Random),
+            Value::Array(ref values) if !values.is_empty() => {
+                let mut choices = Vec::new();
+                for v in values {
+                    match v.as_integer() {
+                        Some(f) => choices.push(try!(flag(f))),
+                        None => return Err(format!("flags: not an integer: {}", v)),
+                    }
+                }
+                Ok(Flags::Choice(choices))
+            }
+            _ => Err("flags must be an integer, \"random\", or an array of integers".to_owned()),
+        }
+    }
+
+    /// The flags stored with the key
+    pub fn for_key(&self, key: &[u8]) -> u32 {
+        match *self {
+            Flags::Fixed(f) => f,
+            Flags::Random => tools::key_hash(key) as u32,
+            Flags::Choice(ref choices) => {
+                choices[(tools::key_hash(key) % choices.len() as u64) as usize]
+            }
+        }
+    }
+
+    /// Whether any of the flags this stores has one of the bits set
+    pub fn uses(&self, bits: u32) -> bool {
+        match *self {
+            Flags::Fixed(f) => f & bits != 0,
+            Flags::Random => false,
+            Flags::Choice(ref choices) => choices.iter().any(|f| f & bits != 0),
+        }
+    }
+}
+
+fn flag(value: i64) -> CResult<u32> {
+    if value < 0 || value > u32::max_value() as i64 {
+        return Err(format!("flags: {} is not a 32-bit unsigned integer", value));
+    }
+    Ok(value as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use toml::Value;
+
+    #[test]
+    fn parse_flags() {
+        assert_eq!(Flags::parse(&Value::Integer(42)), Ok(Flags::Fixed(42)));
+        assert_eq!(Flags::parse(&Value::String("random".to_owned())), Ok(Flags::Random));
+        assert_eq!(Flags::parse(&Value::Array(vec![Value::Integer(0),
+                                                   Value::Integer(4294967295)])),
+                   Ok(Flags::Choice(vec![0, 4294967295])));
+        assert!(Flags::parse(&Value::Integer(-1)).is_err());
+        assert!(Flags::parse(&Value::Integer(4294967296)).is_err());
+        assert!(Flags::parse(&Value::Array(Vec::new())).is_err());
+        assert!(Flags::parse(&Value::String("any".to_owned())).is_err());
+    }
+
+    #[test]
+    fn flags_follow_the_key() {
+        assert_eq!(Flags::Fixed(7).for_key(b"a"), 7);
+        let random = Flags::Random;
+        assert_eq!(random.for_key(b"key"), random.for_key(b"key"));
+        assert!(random.for_key(b"key") != random.for_key(b"kez"));
+        let choice = Flags::Choice(vec![1, 2, 3]);
+        for key in &[&b"a"[..], b"b", b"c", b"key"] {
+            assert!([1, 2, 3].contains(&choice.for_key(key)));
+            assert_eq!(choice.for_key(key), choice.for_key(key));
+        }
+    }
+
+    #[test]
+    fn bits_in_use() {
+        assert!(Flags::Fixed(3).uses(2));
+        assert!(!Flags::Fixed(1).uses(2));
+        assert!(Flags::Choice(vec![0, 4]).uses(4));
+        assert!(!Flags::Random.uses(4));
+    }
+}
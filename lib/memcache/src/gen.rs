@@ -137,6 +137,28 @@ fn add_benchmark(b: &mut test::Bencher) {
     b.iter(|| add("key", "value", Some(1), None));
 }
 
+/// create a set or add request for a value which isn't text, eg: a compressed value
+pub fn store(command: &str,
+             key: &str,
+             value: &[u8],
+             exptime: Option<u32>,
+             flags: Option<u32>)
+             -> Vec<u8> {
+    let flags = flags.unwrap_or(0);
+    let exptime = exptime.unwrap_or(0);
+    let mut request = format!("{} {} {} {} {}\r\n", command, key, flags, exptime, value.len())
+                          .into_bytes();
+    request.extend_from_slice(value);
+    request.extend_from_slice(b"\r\n");
+    request
+}
+
+#[test]
+fn test_store() {
+    assert_eq!(store("set", "key", b"\x00\xff", None, Some(2)),
+               b"set key 2 0 2\r\n\x00\xff\r\n".to_vec());
+}
+
 /// create a replace request
 pub fn replace(key: &str, value: &str, exptime: Option<u32>, flags: Option<u32>) -> String {
     let flags = flags.unwrap_or(0);
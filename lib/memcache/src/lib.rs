@@ -18,17 +18,25 @@
 
 #[macro_use]
 extern crate log;
+extern crate lz4_compress;
 extern crate rpcperf_cfgtypes as cfgtypes;
+extern crate snap;
 extern crate toml;
 extern crate getopts;
 
+mod compress;
+mod flags;
 mod gen;
 mod parse;
+mod sequence;
 
 use cfgtypes::*;
+use compress::Compression;
+use flags::Flags;
 use getopts::Matches;
+use sequence::Sequence;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::str;
 use std::sync::Arc;
 use toml::Value;
 
@@ -44,9 +52,18 @@ enum MemcacheCommand {
 
 struct MemcacheParserFactory {
     flush: bool,
+    codecs: Vec<Compression>,
+    flags: Option<Flags>,
 }
 
-struct MemcacheParser;
+/// Decompresses values carrying the flag of any workload's compression, and checks the flags
+/// the store workloads set
+struct MemcacheParser {
+    codecs: Vec<Compression>,
+    flags: Option<Flags>,
+    // the value parse() decompressed from the last response, for validate() to check
+    decompressed: RefCell<Option<Vec<u8>>>,
+}
 
 #[derive(Clone, Debug)]
 struct CacheData {
@@ -59,6 +76,10 @@ impl Ptype for CacheData {
         self.string = tools::random_string(self.size);
     }
 
+    fn reseed(&mut self, seed: usize) {
+        self.string = tools::seeded_string(self.size, seed);
+    }
+
     fn parse(seed: usize, size: usize, _: &BTreeMap<String, Value>) -> CResult<Self> {
         Ok(CacheData {
             size: size,
@@ -69,7 +90,11 @@ impl Ptype for CacheData {
 
 impl ProtocolParseFactory for MemcacheParserFactory {
     fn new(&self) -> Box<ProtocolParse> {
-        Box::new(MemcacheParser)
+        Box::new(MemcacheParser {
+            codecs: self.codecs.clone(),
+            flags: self.flags.clone(),
+            decompressed: RefCell::new(None),
+        })
     }
 
     fn prepare(&self) -> CResult<Vec<Vec<u8>>> {
@@ -85,41 +110,138 @@ impl ProtocolParseFactory for MemcacheParserFactory {
     }
 }
 
+impl MemcacheParser {
+    // the compression of a hit, if its value is flagged as compressed
+    fn codec(&self, bytes: &[u8]) -> Option<&Compression> {
+        if self.codecs.is_empty() {
+            return None;
+        }
+        parse::response_flags(bytes).and_then(|flags| self.codecs.iter().find(|c| c.flagged(flags)))
+    }
+
+    // the bits of the flags which mark a value compressed
+    fn compression_bits(&self) -> u32 {
+        self.codecs.iter().fold(0, |bits, c| bits | c.flag)
+    }
+}
+
 impl ProtocolParse for MemcacheParser {
     fn parse(&self, bytes: &[u8]) -> ParsedResponse {
-        let s = str::from_utf8(bytes).unwrap();
-        parse::parse_response(s)
+        let response = parse::parse_bytes(bytes);
+        *self.decompressed.borrow_mut() = None;
+        if response == ParsedResponse::Hit {
+            if let Some(codec) = self.codec(bytes) {
+                let value = parse::response_value(bytes).and_then(|v| codec.decompress(v));
+                if value.is_none() {
+                    debug!("memcache: {} value failed to decompress", codec.algorithm.name());
+                    return ParsedResponse::Invalid;
+                }
+                *self.decompressed.borrow_mut() = value;
+            }
+        }
+        response
+    }
+
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_response(bytes)
+    }
+
+    fn value<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        parse::response_value(bytes)
+    }
+
+    fn validate(&self, key: &[u8], bytes: &[u8]) -> bool {
+        let value = match parse::response_value(bytes) {
+            Some(v) => v,
+            None => return false,
+        };
+        match self.codec(bytes) {
+            // the value parse() decompressed, decoded again only if parse() wasn't called
+            Some(codec) => {
+                self.decompressed
+                    .borrow_mut()
+                    .take()
+                    .or_else(|| codec.decode(value))
+                    .map_or(false, |v| tools::is_valid_value(key, &v))
+            }
+            None => tools::is_valid_value(key, value),
+        }
+    }
+
+    fn validate_flags(&self, key: &[u8], bytes: &[u8]) -> bool {
+        let flags = match self.flags {
+            Some(ref f) => f,
+            None => return true,
+        };
+        let mask = !self.compression_bits();
+        parse::response_flags(bytes).map_or(false, |f| f & mask == flags.for_key(key) & mask)
+    }
+
+    fn follow(&self, request: &[u8], previous: &[u8]) -> Vec<u8> {
+        sequence::follow(request, previous)
     }
 }
 
-impl ProtocolGen for MemcacheCommand {
-    fn generate_message(&mut self) -> Vec<u8> {
+impl MemcacheCommand {
+    /// With validation, stored values are derived from their key and reads are checked for it
+    fn generate(&mut self,
+                validate: bool,
+                compress: Option<&Compression>,
+                flags: Option<&Flags>,
+                mask: u32)
+                -> WorkItem {
         match *self {
             MemcacheCommand::Set(ref mut key, ref mut val) => {
                 key.regen();
                 val.regen();
-                gen::set(key.value.string.as_str(),
-                         val.value.string.as_str(),
-                         None,
-                         None)
-                    .into_bytes()
+                if validate {
+                    val.value.string = tools::validation_value(key.value.string.as_bytes(),
+                                                               val.value.size);
+                }
+                let stored = stored_flags(&key.value.string, flags, mask);
+                let item = compressed("set",
+                                      &key.value.string,
+                                      &val.value.string,
+                                      stored,
+                                      compress);
+                if let Some(item) = item {
+                    return item;
+                }
+                WorkItem::new(gen::set(key.value.string.as_str(),
+                                       val.value.string.as_str(),
+                                       None,
+                                       stored)
+                                  .into_bytes())
             }
             MemcacheCommand::Get(ref mut key) => {
                 key.regen();
-                gen::get(key.value.string.as_str()).into_bytes()
+                read_item(gen::get(key.value.string.as_str()), &key.value.string, validate)
             }
             MemcacheCommand::Gets(ref mut key) => {
                 key.regen();
-                gen::gets(key.value.string.as_str()).into_bytes()
+                read_item(gen::gets(key.value.string.as_str()), &key.value.string, validate)
             }
             MemcacheCommand::Add(ref mut key, ref mut val) => {
                 key.regen();
                 val.regen();
-                gen::add(key.value.string.as_str(),
-                         val.value.string.as_str(),
-                         None,
-                         None)
-                    .into_bytes()
+                if validate {
+                    val.value.string = tools::validation_value(key.value.string.as_bytes(),
+                                                               val.value.size);
+                }
+                let stored = stored_flags(&key.value.string, flags, mask);
+                let item = compressed("add",
+                                      &key.value.string,
+                                      &val.value.string,
+                                      stored,
+                                      compress);
+                if let Some(item) = item {
+                    return item;
+                }
+                WorkItem::new(gen::add(key.value.string.as_str(),
+                                       val.value.string.as_str(),
+                                       None,
+                                       stored)
+                                  .into_bytes())
             }
         }
     }
@@ -134,22 +256,118 @@ impl ProtocolGen for MemcacheCommand {
     }
 }
 
+// the flags of the key, leaving the bits which mark a value compressed to the compression
+fn stored_flags(key: &str, flags: Option<&Flags>, mask: u32) -> Option<u32> {
+    flags.map(|f| f.for_key(key.as_bytes()) & !mask)
+}
+
+// a store request for the value compressed, unless it's too small to compress
+fn compressed(command: &str,
+              key: &str,
+              value: &str,
+              flags: Option<u32>,
+              compress: Option<&Compression>)
+              -> Option<WorkItem> {
+    compress.and_then(|c| {
+        c.compress(value.as_bytes()).map(|v| {
+            WorkItem::new(gen::store(command, key, &v, None, Some(flags.unwrap_or(0) | c.flag)))
+        })
+    })
+}
+
+fn read_item(request: String, key: &str, validate: bool) -> WorkItem {
+    let mut item = WorkItem::new(request.into_bytes());
+    if validate {
+        item.validate = Some(key.as_bytes().to_vec());
+    }
+    item
+}
+
+struct MemcacheWorkload {
+    command: MemcacheCommand,
+    validate: bool,
+    compress: Option<Compression>,
+    flags: Option<Flags>,
+    // the flag bits of every workload's compression
+    mask: u32,
+}
+
+impl ProtocolGen for MemcacheWorkload {
+    fn generate_message(&mut self) -> Vec<u8> {
+        self.generate_work().bytes
+    }
+
+    fn generate_work(&mut self) -> WorkItem {
+        self.command.generate(self.validate,
+                              self.compress.as_ref(),
+                              self.flags.as_ref(),
+                              self.mask)
+    }
+
+    fn method(&self) -> &str {
+        self.command.method()
+    }
+}
+
 /// Load the memcache benchmark configuration from the config toml and command line arguments
 pub fn load_config(table: &BTreeMap<String, Value>, matches: &Matches) -> CResult<ProtocolConfig> {
 
     let mut ws = Vec::new();
+    let mut codecs: Vec<Compression> = Vec::new();
+    // the flags of the first store workload, which the others must set too
+    let mut stored_flags: Option<Option<Flags>> = None;
 
     if let Some(&Value::Array(ref workloads)) = table.get("workload") {
+        for workload in workloads {
+            if let Value::Table(ref workload) = *workload {
+                if let Some(c) = try!(extract_compression(workload)) {
+                    // reads can't tell which algorithm a flag bit means if it has two
+                    if let Some(other) = codecs.iter().find(|o| o.flag == c.flag) {
+                        if other.algorithm != c.algorithm {
+                            return Err(format!("compress: flag {} is used by both {} and {}",
+                                               c.flag,
+                                               other.algorithm.name(),
+                                               c.algorithm.name()));
+                        }
+                    }
+                    codecs.push(c);
+                }
+            }
+        }
+        // reads decompress a value carrying the flag bit of any workload's compression
+        let mask = codecs.iter().fold(0, |bits, c| bits | c.flag);
+
         for (i, workload) in workloads.iter().enumerate() {
             if let Value::Table(ref workload) = *workload {
-                let w = try!(extract_workload(i, workload));
+                let (w, flags, stores) = try!(extract_workload(i, workload, mask));
+                if stores {
+                    // reads check the flags of whichever workload stored the key
+                    if stored_flags.as_ref().map_or(false, |s| *s != flags) {
+                        return Err("flags: store workloads must all set the same flags"
+                                       .to_owned());
+                    }
+                    stored_flags = Some(flags);
+                }
                 ws.push(w);
             } else {
                 return Err("malformed config: workload must be a struct".to_owned());
             }
         }
+        let stored_flags = stored_flags.and_then(|f| f);
+
+        if let Some(ref f) = stored_flags {
+            if let Some(c) = codecs.iter().find(|c| f.uses(c.flag)) {
+                return Err(format!("flags: {:?} use the bit {} compression marks values with",
+                                   f,
+                                   c.flag));
+            }
+        }
 
-        let protocol = Arc::new(MemcacheParserFactory { flush: matches.opt_present("flush") });
+        let protocol = Arc::new(MemcacheParserFactory {
+            flush: matches.opt_present("flush"),
+            codecs: codecs,
+            flags: stored_flags,
+        });
 
         Ok(ProtocolConfig {
             protocol: protocol,
@@ -160,7 +378,19 @@ pub fn load_config(table: &BTreeMap<String, Value>, matches: &Matches) -> CResul
     }
 }
 
-fn extract_workload(i: usize, workload: &BTreeMap<String, Value>) -> CResult<BenchmarkWorkload> {
+fn extract_compression(workload: &BTreeMap<String, Value>) -> CResult<Option<Compression>> {
+    match workload.get("compress") {
+        Some(&Value::Table(ref c)) => Ok(Some(try!(Compression::parse(c)))),
+        Some(_) => Err("malformed config: compress must be a struct".to_owned()),
+        None => Ok(None),
+    }
+}
+
+// the workload, its flags, and whether it stores values
+fn extract_workload(i: usize,
+                    workload: &BTreeMap<String, Value>,
+                    mask: u32)
+                    -> CResult<(BenchmarkWorkload, Option<Flags>, bool)> {
 
     let rate = workload.get("rate")
                        .and_then(|k| k.as_integer())
@@ -176,6 +406,24 @@ fn extract_workload(i: usize, workload: &BTreeMap<String, Value>) -> CResult<Ben
                        .unwrap_or(method.as_str())
                        .to_owned();
 
+    let validate = workload.get("validate")
+                           .and_then(|k| k.as_bool())
+                           .unwrap_or(false);
+
+    let compress = try!(extract_compression(workload));
+
+    let flags = match workload.get("flags") {
+        Some(f) if method == "set" || method == "add" || method == "sequence" => {
+            Some(try!(Flags::parse(f)))
+        }
+        Some(_) => return Err(format!("flags are set by set, add and sequence, not {}", method)),
+        None => None,
+    };
+
+    if method == "sequence" && compress.is_some() {
+        return Err("sequence: values can't be compressed".to_owned());
+    }
+
     if let Some(&Value::Array(ref params)) = workload.get("parameter") {
         let mut ps = Vec::new();
         for param in params {
@@ -190,6 +438,16 @@ fn extract_workload(i: usize, workload: &BTreeMap<String, Value>) -> CResult<Ben
             }
         }
 
+        if method == "sequence" {
+            let ops = try!(sequence::parse_ops(workload.get("ops")));
+            let pipelined = workload.get("pipelined")
+                                    .and_then(|k| k.as_bool())
+                                    .unwrap_or(false);
+            let gen = try!(Sequence::new(ops, ps, pipelined, validate, flags.clone(), mask));
+            let stores = gen.stores();
+            return Ok((BenchmarkWorkload::new(name, rate as usize, Box::new(gen)), flags, stores));
+        }
+
         let cmd = match method.as_str() {
             "get" if ps.len() == 1 => MemcacheCommand::Get(ps[0].clone()),
             "gets" if ps.len() == 1 => MemcacheCommand::Gets(ps[0].clone()),
@@ -203,8 +461,66 @@ fn extract_workload(i: usize, workload: &BTreeMap<String, Value>) -> CResult<Ben
             _ => return Err(format!("invalid command: {}", method)),
         };
 
-        Ok(BenchmarkWorkload::new(name, rate as usize, Box::new(cmd)))
+        let stores = method == "set" || method == "add";
+        let gen = MemcacheWorkload {
+            command: cmd,
+            validate: validate,
+            compress: compress,
+            flags: flags.clone(),
+            mask: mask,
+        };
+
+        Ok((BenchmarkWorkload::new(name, rate as usize, Box::new(gen)), flags, stores))
     } else {
         Err("malformed config: 'parameter' must be an array".to_owned())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfgtypes::{CResult, ProtocolConfig};
+    use flags::Flags;
+    use getopts::Options;
+    use toml::Parser;
+
+    fn workload(method: &str, extra: &str) -> String {
+        let param = "  [[workload.parameter]]\n";
+        let params = if method == "get" { 1 } else { 2 };
+        let mut w = format!("[[workload]]\nmethod = \"{}\"\n{}\n", method, extra);
+        for _ in 0..params {
+            w.push_str(param);
+        }
+        w
+    }
+
+    fn config(workloads: &[String]) -> CResult<ProtocolConfig> {
+        let table = Parser::new(&workloads.concat()).parse().unwrap();
+        let mut opts = Options::new();
+        opts.optflag("", "flush", "");
+        load_config(&table, &opts.parse(Vec::<String>::new()).unwrap())
+    }
+
+    #[test]
+    fn store_workloads_set_the_same_flags() {
+        let set = workload("set", "flags = 42");
+        assert!(config(&[set.clone(), workload("add", "flags = 42"), workload("get", "")])
+                    .is_ok());
+        assert!(config(&[set.clone(), workload("add", "flags = 7")]).is_err());
+        assert!(config(&[set.clone(), workload("add", "")]).is_err());
+        assert!(config(&[workload("add", ""), set.clone()]).is_err());
+        assert!(config(&[set.clone(), workload("sequence", "ops = [\"set\", \"get\"]")])
+                    .is_err());
+        assert!(config(&[set, workload("get", "flags = 42")]).is_err());
+    }
+
+    #[test]
+    fn stored_flags_leave_compression_bits() {
+        let mask = 0b101;
+        for key in &["a", "b", "key", "other"] {
+            assert_eq!(stored_flags(key, Some(&Flags::Random), mask).unwrap() & mask, 0);
+        }
+        assert_eq!(stored_flags("a", Some(&Flags::Fixed(6)), mask), Some(2));
+        assert_eq!(stored_flags("a", None, mask), None);
+    }
+}
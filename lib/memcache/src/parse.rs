@@ -15,6 +15,8 @@
 
 pub use cfgtypes::ParsedResponse;
 
+use std::str;
+
 
 pub fn parse_response(response: &str) -> ParsedResponse {
 
@@ -126,12 +128,123 @@ pub fn parse_response(response: &str) -> ParsedResponse {
     ParsedResponse::Unknown
 }
 
+/// Find the length of the first complete response in the buffer
+pub fn frame_response(response: &[u8]) -> Option<usize> {
+    let mut position = 0;
+    loop {
+        let end = match find_crlf(&response[position..]) {
+            Some(i) => position + i,
+            None => return None,
+        };
+        let line = &response[position..end];
+        if !line.starts_with(b"VALUE ") {
+            return Some(end + 2);
+        }
+        // skip the data block of each VALUE, the response ends at END
+        let bytes = str::from_utf8(line)
+                        .ok()
+                        .and_then(|l| l.split_whitespace().nth(3))
+                        .and_then(|b| b.parse::<usize>().ok());
+        match bytes {
+            Some(b) => {
+                position = end + 2 + b + 2;
+                if position > response.len() {
+                    return None;
+                }
+            }
+            None => {
+                // the malformed response ends at its END, for the parser to reject
+                return response[end..]
+                           .windows(7)
+                           .position(|w| w == b"\r\nEND\r\n")
+                           .map(|i| end + i + 7);
+            }
+        }
+    }
+}
+
+/// Parse a response whose data blocks may not be text, eg: compressed values
+pub fn parse_bytes(response: &[u8]) -> ParsedResponse {
+    match str::from_utf8(response) {
+        Ok(s) => parse_response(s),
+        Err(_) => {
+            // only a data block may hold binary, the lines around it are text
+            if !response.starts_with(b"VALUE ") {
+                return ParsedResponse::Invalid;
+            }
+            match frame_response(response) {
+                Some(n) if n == response.len() && response.ends_with(b"\r\nEND\r\n") => {
+                    ParsedResponse::Hit
+                }
+                Some(_) => ParsedResponse::Invalid,
+                None => ParsedResponse::Incomplete,
+            }
+        }
+    }
+}
+
+/// Data block of the first VALUE in a complete get response
+pub fn response_value(response: &[u8]) -> Option<&[u8]> {
+    match value_header(response) {
+        Some((end, _, b)) if end + 2 + b <= response.len() => {
+            Some(&response[(end + 2)..(end + 2 + b)])
+        }
+        _ => None,
+    }
+}
+
+/// Flags of the first VALUE in a get response
+pub fn response_flags(response: &[u8]) -> Option<u32> {
+    value_header(response).map(|(_, flags, _)| flags)
+}
+
+/// Unique of the first VALUE in a gets response, which a cas of the key must carry
+pub fn response_unique(response: &[u8]) -> Option<u64> {
+    let end = match find_crlf(response) {
+        Some(i) => i,
+        None => return None,
+    };
+    if !response.starts_with(b"VALUE ") {
+        return None;
+    }
+    str::from_utf8(&response[..end])
+        .ok()
+        .and_then(|line| line.split_whitespace().nth(4))
+        .and_then(|u| u.parse::<u64>().ok())
+}
+
+// end of the header line, flags and data length of the first VALUE
+fn value_header(response: &[u8]) -> Option<(usize, u32, usize)> {
+    let end = match find_crlf(response) {
+        Some(i) => i,
+        None => return None,
+    };
+    if !response.starts_with(b"VALUE ") {
+        return None;
+    }
+    let tokens: Vec<&str> = match str::from_utf8(&response[..end]) {
+        Ok(line) => line.split_whitespace().collect(),
+        Err(_) => return None,
+    };
+    let flags = tokens.get(2).and_then(|f| f.parse::<u32>().ok());
+    let bytes = tokens.get(3).and_then(|b| b.parse::<usize>().ok());
+    match (flags, bytes) {
+        (Some(f), Some(b)) => Some((end, f, b)),
+        _ => None,
+    }
+}
+
+fn find_crlf(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(2).position(|w| w[0] == b'\r' && w[1] == b'\n')
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "unstable")]
     extern crate test;
 
-    use super::parse_response;
+    use super::{frame_response, parse_bytes, parse_response, response_flags, response_unique,
+                response_value};
     use cfgtypes::ParsedResponse;
 
     #[test]
@@ -243,6 +356,73 @@ mod tests {
                    ParsedResponse::Version("1.2.3".to_owned()));
     }
 
+    #[test]
+    fn test_frame_incomplete() {
+        assert_eq!(frame_response(b"STORED"), None);
+        assert_eq!(frame_response(b"STORED\r"), None);
+        assert_eq!(frame_response(b"VALUE key 0 10\r\n0123456789\r\n"), None);
+        assert_eq!(frame_response(b"VALUE key 0 10\r\n0123456789\r\nEND\r"), None);
+    }
+
+    #[test]
+    fn test_frame_complete() {
+        assert_eq!(frame_response(b"STORED\r\n"), Some(8));
+        assert_eq!(frame_response(b"STORED\r\nSTORED\r\n"), Some(8));
+        assert_eq!(frame_response(b"END\r\nSTORED\r\n"), Some(5));
+
+        let r = b"VALUE key 0 10\r\n0123456789\r\nEND\r\nSTORED\r\n";
+        assert_eq!(frame_response(r), Some(33));
+
+        // data containing CRLF is skipped by length
+        let r = b"VALUE key 0 4\r\n\r\nOK\r\nEND\r\n";
+        assert_eq!(frame_response(r), Some(26));
+
+        // a malformed header doesn't swallow the replies behind it
+        let r = b"VALUE key 0 x\r\n0123\r\nEND\r\nSTORED\r\n";
+        assert_eq!(frame_response(r), Some(26));
+        assert_eq!(frame_response(b"VALUE key 0 x\r\n0123\r\n"), None);
+    }
+
+    #[test]
+    fn test_response_value() {
+        let r = b"VALUE key 0 10\r\n0123456789\r\nEND\r\n";
+        assert_eq!(response_value(r), Some(&b"0123456789"[..]));
+
+        let r = b"VALUE key 0 4 99\r\n\r\nOK\r\nEND\r\n";
+        assert_eq!(response_value(r), Some(&b"\r\nOK"[..]));
+
+        assert_eq!(response_value(b"END\r\n"), None);
+        assert_eq!(response_value(b"VALUE key 0 10\r\n0123"), None);
+    }
+
+    #[test]
+    fn test_response_flags() {
+        assert_eq!(response_flags(b"VALUE key 2 3\r\nabc\r\nEND\r\n"), Some(2));
+        assert_eq!(response_flags(b"VALUE key NaN 3\r\nabc\r\nEND\r\n"), None);
+        assert_eq!(response_flags(b"END\r\n"), None);
+    }
+
+    #[test]
+    fn test_response_unique() {
+        assert_eq!(response_unique(b"VALUE key 2 3 42\r\nabc\r\nEND\r\n"), Some(42));
+        assert_eq!(response_unique(b"VALUE key 2 3\r\nabc\r\nEND\r\n"), None);
+        assert_eq!(response_unique(b"END\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes(b"STORED\r\n"), ParsedResponse::Ok);
+
+        let r = b"VALUE key 2 4\r\n\x00\xff\r\n\r\nEND\r\n";
+        assert_eq!(parse_bytes(r), ParsedResponse::Hit);
+
+        let r = b"VALUE key 2 4\r\n\x00\xff\r\n";
+        assert_eq!(parse_bytes(r), ParsedResponse::Incomplete);
+
+        let r = b"VALUE key 2 2\r\n\x00\xff\r\nSTORED\r\n";
+        assert_eq!(parse_bytes(r), ParsedResponse::Invalid);
+    }
+
     #[cfg(feature = "unstable")]
     #[bench]
     fn parse_hit_benchmark(b: &mut test::Bencher) {
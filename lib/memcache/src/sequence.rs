@@ -0,0 +1,345 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use cfgtypes::{CResult, ProtocolGen, WorkItem, tools};
+use flags::Flags;
+use gen;
+use parse;
+use super::Param;
+use toml::Value;
+
+// a validated value carries the sequence number in hex, followed by a value derived from it
+const MIN_VALIDATED_SIZE: usize = 32;
+
+/// An op of a sequence, on the key the sequence generated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Get,
+    Gets,
+    Set,
+    Add,
+    /// a compare-and-swap with the unique of the gets before it
+    Cas,
+    Delete,
+}
+
+impl Op {
+    fn parse(name: &str) -> CResult<Op> {
+        match name {
+            "get" => Ok(Op::Get),
+            "gets" => Ok(Op::Gets),
+            "set" => Ok(Op::Set),
+            "add" => Ok(Op::Add),
+            "cas" => Ok(Op::Cas),
+            "delete" => Ok(Op::Delete),
+            _ => Err(format!("sequence: invalid op: {}", name)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Op::Get => "get",
+            Op::Gets => "gets",
+            Op::Set => "set",
+            Op::Add => "add",
+            Op::Cas => "cas",
+            Op::Delete => "delete",
+        }
+    }
+
+    fn stores(&self) -> bool {
+        match *self {
+            Op::Set | Op::Add | Op::Cas => true,
+            _ => false,
+        }
+    }
+
+    fn reads(&self) -> bool {
+        *self == Op::Get || *self == Op::Gets
+    }
+}
+
+/// Parse the `ops` of a sequence workload, an array of op names
+pub fn parse_ops(value: Option<&Value>) -> CResult<Vec<Op>> {
+    let names = match value {
+        Some(&Value::Array(ref names)) if !names.is_empty() => names,
+        _ => return Err("sequence: 'ops' must be an array of op names".to_owned()),
+    };
+    let mut ops = Vec::new();
+    for name in names {
+        match name.as_str() {
+            Some(n) => ops.push(try!(Op::parse(n))),
+            None => return Err(format!("sequence: op is not a string: {}", name)),
+        }
+    }
+    Ok(ops)
+}
+
+/// Ops on the same generated key, sent back-to-back on one connection
+pub struct Sequence {
+    ops: Vec<Op>,
+    key: Param,
+    value: Option<Param>,
+    pipelined: bool,
+    validate: bool,
+    flags: Option<Flags>,
+    // the bits of the flags which mark a value compressed, left clear in the flags stored
+    mask: u32,
+    // numbers each sequence, so a read can tell the value its own sequence stored from one an
+    // earlier sequence left behind
+    count: u64,
+}
+
+impl Sequence {
+    /// The ops take the key from the first parameter, and any stored value from the second.
+    /// The `mask` bits of the flags are left clear, as the compression of other workloads
+    /// marks values with them.
+    pub fn new(ops: Vec<Op>,
+               params: Vec<Param>,
+               pipelined: bool,
+               validate: bool,
+               flags: Option<Flags>,
+               mask: u32)
+               -> CResult<Sequence> {
+        let stores = ops.iter().any(|op| op.stores());
+        let expected = if stores { 2 } else { 1 };
+        if params.len() != expected {
+            return Err(format!("sequence: invalid number of params ({}), expected {}",
+                               params.len(),
+                               expected));
+        }
+        if validate && params.get(1).map_or(false, |v| v.value.size < MIN_VALIDATED_SIZE) {
+            return Err(format!("sequence: validated values must be at least {} bytes, to carry \
+                                the sequence number",
+                               MIN_VALIDATED_SIZE));
+        }
+        if flags.is_some() && !stores {
+            return Err("sequence: flags are set by set, add and cas, none of its ops".to_owned());
+        }
+        for (i, op) in ops.iter().enumerate() {
+            if *op != Op::Cas {
+                continue;
+            }
+            // the unique comes from the response to the gets, which isn't there to read until
+            // it arrives
+            if i == 0 || ops[i - 1] != Op::Gets {
+                return Err("sequence: cas must directly follow a gets".to_owned());
+            }
+            if pipelined {
+                return Err("sequence: cas can't be pipelined behind its gets".to_owned());
+            }
+        }
+        let mut params = params.into_iter();
+        Ok(Sequence {
+            ops: ops,
+            key: params.next().unwrap(),
+            value: params.next(),
+            pipelined: pipelined,
+            validate: validate,
+            flags: flags,
+            mask: mask,
+            count: 0,
+        })
+    }
+
+    /// Whether any of the ops store a value
+    pub fn stores(&self) -> bool {
+        self.value.is_some()
+    }
+
+    /// The first op of the next sequence, carrying the rest
+    ///
+    /// With validation, stored values carry the sequence number and are derived from it and the
+    /// key. Reads after a store are checked for its value, and a value stored by an earlier
+    /// sequence on the key is stale.
+    fn generate(&mut self) -> WorkItem {
+        self.key.regen();
+        if let Some(ref mut value) = self.value {
+            value.regen();
+        }
+        self.count = self.count.wrapping_add(1);
+        let key = self.key.value.string.clone();
+        let derived = tools::sequence_key(key.as_bytes(), self.count);
+        let value = match self.value {
+            Some(ref v) if self.validate => {
+                tools::sequence_value(key.as_bytes(), self.count, v.value.size)
+            }
+            Some(ref v) => v.value.string.clone(),
+            None => String::new(),
+        };
+        let stored = self.flags.as_ref().map(|f| f.for_key(&derived) & !self.mask);
+
+        let mut stored_before = false;
+        let mut items: Vec<WorkItem> = Vec::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            let request = match *op {
+                Op::Get => gen::get(&key),
+                Op::Gets => gen::gets(&key),
+                Op::Set => gen::set(&key, &value, None, stored),
+                Op::Add => gen::add(&key, &value, None, stored),
+                // the unique is filled in from the response to the gets
+                Op::Cas => gen::cas(&key, &value, None, stored, 0),
+                Op::Delete => gen::delete(&key),
+            };
+            let mut item = WorkItem::new(request.into_bytes());
+            if self.validate && op.reads() && stored_before {
+                item.validate = Some(derived.clone());
+            }
+            item.op = Some(i);
+            item.serial = !self.pipelined;
+            item.follows = *op == Op::Cas;
+            stored_before = stored_before || op.stores();
+            items.push(item);
+        }
+        let mut first = items.remove(0);
+        first.then = items;
+        first
+    }
+}
+
+impl ProtocolGen for Sequence {
+    fn generate_message(&mut self) -> Vec<u8> {
+        self.generate().bytes
+    }
+
+    fn generate_work(&mut self) -> WorkItem {
+        self.generate()
+    }
+
+    fn method(&self) -> &str {
+        "sequence"
+    }
+
+    /// An op which appears more than once is numbered by its occurrence, eg: `get#2`
+    fn ops(&self) -> Vec<String> {
+        self.ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| {
+                if self.ops.iter().filter(|o| *o == op).count() == 1 {
+                    return op.name().to_owned();
+                }
+                let occurrence = self.ops[..(i + 1)].iter().filter(|o| *o == op).count();
+                format!("{}#{}", op.name(), occurrence)
+            })
+            .collect()
+    }
+}
+
+/// A cas with the unique of the gets response before it, or as it was if the gets missed
+pub fn follow(request: &[u8], previous: &[u8]) -> Vec<u8> {
+    let unique = match parse::response_unique(previous) {
+        Some(u) => u,
+        None => return request.to_vec(),
+    };
+    if !request.starts_with(b"cas ") {
+        return request.to_vec();
+    }
+    let end = match request.windows(2).position(|w| w[0] == b'\r' && w[1] == b'\n') {
+        Some(i) => i,
+        None => return request.to_vec(),
+    };
+    let unique = format!("{}", unique);
+    let line = String::from_utf8_lossy(&request[..end]).into_owned();
+    let mut tokens: Vec<&str> = line.split(' ').collect();
+    tokens.pop();
+    tokens.push(&unique);
+    let mut completed = tokens.join(" ").into_bytes();
+    completed.extend_from_slice(&request[end..]);
+    completed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cfgtypes::{Parameter, ProtocolGen, Style, tools};
+    use super::super::CacheData;
+
+    fn param(size: usize) -> Param {
+        Parameter {
+            style: Style::Random,
+            regenerate: true,
+            keyspace: None,
+            value: CacheData {
+                size: size,
+                string: String::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn sequence_on_one_key() {
+        let mut sequence = Sequence::new(vec![Op::Set, Op::Get], vec![param(8), param(40)], false,
+                                         true, None, 0)
+                               .unwrap();
+        let first = sequence.generate_work();
+        assert_eq!(first.op, Some(0));
+        assert!(first.serial);
+        assert_eq!(first.validate, None);
+        assert_eq!(first.then.len(), 1);
+        let get = &first.then[0];
+        assert_eq!(get.op, Some(1));
+        assert!(get.then.is_empty());
+
+        let request = String::from_utf8(first.bytes.clone()).unwrap();
+        let key = request.split(' ').nth(1).unwrap().to_owned();
+        assert_eq!(get.bytes, format!("get {}\r\n", key).into_bytes());
+        let value = request.split("\r\n").nth(1).unwrap();
+        assert_eq!(get.validate, Some(tools::sequence_key(key.as_bytes(), 1)));
+        assert_eq!(tools::sequence_number(key.as_bytes(), value.as_bytes()), Some(1));
+
+        // the next sequence stores a value the read can tell apart
+        let next = sequence.generate_work();
+        assert!(next.then[0].validate != get.validate);
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        assert!(Sequence::new(vec![Op::Set], vec![param(8)], false, false, None, 0).is_err());
+        assert!(Sequence::new(vec![Op::Get], vec![param(8), param(8)], false, false, None, 0)
+                    .is_err());
+        assert!(Sequence::new(vec![Op::Add, Op::Cas], vec![param(8), param(8)], false, false,
+                              None, 0)
+                    .is_err());
+        assert!(Sequence::new(vec![Op::Gets, Op::Cas], vec![param(8), param(8)], true, false,
+                              None, 0)
+                    .is_err());
+        assert!(Sequence::new(vec![Op::Get, Op::Delete], vec![param(8)], false, false,
+                              Some(Flags::Fixed(1)), 0)
+                    .is_err());
+        assert!(Sequence::new(vec![Op::Set, Op::Get], vec![param(8), param(20)], false, true,
+                              None, 0)
+                    .is_err());
+        assert!(Sequence::new(vec![Op::Add, Op::Gets, Op::Cas], vec![param(8), param(8)], false,
+                              false, None, 0)
+                    .is_ok());
+    }
+
+    #[test]
+    fn repeated_ops_are_numbered() {
+        let sequence = Sequence::new(vec![Op::Get, Op::Set, Op::Get], vec![param(8), param(8)],
+                                     true, false, None, 0)
+                           .unwrap();
+        assert_eq!(sequence.ops(), vec!["get#1", "set", "get#2"]);
+    }
+
+    #[test]
+    fn cas_takes_the_unique() {
+        let cas = b"cas key 0 0 5 0\r\nvalue\r\n";
+        assert_eq!(follow(cas, b"VALUE key 0 5 42\r\nhello\r\nEND\r\n"),
+                   b"cas key 0 0 5 42\r\nvalue\r\n".to_vec());
+        assert_eq!(follow(cas, b"END\r\n"), cas.to_vec());
+    }
+}
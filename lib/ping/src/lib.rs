@@ -56,6 +56,10 @@ impl ProtocolParse for Ping {
         let s = str::from_utf8(bytes).unwrap();
         parse::parse_response(s)
     }
+
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_response(bytes)
+    }
 }
 
 /// Load the ping benchmark configuration from the config toml
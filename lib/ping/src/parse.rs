@@ -48,13 +48,20 @@ pub fn parse_response(response: &str) -> ParsedResponse {
     ParsedResponse::Unknown
 }
 
+/// Find the length of the first complete response in the buffer
+pub fn frame_response(response: &[u8]) -> Option<usize> {
+    response.windows(2)
+            .position(|w| w[0] == b'\r' && w[1] == b'\n')
+            .map(|i| i + 2)
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "unstable")]
     extern crate test;
 
     use cfgtypes::ParsedResponse;
-    use super::parse_response;
+    use super::{frame_response, parse_response};
 
     #[test]
     fn test_parse_pong() {
@@ -68,6 +75,13 @@ mod tests {
         assert_eq!(parse_response(r), ParsedResponse::Unknown);
     }
 
+    #[test]
+    fn test_frame_pong() {
+        assert_eq!(frame_response(b"PONG"), None);
+        assert_eq!(frame_response(b"PONG\r\n"), Some(6));
+        assert_eq!(frame_response(b"PONG\r\nPONG\r\n"), Some(6));
+    }
+
     #[cfg(feature = "unstable")]
     #[bench]
     fn parse_ok_benchmark(b: &mut test::Bencher) {
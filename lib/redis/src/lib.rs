@@ -43,6 +43,10 @@ impl Ptype for RedisData {
         self.string = tools::random_string(self.size);
     }
 
+    fn reseed(&mut self, seed: usize) {
+        self.string = tools::seeded_string(self.size, seed);
+    }
+
     fn parse(seed: usize, size: usize, _: &BTreeMap<String, Value>) -> CResult<Self> {
         Ok(RedisData {
             size: size,
@@ -59,30 +63,55 @@ enum Command {
 }
 
 impl Command {
-    fn gen(&mut self) -> Vec<u8> {
+    /// With validation, stored values are derived from their key and reads are checked for it
+    fn gen(&mut self, validate: bool) -> WorkItem {
         match *self {
             Command::Get(ref mut p1) => {
                 p1.regen();
-                gen::get(p1.value.string.as_str()).into_bytes()
+                let mut item = WorkItem::new(gen::get(p1.value.string.as_str()).into_bytes());
+                if validate {
+                    item.validate = Some(p1.value.string.as_bytes().to_vec());
+                }
+                item
             }
             Command::Hget(ref mut p1, ref mut p2) => {
                 p1.regen();
                 p2.regen();
-                gen::hget(p1.value.string.as_str(), p2.value.string.as_str()).into_bytes()
+                WorkItem::new(gen::hget(p1.value.string.as_str(), p2.value.string.as_str())
+                                  .into_bytes())
             }
             Command::Set(ref mut p1, ref mut p2) => {
                 p1.regen();
                 p2.regen();
-                gen::set(p1.value.string.as_str(), p2.value.string.as_str()).into_bytes()
+                if validate {
+                    p2.value.string = tools::validation_value(p1.value.string.as_bytes(),
+                                                              p2.value.size);
+                }
+                WorkItem::new(gen::set(p1.value.string.as_str(), p2.value.string.as_str())
+                                  .into_bytes())
             }
             Command::Hset(ref mut p1, ref mut p2, ref mut p3) => {
                 p1.regen();
                 p2.regen();
                 p3.regen();
-                gen::hset(p1.value.string.as_str(), p2.value.string.as_str(), p3.value.string.as_str()).into_bytes()
+                WorkItem::new(gen::hset(p1.value.string.as_str(), p2.value.string.as_str(), p3.value.string.as_str()).into_bytes())
             }
         }
     }
+
+    fn method(&self) -> &str {
+        match *self {
+            Command::Get(_) => "get",
+            Command::Set(_,_) => "set",
+            Command::Hget(_,_) => "hget",
+            Command::Hset(_,_,_) => "hset",
+        }
+    }
+}
+
+struct RedisWorkload {
+    command: Command,
+    validate: bool,
 }
 
 struct RedisParse;
@@ -91,18 +120,17 @@ struct RedisParseFactory {
     flush: bool
 }
 
-impl ProtocolGen for Command {
+impl ProtocolGen for RedisWorkload {
     fn generate_message(&mut self) -> Vec<u8> {
-        self.gen()
+        self.command.gen(self.validate).bytes
+    }
+
+    fn generate_work(&mut self) -> WorkItem {
+        self.command.gen(self.validate)
     }
 
     fn method(&self) -> &str {
-        match *self {
-            Command::Get(_) => "get",
-            Command::Set(_,_) => "set",
-            Command::Hget(_,_) => "hget",
-            Command::Hset(_,_,_) => "hset",
-        }
+        self.command.method()
     }
 }
 
@@ -131,6 +159,14 @@ impl ProtocolParse for RedisParse {
         let s = str::from_utf8(bytes).unwrap();
         parse::parse_response(s)
     }
+
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_response(bytes)
+    }
+
+    fn value<'a>(&self, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        parse::response_value(bytes)
+    }
 }
 
 /// Load the redis benchmark configuration from the config toml and command line arguments
@@ -177,6 +213,14 @@ fn extract_workload(workload: &BTreeMap<String, Value>) -> CResult<BenchmarkWork
                        .unwrap_or(method.as_str())
                        .to_owned();
 
+    let validate = workload.get("validate")
+                           .and_then(|k| k.as_bool())
+                           .unwrap_or(false);
+
+    if validate && method != "get" && method != "set" {
+        return Err(format!("validate is not supported for method {}", method));
+    }
+
     if let Some(&Value::Array(ref params)) = workload.get("parameter") {
         let mut ps = Vec::new();
         for (i, param) in params.iter().enumerate() {
@@ -204,7 +248,12 @@ fn extract_workload(workload: &BTreeMap<String, Value>) -> CResult<BenchmarkWork
             _ => return Err(format!("invalid command: {}", method)),
         };
 
-        Ok(BenchmarkWorkload::new(name, rate as usize, Box::new(cmd)))
+        let gen = RedisWorkload {
+            command: cmd,
+            validate: validate,
+        };
+
+        Ok(BenchmarkWorkload::new(name, rate as usize, Box::new(gen)))
     } else {
         Err("malformed config: 'parameter' must be an array".to_owned())
     }
@@ -15,6 +15,8 @@
 
 pub use cfgtypes::ParsedResponse;
 
+use std::str;
+
 
 pub fn parse_response(response: &str) -> ParsedResponse {
 
@@ -27,6 +29,11 @@ pub fn parse_response(response: &str) -> ParsedResponse {
         return ParsedResponse::Incomplete;
     }
 
+    // a line without a type
+    if lines.is_empty() || lines[0].is_empty() {
+        return ParsedResponse::Invalid;
+    }
+
     let (first_char, msg) = lines[0].split_at(1);
 
     match first_char {
@@ -74,10 +81,78 @@ pub fn parse_response(response: &str) -> ParsedResponse {
     }
 }
 
+/// Find the length of the first complete response in the buffer
+pub fn frame_response(response: &[u8]) -> Option<usize> {
+    let end = match response.windows(2).position(|w| w[0] == b'\r' && w[1] == b'\n') {
+        Some(i) => i,
+        None => return None,
+    };
+    // an empty line has no type, it's framed alone for the parser to reject
+    if end == 0 {
+        return Some(2);
+    }
+
+    // a null or malformed length ends the response at its header, for the parser to reject
+    let length = str::from_utf8(&response[1..end])
+                     .ok()
+                     .and_then(|b| b.parse::<i64>().ok());
+    match response[0] {
+        b'$' => {
+            match length {
+                Some(b) if b >= 0 => {
+                    // bulk string is followed by its own CRLF
+                    let length = end + 2 + b as usize + 2;
+                    if response.len() >= length {
+                        Some(length)
+                    } else {
+                        None
+                    }
+                }
+                _ => Some(end + 2),
+            }
+        }
+        b'*' => {
+            match length {
+                Some(n) if n > 0 => {
+                    // each element is framed in turn, it may be an array itself
+                    let mut position = end + 2;
+                    for _ in 0..n {
+                        match frame_response(&response[position..]) {
+                            Some(l) => position += l,
+                            None => return None,
+                        }
+                    }
+                    Some(position)
+                }
+                _ => Some(end + 2),
+            }
+        }
+        _ => Some(end + 2),
+    }
+}
+
+/// Data of a complete bulk string response
+pub fn response_value(response: &[u8]) -> Option<&[u8]> {
+    let end = match response.windows(2).position(|w| w[0] == b'\r' && w[1] == b'\n') {
+        Some(i) => i,
+        None => return None,
+    };
+    if end == 0 || response[0] != b'$' {
+        return None;
+    }
+    let bytes = str::from_utf8(&response[1..end])
+                    .ok()
+                    .and_then(|b| b.parse::<usize>().ok());
+    match bytes {
+        Some(b) if end + 2 + b <= response.len() => Some(&response[(end + 2)..(end + 2 + b)]),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use cfgtypes::ParsedResponse;
-    use super::parse_response;
+    use super::{frame_response, parse_response, response_value};
 
     #[test]
     fn test_parse_incomplete() {
@@ -95,6 +170,9 @@ mod tests {
 
         let r = ":OK\r\n";
         assert_eq!(parse_response(r), ParsedResponse::Invalid);
+
+        let r = "\r\n";
+        assert_eq!(parse_response(r), ParsedResponse::Invalid);
     }
 
     #[test]
@@ -133,4 +211,44 @@ mod tests {
         let r = "*-1\r\n";
         assert_eq!(parse_response(r), ParsedResponse::Miss);
     }
+
+    #[test]
+    fn test_frame_incomplete() {
+        assert_eq!(frame_response(b"+OK"), None);
+        assert_eq!(frame_response(b"$5\r\nval"), None);
+        assert_eq!(frame_response(b"$5\r\nvalue\r"), None);
+        assert_eq!(frame_response(b"*2\r\n$1\r\na\r\n"), None);
+        assert_eq!(frame_response(b"*2\r\n*1\r\n:1\r\n"), None);
+    }
+
+    #[test]
+    fn test_frame_complete() {
+        assert_eq!(frame_response(b"+OK\r\n+OK\r\n"), Some(5));
+        assert_eq!(frame_response(b":12345\r\n+OK\r\n"), Some(8));
+        assert_eq!(frame_response(b"$-1\r\n$-1\r\n"), Some(5));
+        assert_eq!(frame_response(b"$5\r\nvalue\r\n$-1\r\n"), Some(11));
+        assert_eq!(frame_response(b"$4\r\n\r\nOK\r\n"), Some(10));
+        assert_eq!(frame_response(b"$x\r\nvalue\r\n+OK\r\n"), Some(4));
+        assert_eq!(frame_response(b"\r\n+OK\r\n"), Some(2));
+        assert_eq!(frame_response(b"*1\r\n\r\n"), Some(6));
+    }
+
+    #[test]
+    fn test_frame_arrays() {
+        // pipelined arrays are framed one reply at a time
+        let replies = b"*2\r\n$1\r\na\r\n$-1\r\n*0\r\n*-1\r\n";
+        assert_eq!(frame_response(replies), Some(16));
+        assert_eq!(frame_response(&replies[16..]), Some(4));
+        assert_eq!(frame_response(&replies[20..]), Some(5));
+        assert_eq!(frame_response(b"*2\r\n*1\r\n:1\r\n+OK\r\n:2\r\n"), Some(17));
+    }
+
+    #[test]
+    fn test_response_value() {
+        assert_eq!(response_value(b"$5\r\nvalue\r\n"), Some(&b"value"[..]));
+        assert_eq!(response_value(b"$4\r\n\r\nOK\r\n"), Some(&b"\r\nOK"[..]));
+        assert_eq!(response_value(b"$-1\r\n"), None);
+        assert_eq!(response_value(b"+OK\r\n"), None);
+        assert_eq!(response_value(b"\r\n"), None);
+    }
 }
@@ -26,12 +26,13 @@ use toml::Value;
 use toml::Value::Table;
 
 use echo;
+use http;
 use memcache;
 use redis;
 use ping;
 use thrift;
-use cfgtypes::{ProtocolParse, ProtocolParseFactory};
-use super::BenchmarkConfig;
+use cfgtypes::{ProtocolParse, ProtocolParseFactory, tools};
+use super::{BenchmarkConfig, Interleave, Percentiles, Ramp};
 
 
 /// Helper for extracting non-string values from the `Matches`
@@ -49,6 +50,11 @@ fn parse_opt<F>(name: &str, matches: &Matches) -> Result<Option<F>, String>
     }
 }
 
+/// Helper for toml values which may be written as an integer or a float
+fn as_number(value: &Value) -> Option<f64> {
+    value.as_float().or_else(|| value.as_integer().map(|i| i as f64))
+}
+
 pub fn load_config(matches: &Matches) -> Result<BenchmarkConfig, String> {
 
     // load the config
@@ -85,15 +91,32 @@ pub fn load_config(matches: &Matches) -> Result<BenchmarkConfig, String> {
                 Err("failed to load config".to_owned())
             }
         }
+    } else if matches.opt_present("replay") {
+        // the requests come from the replay, the protocol is picked on the command line
+        load_config_table(BTreeMap::new(), matches)
     } else {
         Err("config file not specified".to_owned())
     }
 }
 
-fn load_config_table(table: BTreeMap<String, Value>,
+fn load_config_table(mut table: BTreeMap<String, Value>,
                      matches: &Matches)
                      -> Result<BenchmarkConfig, String> {
 
+    // a replay supplies the requests, only the protocol is needed to parse the responses
+    let replay = matches.opt_str("replay");
+    if replay.is_some() {
+        if table.contains_key("workload") {
+            warn!("replay: ignoring the workloads in the config");
+        }
+        table.insert("workload".to_owned(), Value::Array(Vec::new()));
+    }
+    // a run without workloads may still preload or serve stats, which is decided once the
+    // connections and prepare requests are known
+    if !table.contains_key("workload") {
+        table.insert("workload".to_owned(), Value::Array(Vec::new()));
+    }
+
     let protocol: String = matches.opt_str("protocol")
                                   .or_else(|| {
                                       table.get("general")
@@ -104,21 +127,35 @@ fn load_config_table(table: BTreeMap<String, Value>,
                                   })
                                   .unwrap_or_else(|| "memcache".to_owned());
 
+    // seed before loading the workloads, which may generate random values. without a seed
+    // one is picked at random, so that any run can be reproduced
+    let seed = match try!(parse_opt("seed", matches)) {
+        Some(seed) => seed,
+        None => {
+            table.get("general")
+                 .and_then(|k| k.as_table())
+                 .and_then(|k| k.get("seed"))
+                 .and_then(|k| k.as_integer())
+                 .map_or_else(tools::random::<u64>, |k| k as u64)
+        }
+    };
+    tools::seed(seed);
+
     // Pick a protocol
     let proto = match protocol.as_str() {
         "memcache" => try!(memcache::load_config(&table, matches)),
         "echo" => try!(echo::load_config(&table)),
+        "http" => try!(http::load_config(&table, matches)),
         "redis" => try!(redis::load_config(&table, matches)),
         "ping" => try!(ping::load_config(&table)),
         "thrift" => try!(thrift::load_config(&table)),
         _ => return Err(format!("Protocol {} not known", protocol)),
     };
 
-    if proto.workloads.is_empty() {
-        return Err("no workloads specified".to_owned());
-    }
-
     let mut config = BenchmarkConfig::new(proto);
+    config.seed = seed;
+    config.replay = replay;
+    config.replay_loop = matches.opt_present("replay-loop");
 
     if let Some(&Table(ref general)) = table.get("general") {
         if let Some(connections) = general.get("connections")
@@ -135,16 +172,88 @@ fn load_config_table(table: BTreeMap<String, Value>,
         if let Some(windows) = general.get("windows").and_then(|k| k.as_integer()) {
             config.windows = windows as usize;
         }
+        if let Some(max_duration) = general.get("max-duration")
+                                           .and_then(|k| k.as_integer()) {
+            config.max_duration = Some(max_duration as usize);
+        }
         if let Some(tcp_nodelay) = general.get("tcp-nodelay")
                                           .and_then(|k| k.as_bool()) {
             config.tcp_nodelay = tcp_nodelay;
         }
+        if let Some(pipeline) = general.get("pipeline").and_then(|k| k.as_integer()) {
+            config.pipeline = pipeline as usize;
+        }
+        if let Some(capacity) = general.get("event-capacity").and_then(|k| k.as_integer()) {
+            config.event_capacity = Some(capacity as usize);
+        }
+        if let Some(capacity) = general.get("timer-capacity").and_then(|k| k.as_integer()) {
+            config.timer_capacity = Some(capacity as usize);
+        }
+        if let Some(timeout) = general.get("timeout").and_then(|k| k.as_integer()) {
+            config.timeout = Some(timeout as usize);
+        }
+        if let Some(retries) = general.get("retries").and_then(|k| k.as_integer()) {
+            config.retries = retries as usize;
+        }
+        if let Some(&Value::Array(ref percentiles)) = general.get("percentiles") {
+            let mut values = Vec::new();
+            for p in percentiles {
+                match as_number(p) {
+                    Some(v) => values.push(v),
+                    None => return Err(format!("percentile must be a number: {:?}", p)),
+                }
+            }
+            config.percentiles = try!(Percentiles::new(values));
+        }
+        if let Some(slow) = general.get("slow-threshold").and_then(|k| k.as_integer()) {
+            config.slow_threshold = Some(slow as usize);
+        }
+        if let Some(slow_log) = general.get("slow-log").and_then(|k| k.as_bool()) {
+            config.slow_log = slow_log;
+        }
+        if let Some(max) = general.get("histogram-max").and_then(|k| k.as_integer()) {
+            config.histogram_max = max as usize;
+        }
+        if let Some(max) = general.get("heatmap-max").and_then(|k| k.as_integer()) {
+            config.heatmap_max = max as usize;
+        }
+        if let Some(tolerance) = general.get("mix-tolerance").and_then(as_number) {
+            config.mix_tolerance = tolerance;
+        }
+        if let Some(threshold) = general.get("establish-threshold").and_then(as_number) {
+            config.establish_threshold = threshold;
+        }
+        if let Some(include) = general.get("include-connection-ramp").and_then(|k| k.as_bool()) {
+            config.include_connection_ramp = include;
+        }
+        if let Some(interleave) = general.get("interleave").and_then(|k| k.as_str()) {
+            config.interleave = try!(interleave.parse());
+        }
         if let Some(ipv4) = general.get("ipv4").and_then(|k| k.as_bool()) {
             config.ipv4 = ipv4;
         }
         if let Some(ipv6) = general.get("ipv6").and_then(|k| k.as_bool()) {
             config.ipv6 = ipv6;
         }
+        if let Some(p99) = general.get("assert-p99").and_then(|k| k.as_integer()) {
+            config.assert_p99 = Some(p99 as u64);
+        }
+        if let Some(p999) = general.get("assert-p999").and_then(|k| k.as_integer()) {
+            config.assert_p999 = Some(p999 as u64);
+        }
+        if let Some(error_rate) = general.get("assert-error-rate").and_then(as_number) {
+            config.assert_error_rate = Some(error_rate);
+        }
+        if let Some(min_rate) = general.get("assert-min-rate").and_then(as_number) {
+            config.assert_min_rate = Some(min_rate);
+        }
+        if let Some(slow_rate) = general.get("assert-slow-rate").and_then(as_number) {
+            config.assert_slow_rate = Some(slow_rate);
+        }
+    }
+
+    if let Some(&Table(ref ramp)) = table.get("ramp") {
+        config.ramp = Some(try!(extract_ramp(ramp)));
     }
 
     // get any overrides from the command line
@@ -153,6 +262,22 @@ fn load_config_table(table: BTreeMap<String, Value>,
     Ok(config)
 }
 
+/// Extract the `[ramp]` table, all of start, end and step are required
+fn extract_ramp(ramp: &BTreeMap<String, Value>) -> Result<Ramp, String> {
+    let get = |name: &str| {
+        match ramp.get(name).and_then(|k| k.as_integer()) {
+            Some(v) if v >= 0 => Ok(v as usize),
+            Some(v) => Err(format!("ramp {} must not be negative: {}", name, v)),
+            None => Err(format!("ramp {} not specified", name)),
+        }
+    };
+    Ok(Ramp {
+        start: try!(get("start")),
+        end: try!(get("end")),
+        step: try!(get("step")),
+    })
+}
+
 /// Override parameters using command line arguments
 fn config_overrides(config: &mut BenchmarkConfig, matches: &Matches) -> Result<(), String> {
     // override config with commandline options
@@ -174,9 +299,173 @@ fn config_overrides(config: &mut BenchmarkConfig, matches: &Matches) -> Result<(
         config.duration = duration;
     }
 
+    if let Some(max_duration) = try!(parse_opt("max-duration", matches)) {
+        config.max_duration = Some(max_duration);
+    }
+
     if matches.opt_present("tcp-nodelay") {
         config.tcp_nodelay = true;
     }
 
+    if let Some(pipeline) = try!(parse_opt("pipeline", matches)) {
+        config.pipeline = pipeline;
+    }
+
+    if let Some(capacity) = try!(parse_opt("event-capacity", matches)) {
+        config.event_capacity = Some(capacity);
+    }
+
+    if let Some(capacity) = try!(parse_opt("timer-capacity", matches)) {
+        config.timer_capacity = Some(capacity);
+    }
+
+    if let Some(ramp) = try!(parse_opt("ramp-rate", matches)) {
+        config.ramp = Some(ramp);
+    }
+
+    if let Some(timeout) = try!(parse_opt("timeout", matches)) {
+        config.timeout = Some(timeout);
+    }
+
+    if let Some(retries) = try!(parse_opt("retries", matches)) {
+        config.retries = retries;
+    }
+
+    if let Some(percentiles) = try!(parse_opt("percentiles", matches)) {
+        config.percentiles = percentiles;
+    }
+
+    if let Some(slow) = try!(parse_opt("slow-threshold", matches)) {
+        config.slow_threshold = Some(slow);
+    }
+
+    if matches.opt_present("slow-log") {
+        config.slow_log = true;
+    }
+
+    if let Some(max) = try!(parse_opt("histogram-max", matches)) {
+        config.histogram_max = max;
+    }
+
+    if let Some(max) = try!(parse_opt("heatmap-max", matches)) {
+        config.heatmap_max = max;
+    }
+
+    if let Some(tolerance) = try!(parse_opt("mix-tolerance", matches)) {
+        config.mix_tolerance = tolerance;
+    }
+
+    if let Some(threshold) = try!(parse_opt("establish-threshold", matches)) {
+        config.establish_threshold = threshold;
+    }
+
+    if matches.opt_present("include-connection-ramp") {
+        config.include_connection_ramp = true;
+    }
+
+    if let Some(interleave) = try!(parse_opt("interleave", matches)) {
+        config.interleave = interleave;
+    }
+
+    if let Some(p99) = try!(parse_opt("assert-p99", matches)) {
+        config.assert_p99 = Some(p99);
+    }
+
+    if let Some(p999) = try!(parse_opt("assert-p999", matches)) {
+        config.assert_p999 = Some(p999);
+    }
+
+    if let Some(error_rate) = try!(parse_opt("assert-error-rate", matches)) {
+        config.assert_error_rate = Some(error_rate);
+    }
+
+    if let Some(min_rate) = try!(parse_opt("assert-min-rate", matches)) {
+        config.assert_min_rate = Some(min_rate);
+    }
+
+    if let Some(slow_rate) = try!(parse_opt("assert-slow-rate", matches)) {
+        config.assert_slow_rate = Some(slow_rate);
+    }
+
+    if config.threads < 1 {
+        return Err("threads must be at least 1".to_owned());
+    }
+
+    // latency limits are compared in nanoseconds
+    let limits = [("assert-p99", config.assert_p99), ("assert-p999", config.assert_p999)];
+    for &(name, limit) in &limits {
+        if limit.map_or(false, |l| l.checked_mul(1_000).is_none()) {
+            return Err(format!("{} is too large", name));
+        }
+    }
+
+    if config.duration < 1 || config.windows < 1 {
+        return Err("duration and windows must be at least 1".to_owned());
+    }
+
+    if config.max_duration == Some(0) {
+        return Err("max duration must be at least 1 second".to_owned());
+    }
+
+    if let Some(ramp) = config.ramp {
+        if ramp.step < 1 {
+            return Err("ramp step must be at least 1".to_owned());
+        }
+        if ramp.start < 1 || ramp.end < 1 {
+            return Err("ramp rates must be at least 1".to_owned());
+        }
+    }
+
+    if config.pipeline < 1 {
+        return Err("pipeline depth must be at least 1".to_owned());
+    }
+
+    if config.event_capacity == Some(0) || config.timer_capacity == Some(0) {
+        return Err("event and timer capacity must be at least 1".to_owned());
+    }
+
+    if config.timeout == Some(0) {
+        return Err("timeout must be at least 1 ms".to_owned());
+    }
+
+    if config.replay_loop && config.replay.is_none() {
+        return Err("replay-loop requires a replay file".to_owned());
+    }
+
+    // both are applied by the workloads, which a replay replaces
+    if config.replay.is_some() && (config.ramp.is_some() || config.retries > 0) {
+        return Err("ramp and retries are not supported with replay".to_owned());
+    }
+
+    if config.mix_tolerance < 0.0 || config.mix_tolerance > 100.0 {
+        return Err("mix tolerance must be between 0 and 100".to_owned());
+    }
+
+    if config.establish_threshold <= 0.0 || config.establish_threshold > 100.0 {
+        return Err("establish threshold must be over 0 and at most 100".to_owned());
+    }
+
+    // a single workload has nothing to interleave with, and would stall under max-run
+    if config.interleave != Interleave::Free && config.protocol_config.workloads.len() < 2 {
+        return Err(format!("interleave {} requires at least two workloads", config.interleave));
+    }
+
+    if config.slow_threshold == Some(0) {
+        return Err("slow threshold must be at least 1 ms".to_owned());
+    }
+
+    if config.histogram_max == 0 || config.heatmap_max == 0 {
+        return Err("histogram and heatmap max must be at least 1 ms".to_owned());
+    }
+
+    if config.slow_threshold.is_none() && (config.slow_log || config.assert_slow_rate.is_some()) {
+        return Err("slow-log and assert-slow-rate require a slow threshold".to_owned());
+    }
+
+    // only a request which timed out is retried
+    if config.retries > 0 && config.timeout.is_none() {
+        return Err("retries require a request timeout".to_owned());
+    }
+
     Ok(())
 }
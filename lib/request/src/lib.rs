@@ -27,24 +27,251 @@ extern crate time;
 
 extern crate rpcperf_cfgtypes as cfgtypes;
 extern crate rpcperf_echo as echo;
+extern crate rpcperf_http as http;
 extern crate rpcperf_redis as redis;
 extern crate rpcperf_memcache as memcache;
 extern crate rpcperf_ping as ping;
 extern crate rpcperf_thrift as thrift;
 
 pub mod config;
+pub mod replay;
 pub mod workload;
 
 use cfgtypes::ProtocolConfig;
+use std::fmt;
+use std::str::FromStr;
+
+/// What a run does, given the connections and requests it was configured with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// send the workloads or a replay and measure the responses
+    Measure,
+    /// send only the protocol's prepare requests, then exit once they are answered
+    Preload,
+    /// open no connections, and serve the stats of the listen address until the run ends
+    Scrape,
+}
+
+impl Mode {
+    /// Pick the mode, or explain why the configuration can do nothing useful
+    ///
+    /// `connections` is the total across threads and servers, `prepared` the number of requests
+    /// the protocol sends before the workloads.
+    pub fn pick(connections: usize,
+                workloads: usize,
+                replay: bool,
+                prepared: usize,
+                listen: bool)
+                -> Result<Mode, String> {
+        if connections == 0 {
+            if listen {
+                return Ok(Mode::Scrape);
+            }
+            return Err("no connections to send requests on, use --listen to only serve stats"
+                           .to_owned());
+        }
+        if workloads > 0 || replay {
+            return Ok(Mode::Measure);
+        }
+        if prepared > 0 {
+            return Ok(Mode::Preload);
+        }
+        if listen {
+            return Ok(Mode::Scrape);
+        }
+        Err("no workloads specified".to_owned())
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Measure => "measure",
+            Mode::Preload => "preload-only",
+            Mode::Scrape => "scrape-only",
+        }
+    }
+}
+
+/// Offered load which steps from `start` towards `end` by `step` each window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    pub start: usize,
+    pub end: usize,
+    pub step: usize,
+}
+
+impl Ramp {
+    /// Rate for the given step of the ramp, holding at `end` once it is reached
+    pub fn rate(&self, step: usize) -> usize {
+        let delta = self.step.saturating_mul(step);
+        if self.start <= self.end {
+            self.start.saturating_add(delta).min(self.end)
+        } else {
+            self.start.saturating_sub(delta).max(self.end)
+        }
+    }
+
+    /// Number of steps before the ramp reaches `end`
+    pub fn steps(&self) -> usize {
+        let distance = if self.start <= self.end {
+            self.end - self.start
+        } else {
+            self.start - self.end
+        };
+        (distance + self.step - 1) / self.step + 1
+    }
+}
+
+impl FromStr for Ramp {
+    type Err = String;
+
+    /// Parse a ramp written as `START:END:STEP`
+    fn from_str(s: &str) -> Result<Ramp, String> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 3 {
+            return Err(format!("ramp must be START:END:STEP: {}", s));
+        }
+        let mut values = Vec::new();
+        for part in parts {
+            match part.parse() {
+                Ok(v) => values.push(v),
+                Err(e) => return Err(format!("bad ramp value {}: {}", part, e)),
+            }
+        }
+        Ok(Ramp {
+            start: values[0],
+            end: values[1],
+            step: values[2],
+        })
+    }
+}
+
+/// Order in which the requests of several workloads may be sent on a connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interleave {
+    /// in whatever order they are generated
+    Free,
+    /// one request of each workload in turn
+    RoundRobin,
+    /// no more than this many consecutive requests of one workload
+    MaxRun(usize),
+}
+
+impl Default for Interleave {
+    fn default() -> Interleave {
+        Interleave::Free
+    }
+}
+
+impl FromStr for Interleave {
+    type Err = String;
+
+    /// Parse `free`, `round-robin` or `max-run K`, where K may also follow a colon
+    fn from_str(s: &str) -> Result<Interleave, String> {
+        let mut parts = s.split(|c: char| c == ':' || c.is_whitespace())
+                         .filter(|p| !p.is_empty());
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("free"), None, _) => Ok(Interleave::Free),
+            (Some("round-robin"), None, _) => Ok(Interleave::RoundRobin),
+            (Some("max-run"), Some(k), None) => {
+                match k.parse() {
+                    Ok(0) => Err("max-run must be at least 1".to_owned()),
+                    Ok(k) => Ok(Interleave::MaxRun(k)),
+                    Err(e) => Err(format!("bad max-run {}: {}", k, e)),
+                }
+            }
+            _ => Err(format!("interleave must be free, round-robin or max-run K: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interleave {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Interleave::Free => write!(f, "free"),
+            Interleave::RoundRobin => write!(f, "round-robin"),
+            Interleave::MaxRun(k) => write!(f, "max-run {}", k),
+        }
+    }
+}
+
+/// Latency percentiles to report, each between 0 and 100
+#[derive(Clone, Debug, PartialEq)]
+pub struct Percentiles(pub Vec<f64>);
+
+impl Percentiles {
+    pub fn new(values: Vec<f64>) -> Result<Percentiles, String> {
+        if values.is_empty() {
+            return Err("at least one percentile is required".to_owned());
+        }
+        for v in &values {
+            if !(*v >= 0.0 && *v <= 100.0) {
+                return Err(format!("percentile must be between 0 and 100: {}", v));
+            }
+        }
+        Ok(Percentiles(values))
+    }
+}
+
+impl Default for Percentiles {
+    fn default() -> Percentiles {
+        Percentiles(vec![50.0, 90.0, 99.0, 99.9, 99.99])
+    }
+}
+
+impl FromStr for Percentiles {
+    type Err = String;
+
+    /// Parse a comma separated list, eg: `50,90,99,99.9`
+    fn from_str(s: &str) -> Result<Percentiles, String> {
+        let mut values = Vec::new();
+        for part in s.split(',') {
+            match part.trim().parse() {
+                Ok(v) => values.push(v),
+                Err(e) => return Err(format!("bad percentile {}: {}", part, e)),
+            }
+        }
+        Percentiles::new(values)
+    }
+}
 
 pub struct BenchmarkConfig {
     pub connections: usize,
     pub threads: usize,
     pub duration: usize,
     pub windows: usize,
+    pub max_duration: Option<usize>,
+    pub seed: u64,
     pub tcp_nodelay: bool,
+    pub pipeline: usize,
+    /// mio event loop sizing, None keeps the mio default
+    pub event_capacity: Option<usize>,
+    pub timer_capacity: Option<usize>,
+    pub ramp: Option<Ramp>,
+    pub timeout: Option<usize>,
+    pub retries: usize,
+    pub percentiles: Percentiles,
+    pub slow_threshold: Option<usize>,
+    pub slow_log: bool,
+    /// largest latency in milliseconds the histograms and heatmap track, slower samples are
+    /// recorded at the maximum and counted as overflow
+    pub histogram_max: usize,
+    pub heatmap_max: usize,
+    pub interleave: Interleave,
+    /// percentage points the realized workload mix may drift from the rates before a warning
+    pub mix_tolerance: f64,
+    /// percent of the connections established before windows count towards the run
+    pub establish_threshold: f64,
+    /// count the windows of the connection ramp towards the run all the same
+    pub include_connection_ramp: bool,
+    pub replay: Option<String>,
+    pub replay_loop: bool,
     pub ipv4: bool,
     pub ipv6: bool,
+    pub assert_p99: Option<u64>,
+    pub assert_p999: Option<u64>,
+    pub assert_error_rate: Option<f64>,
+    pub assert_min_rate: Option<f64>,
+    pub assert_slow_rate: Option<f64>,
     pub protocol_config: ProtocolConfig,
 }
 
@@ -55,10 +282,113 @@ impl BenchmarkConfig {
             threads: 1,
             duration: 60,
             windows: 5,
+            max_duration: None,
+            seed: 0,
             tcp_nodelay: false,
+            pipeline: 1,
+            event_capacity: None,
+            timer_capacity: None,
+            ramp: None,
+            timeout: None,
+            retries: 0,
+            percentiles: Percentiles::default(),
+            slow_threshold: None,
+            slow_log: false,
+            histogram_max: 60_000,
+            heatmap_max: 1_000,
+            mix_tolerance: 5.0,
+            establish_threshold: 100.0,
+            include_connection_ramp: false,
+            interleave: Interleave::Free,
+            replay: None,
+            replay_loop: false,
             ipv4: true,
             ipv6: true,
+            assert_p99: None,
+            assert_p999: None,
+            assert_error_rate: None,
+            assert_min_rate: None,
+            assert_slow_rate: None,
             protocol_config: protocol,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Interleave, Mode, Percentiles, Ramp};
+
+    #[test]
+    fn ramp_parse() {
+        assert_eq!("1000:50000:1000".parse(),
+                   Ok(Ramp {
+                       start: 1000,
+                       end: 50000,
+                       step: 1000,
+                   }));
+        assert!("1000:50000".parse::<Ramp>().is_err());
+        assert!("1000:50000:x".parse::<Ramp>().is_err());
+    }
+
+    #[test]
+    fn ramp_up_holds_at_end() {
+        let ramp = Ramp {
+            start: 1000,
+            end: 2500,
+            step: 1000,
+        };
+        assert_eq!(ramp.rate(0), 1000);
+        assert_eq!(ramp.rate(1), 2000);
+        assert_eq!(ramp.rate(2), 2500);
+        assert_eq!(ramp.rate(10), 2500);
+        assert_eq!(ramp.steps(), 3);
+    }
+
+    #[test]
+    fn ramp_down() {
+        let ramp = Ramp {
+            start: 3000,
+            end: 1000,
+            step: 1000,
+        };
+        assert_eq!(ramp.rate(0), 3000);
+        assert_eq!(ramp.rate(2), 1000);
+        assert_eq!(ramp.rate(5), 1000);
+        assert_eq!(ramp.steps(), 3);
+    }
+
+    #[test]
+    fn percentiles_parse() {
+        assert_eq!("50,90,99.9".parse(), Ok(Percentiles(vec![50.0, 90.0, 99.9])));
+        assert_eq!("0, 100".parse(), Ok(Percentiles(vec![0.0, 100.0])));
+        assert!("50,100.1".parse::<Percentiles>().is_err());
+        assert!("-1".parse::<Percentiles>().is_err());
+        assert!("50,,99".parse::<Percentiles>().is_err());
+        assert!("NaN".parse::<Percentiles>().is_err());
+    }
+
+    #[test]
+    fn interleave_parse() {
+        assert_eq!("free".parse(), Ok(Interleave::Free));
+        assert_eq!("round-robin".parse(), Ok(Interleave::RoundRobin));
+        assert_eq!("max-run 3".parse(), Ok(Interleave::MaxRun(3)));
+        assert_eq!("max-run:2".parse(), Ok(Interleave::MaxRun(2)));
+        assert!("max-run 0".parse::<Interleave>().is_err());
+        assert!("max-run".parse::<Interleave>().is_err());
+        assert!("max-run 1 2".parse::<Interleave>().is_err());
+        assert!("round-robin 2".parse::<Interleave>().is_err());
+        assert!("fifo".parse::<Interleave>().is_err());
+    }
+
+    #[test]
+    fn mode_pick() {
+        assert_eq!(Mode::pick(4, 2, false, 0, false), Ok(Mode::Measure));
+        assert_eq!(Mode::pick(4, 0, true, 0, false), Ok(Mode::Measure));
+        assert_eq!(Mode::pick(4, 0, false, 1, false), Ok(Mode::Preload));
+        assert_eq!(Mode::pick(4, 0, false, 1, true), Ok(Mode::Preload));
+        assert_eq!(Mode::pick(4, 0, false, 0, true), Ok(Mode::Scrape));
+        assert_eq!(Mode::pick(0, 2, false, 1, true), Ok(Mode::Scrape));
+        assert!(Mode::pick(0, 2, false, 1, false).is_err());
+        assert!(Mode::pick(4, 0, false, 0, false).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use mpmc;
+use shuteye;
+use std::fs::File;
+use std::io::Read;
+use std::thread;
+
+use cfgtypes::WorkItem;
+
+// nanoseconds to wait for room on a full queue, doubling from the shortest to the longest
+const MIN_WAIT: i64 = 1_000;
+const MAX_WAIT: i64 = 1_000_000;
+
+/// Load captured requests, each framed as a 4-byte big-endian length followed by its bytes
+pub fn load(path: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut f) => {
+            if let Err(e) = f.read_to_end(&mut bytes) {
+                return Err(format!("Error reading replay {}: {}", path, e));
+            }
+        }
+        Err(e) => return Err(format!("Error opening replay {}: {}", path, e)),
+    }
+    parse(&bytes)
+}
+
+/// Split a capture into its requests
+pub fn parse(bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut requests = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        if bytes.len() - offset < 4 {
+            return Err(format!("replay: truncated length at byte {}", offset));
+        }
+        let length = bytes[offset..(offset + 4)]
+                         .iter()
+                         .fold(0, |len, b| (len << 8) | *b as usize);
+        offset += 4;
+        if bytes.len() - offset < length {
+            return Err(format!("replay: request at byte {} needs {} bytes, {} remain",
+                               offset - 4,
+                               length,
+                               bytes.len() - offset));
+        }
+        requests.push(bytes[offset..(offset + length)].to_vec());
+        offset += length;
+    }
+    if requests.is_empty() {
+        return Err("replay: no requests".to_owned());
+    }
+    Ok(requests)
+}
+
+/// Push the requests onto the work queue in order, from the start again if looping
+pub fn launch_replay(requests: Vec<Vec<u8>>, work_queue: mpmc::Queue<WorkItem>, looping: bool) {
+    thread::spawn(move || {
+        loop {
+            for request in &requests {
+                // the sequence is replayed exactly, so wait for room rather than drop a request
+                let mut item = WorkItem::new(request.clone());
+                let mut wait = MIN_WAIT;
+                while let Err(i) = work_queue.push(item) {
+                    item = i;
+                    shuteye::sleep(shuteye::Timespec::from_nano(wait).unwrap());
+                    wait = (wait * 2).min(MAX_WAIT);
+                }
+            }
+            if !looping {
+                info!("Replay: all {} requests sent", requests.len());
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parse_requests() {
+        let capture = b"\x00\x00\x00\x05get a\x00\x00\x00\x00\x00\x00\x00\x02ok";
+        assert_eq!(parse(capture),
+                   Ok(vec![b"get a".to_vec(), Vec::new(), b"ok".to_vec()]));
+    }
+
+    #[test]
+    fn parse_truncated() {
+        assert!(parse(b"\x00\x00\x00\x05get").is_err());
+        assert!(parse(b"\x00\x00\x00\x02ok\x00\x00").is_err());
+        assert!(parse(b"").is_err());
+    }
+}
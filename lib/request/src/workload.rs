@@ -19,14 +19,29 @@ pub const BUCKET_SIZE: usize = 10_000;
 use cfgtypes;
 use mpmc;
 use ratelimit::Ratelimit;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use time;
 
-use cfgtypes::ProtocolGen;
+use cfgtypes::{ProtocolGen, WorkItem, tools};
 
 /// Launch each of the workloads in their own thread
+///
+/// Each workload generates the same sequence of requests on every run with the same seed. With an
+/// offered rate, the workloads follow it as it changes, each taking a share in proportion to
+/// its configured rate, or an equal share if no rates are configured.
+///
+/// Requests waiting in the retry queue are sent in place of new ones, so retries are paced by the
+/// same rate limits and the offered load is unchanged.
 pub fn launch_workloads(workloads: Vec<cfgtypes::BenchmarkWorkload>,
-                        work_queue: mpmc::Queue<Vec<u8>>) {
+                        work_queue: mpmc::Queue<WorkItem>,
+                        retry_queue: Option<mpmc::Queue<WorkItem>>,
+                        seed: u64,
+                        offered: Option<Arc<AtomicUsize>>) {
+
+    let total = workloads.iter().fold(0, |sum, w| sum + w.rate);
+    let count = workloads.len();
 
     for (i, w) in workloads.into_iter().enumerate() {
         info!("Workload {}: Method: {} Rate: {}",
@@ -35,8 +50,24 @@ pub fn launch_workloads(workloads: Vec<cfgtypes::BenchmarkWorkload>,
               w.rate);
 
         let mut workload = Workload::new(w.gen, Some(w.rate as u64), work_queue.clone()).unwrap();
+        workload.index = i;
+        workload.retries = retry_queue.clone();
+
+        if let Some(ref offered) = offered {
+            let share = if total > 0 {
+                w.rate as f64 / total as f64
+            } else {
+                1.0 / count as f64
+            };
+            workload.ramp = Some(RampTarget {
+                offered: offered.clone(),
+                share: share,
+                current: 0,
+            });
+        }
 
         thread::spawn(move || {
+            tools::seed(tools::derive_seed(seed, i));
             loop {
                 workload.run();
             }
@@ -44,17 +75,28 @@ pub fn launch_workloads(workloads: Vec<cfgtypes::BenchmarkWorkload>,
     }
 }
 
+// this workload's share of an aggregate offered rate which changes during the run
+struct RampTarget {
+    offered: Arc<AtomicUsize>,
+    share: f64,
+    current: usize,
+}
+
 struct Workload {
     protocol: Box<ProtocolGen>,
     rate: u64,
     ratelimit: Ratelimit,
-    queue: mpmc::Queue<Vec<u8>>,
+    queue: mpmc::Queue<WorkItem>,
+    ramp: Option<RampTarget>,
+    retries: Option<mpmc::Queue<WorkItem>>,
+    // position in the config, which tags each request so the realized mix can be measured
+    index: usize,
 }
 
 impl Workload {
     fn new(protocol: Box<ProtocolGen>,
            rate: Option<u64>,
-           queue: mpmc::Queue<Vec<u8>>)
+           queue: mpmc::Queue<WorkItem>)
            -> Result<Workload, &'static str> {
         let r = rate.unwrap_or(0);
         let i = rate_to_interval(r);
@@ -69,17 +111,63 @@ impl Workload {
             rate: rate.unwrap_or(0),
             ratelimit: ratelimit,
             queue: queue,
+            ramp: None,
+            retries: None,
+            index: 0,
         })
     }
 
+    // the new rate for this workload, if the offered rate has changed
+    fn ramp_rate(&mut self) -> Option<u64> {
+        if let Some(ref mut ramp) = self.ramp {
+            let offered = ramp.offered.load(Ordering::Relaxed);
+            if offered != ramp.current {
+                ramp.current = offered;
+                // a rate of zero would be unlimited
+                return Some(((offered as f64 * ramp.share) as u64).max(1));
+            }
+        }
+        None
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        let i = rate_to_interval(rate);
+        if let Some(r) = Ratelimit::new(BUCKET_SIZE, time::precise_time_ns(), i, 1) {
+            self.rate = rate;
+            self.ratelimit = r;
+        } else {
+            debug!("ratelimit change to {} failed", rate);
+        }
+    }
+
     fn run(&mut self) {
         loop {
+            if let Some(rate) = self.ramp_rate() {
+                self.set_rate(rate);
+            }
+
             if self.rate != 0 {
                 self.ratelimit.block(1);
             }
 
-            let query = self.protocol.generate_message();
-            let _ = self.queue.push(query);
+            match self.retries.as_ref().and_then(|r| r.pop()) {
+                Some(retry) => {
+                    // a retry was already sent once, it must not be dropped when the queue is full
+                    if let Err(retry) = self.queue.push(retry) {
+                        if let Some(ref retries) = self.retries {
+                            let _ = retries.push(retry);
+                        }
+                    }
+                }
+                None => {
+                    let mut query = self.protocol.generate_work();
+                    query.workload = Some(self.index);
+                    for op in &mut query.then {
+                        op.workload = Some(self.index);
+                    }
+                    let _ = self.queue.push(query);
+                }
+            }
         }
     }
 }
@@ -60,6 +60,10 @@ impl ProtocolParse for ThriftParse {
     fn parse(&self, bytes: &[u8]) -> ParsedResponse {
         parse::parse_response(bytes)
     }
+
+    fn frame(&self, bytes: &[u8]) -> Option<usize> {
+        parse::frame_response(bytes)
+    }
 }
 
 /// Load the thrift benchmark configuration from the config toml
@@ -16,7 +16,6 @@
 #![cfg_attr(feature = "unstable", feature(test))]
 
 extern crate byteorder;
-extern crate rand;
 extern crate rpcperf_cfgtypes as cfgtypes;
 extern crate toml;
 
@@ -70,12 +69,12 @@ pub enum Tvalue {
 impl Tvalue {
     fn regen(&mut self, size: usize) {
         match *self {
-            Tvalue::Bool(ref mut v) => *v = rand::random::<bool>(),
-            Tvalue::Byte(ref mut v) => *v = rand::random::<u8>(),
-            Tvalue::Double(ref mut v) => *v = rand::random::<f64>(),
-            Tvalue::Int16(ref mut v) => *v = rand::random::<i16>(),
-            Tvalue::Int32(ref mut v) => *v = rand::random::<i32>(),
-            Tvalue::Int64(ref mut v) => *v = rand::random::<i64>(),
+            Tvalue::Bool(ref mut v) => *v = tools::random::<bool>(),
+            Tvalue::Byte(ref mut v) => *v = tools::random::<u8>(),
+            Tvalue::Double(ref mut v) => *v = tools::random::<f64>(),
+            Tvalue::Int16(ref mut v) => *v = tools::random::<i16>(),
+            Tvalue::Int32(ref mut v) => *v = tools::random::<i32>(),
+            Tvalue::Int64(ref mut v) => *v = tools::random::<i64>(),
             Tvalue::String(ref mut v) => *v = tools::random_string(size),
             _ => {}
         }
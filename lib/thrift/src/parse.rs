@@ -29,12 +29,25 @@ pub fn parse_response(response: &[u8]) -> ParsedResponse {
     ParsedResponse::Incomplete
 }
 
+/// Find the length of the first complete response in the buffer
+pub fn frame_response(response: &[u8]) -> Option<usize> {
+    if response.len() < 4 {
+        return None;
+    }
+    let length = BigEndian::read_u32(&response[0..4]) as usize + 4;
+    if response.len() >= length {
+        Some(length)
+    } else {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[cfg(feature = "unstable")]
     extern crate test;
 
-    use super::parse_response;
+    use super::{frame_response, parse_response};
     use cfgtypes::ParsedResponse;
 
     #[test]
@@ -50,6 +63,14 @@ mod tests {
         assert_eq!(parse_response(&[0, 0, 0, 2, 0]), ParsedResponse::Incomplete);
     }
 
+    #[test]
+    fn test_frame() {
+        assert_eq!(frame_response(&[0, 0, 0]), None);
+        assert_eq!(frame_response(&[0, 0, 0, 2, 0]), None);
+        assert_eq!(frame_response(&[0, 0, 0, 1, 0]), Some(5));
+        assert_eq!(frame_response(&[0, 0, 0, 1, 0, 0, 0, 0, 1, 0]), Some(5));
+    }
+
     #[cfg(feature = "unstable")]
     #[bench]
     fn parse_ok_benchmark(b: &mut test::Bencher) {
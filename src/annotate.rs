@@ -0,0 +1,246 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use lodepng;
+use time::{self, Timespec};
+
+use history;
+
+/// Most annotations a run keeps, later ones are rejected
+pub const MAX_ANNOTATIONS: usize = 100;
+/// Longest label accepted, in bytes
+pub const MAX_LABEL: usize = 256;
+
+/// An external event marked during the run, eg: a deploy or failover
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub label: String,
+    pub time: Timespec,
+    /// window the event fell in, 0 for the warmup
+    pub window: usize,
+}
+
+impl Annotation {
+    /// JSON object of the annotation, the record written to the `--output` file
+    pub fn json(&self) -> String {
+        format!("{{\"type\":\"annotation\",\"window\":{},\"timestamp\":\"{}\",\"label\":{}}}",
+                self.window,
+                time::at_utc(self.time).rfc3339(),
+                history::quote(&self.label))
+    }
+}
+
+/// Annotations of the run, and the start of each window to place them in
+pub struct Annotations {
+    entries: Vec<Annotation>,
+    // the warmup first
+    starts: Vec<Timespec>,
+}
+
+impl Annotations {
+    pub fn new(start: Timespec) -> Annotations {
+        Annotations {
+            entries: Vec::new(),
+            starts: vec![start],
+        }
+    }
+
+    pub fn start_window(&mut self, start: Timespec) {
+        self.starts.push(start);
+    }
+
+    /// Record an event at `time`, which must fall between the start of the run and `now`
+    pub fn add(&mut self,
+               label: String,
+               time: Timespec,
+               now: Timespec)
+               -> Result<Annotation, String> {
+        if self.entries.len() >= MAX_ANNOTATIONS {
+            return Err(format!("the run already has the most annotations, {}", MAX_ANNOTATIONS));
+        }
+        if time > now {
+            return Err("timestamp is in the future".to_owned());
+        }
+        let window = match self.starts.iter().rposition(|s| *s <= time) {
+            Some(w) => w,
+            None => return Err("timestamp is before the run started".to_owned()),
+        };
+        let annotation = Annotation {
+            label: label,
+            time: time,
+            window: window,
+        };
+        self.entries.push(annotation.clone());
+        Ok(annotation)
+    }
+
+    pub fn entries(&self) -> &[Annotation] {
+        &self.entries
+    }
+
+    // rows of the waterfall, which has one per second from the start of the warmup
+    fn rows(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .map(|a| (a.time - self.starts[0]).num_seconds() as usize)
+            .collect()
+    }
+
+    /// Draw a line across the rendered waterfall at the time of each annotation
+    pub fn mark_waterfall(&self, file: &str) -> Result<(), String> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        let mut bitmap = match lodepng::decode32_file(file) {
+            Ok(b) => b,
+            Err(e) => return Err(format!("Error reading waterfall {}: {}", file, e)),
+        };
+        let width = bitmap.width;
+        let height = bitmap.height;
+        {
+            let pixels = bitmap.buffer.as_mut();
+            for row in self.rows().into_iter().filter(|r| *r < height) {
+                for pixel in &mut pixels[(row * width)..((row + 1) * width)] {
+                    pixel.r = 255;
+                    pixel.g = 0;
+                    pixel.b = 255;
+                    pixel.a = 255;
+                }
+            }
+        }
+        match lodepng::encode32_file(file, bitmap.buffer.as_ref(), width, height) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Error writing waterfall {}: {}", file, e)),
+        }
+    }
+}
+
+/// Label and time of an annotation from the query string or form body of `POST /annotate`,
+/// eg: `label=failover&timestamp=1476540000.5`, the time defaulting to `now`
+pub fn parse(query: &str, body: &str, now: Timespec) -> Result<(String, Timespec), String> {
+    let mut label = None;
+    let mut timestamp = None;
+    for pair in query.split('&').chain(body.trim().split('&')).filter(|p| !p.is_empty()) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("label"), Some(v)) => label = Some(try!(decode(v))),
+            (Some("timestamp"), Some(v)) => timestamp = Some(try!(parse_timestamp(v))),
+            _ => {}
+        }
+    }
+    let label = match label {
+        Some(l) => l,
+        None => return Err("label is required".to_owned()),
+    };
+    if label.is_empty() || label.len() > MAX_LABEL {
+        return Err(format!("label must be 1 to {} bytes", MAX_LABEL));
+    }
+    // a label is logged and written to files as it is
+    if label.chars().any(|c| c.is_control()) {
+        return Err("label must not contain control characters".to_owned());
+    }
+    Ok((label, timestamp.unwrap_or(now)))
+}
+
+// seconds since the epoch, with an optional fraction
+fn parse_timestamp(value: &str) -> Result<Timespec, String> {
+    match value.parse::<f64>() {
+        Ok(s) if s.is_finite() && s >= 0.0 => {
+            Ok(Timespec::new(s.trunc() as i64, (s.fract() * 1e9) as i32))
+        }
+        _ => Err(format!("bad timestamp: {} must be seconds since the epoch", value)),
+    }
+}
+
+// form decoding, `+` is a space and `%XX` a byte
+fn decode(value: &str) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    let mut input = value.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = input.by_ref().take(2).collect();
+                let byte = String::from_utf8(hex)
+                               .ok()
+                               .and_then(|h| u8::from_str_radix(&h, 16).ok());
+                match byte {
+                    Some(b) => bytes.push(b),
+                    None => return Err(format!("bad escape in: {}", value)),
+                }
+            }
+            _ => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("not UTF-8: {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Timespec;
+
+    #[test]
+    fn parse_form() {
+        let now = Timespec::new(1_476_540_000, 0);
+        assert_eq!(parse("", "label=cache+clear%21", now),
+                   Ok(("cache clear!".to_owned(), now)));
+        assert_eq!(parse("label=deploy&timestamp=1476539990.5", "", now),
+                   Ok(("deploy".to_owned(), Timespec::new(1_476_539_990, 500_000_000))));
+        assert!(parse("", "", now).is_err());
+        assert!(parse("", "label=", now).is_err());
+        assert!(parse("", "label=a%0Ab", now).is_err());
+        assert!(parse("", "label=a%zz", now).is_err());
+        assert!(parse("", "label=a&timestamp=soon", now).is_err());
+    }
+
+    #[test]
+    fn windows() {
+        let mut annotations = Annotations::new(Timespec::new(100, 0));
+        annotations.start_window(Timespec::new(160, 0));
+        let now = Timespec::new(200, 0);
+
+        let a = annotations.add("warm".to_owned(), Timespec::new(130, 0), now).unwrap();
+        assert_eq!(a.window, 0);
+        let a = annotations.add("deploy".to_owned(), Timespec::new(160, 0), now).unwrap();
+        assert_eq!(a.window, 1);
+        assert!(annotations.add("early".to_owned(), Timespec::new(99, 0), now).is_err());
+        assert!(annotations.add("late".to_owned(), Timespec::new(201, 0), now).is_err());
+        assert_eq!(annotations.rows(), vec![30, 60]);
+    }
+
+    #[test]
+    fn bounded() {
+        let now = Timespec::new(100, 0);
+        let mut annotations = Annotations::new(now);
+        for _ in 0..MAX_ANNOTATIONS {
+            assert!(annotations.add("event".to_owned(), now, now).is_ok());
+        }
+        assert!(annotations.add("event".to_owned(), now, now).is_err());
+        assert_eq!(annotations.entries().len(), MAX_ANNOTATIONS);
+    }
+
+    #[test]
+    fn json() {
+        let a = Annotation {
+            label: "say \"hi\"".to_owned(),
+            time: Timespec::new(0, 0),
+            window: 2,
+        };
+        assert_eq!(a.json(),
+                   "{\"type\":\"annotation\",\"window\":2,\"timestamp\":\"1970-01-01T00:00:00Z\",\
+                    \"label\":\"say \\\"hi\\\"\"}");
+    }
+}
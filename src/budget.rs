@@ -0,0 +1,275 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use libc;
+
+// stdin, stdout and stderr
+const STDIO_FDS: usize = 3;
+// the epoll or kqueue instance of each client's event loop, and both ends of its wakeup pipe
+const EVENT_LOOP_FDS: usize = 3;
+// a budget using more than this share of a limit is tight
+const TIGHT: f64 = 0.8;
+
+const MEGABYTE: usize = 1024 * 1024;
+
+/// Limits of the host a run has to fit in, None where one couldn't be read
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// soft and hard RLIMIT_NOFILE
+    pub files: Option<(usize, usize)>,
+    /// bytes of physical memory
+    pub memory: Option<usize>,
+    pub cores: Option<usize>,
+}
+
+impl Limits {
+    #[cfg(unix)]
+    pub fn host() -> Limits {
+        let mut rlimit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let files = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlimit) } == 0 {
+            Some((rlimit.rlim_cur as usize, rlimit.rlim_max as usize))
+        } else {
+            None
+        };
+        let pages = unsafe { libc::sysconf(libc::_SC_PHYS_PAGES) };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        let cores = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        Limits {
+            files: files,
+            memory: if pages > 0 && page_size > 0 {
+                Some(pages as usize * page_size as usize)
+            } else {
+                None
+            },
+            cores: if cores > 0 {
+                Some(cores as usize)
+            } else {
+                None
+            },
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn host() -> Limits {
+        Limits::default()
+    }
+}
+
+/// What a run needs from the host, sized from the structures it allocates
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+    /// connections across every client thread
+    pub connections: usize,
+    pub client_threads: usize,
+    /// threads generating requests, one per workload or one replaying a capture
+    pub generator_threads: usize,
+    /// the stats listen address
+    pub listeners: usize,
+    /// files written with results
+    pub outputs: usize,
+    /// buffer bytes of each connection
+    pub connection_memory: usize,
+    /// bytes of latency and size samples
+    pub stats_memory: usize,
+}
+
+/// A budget the host can't meet, or one it only just meets
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    Impossible(String),
+    Tight(String),
+}
+
+impl Budget {
+    pub fn file_descriptors(&self) -> usize {
+        STDIO_FDS + self.connections + self.client_threads * EVENT_LOOP_FDS + self.listeners +
+        self.outputs
+    }
+
+    pub fn memory(&self) -> usize {
+        self.connections * self.connection_memory + self.stats_memory
+    }
+
+    /// Every thread of the run, including the main thread collecting stats
+    pub fn threads(&self) -> usize {
+        self.client_threads + self.generator_threads + self.listeners + 1
+    }
+
+    /// Log the budget beside the limits it has to fit in
+    pub fn report(&self, limits: &Limits) {
+        info!("Budget: File Descriptors: {} Limit: {}",
+              self.file_descriptors(),
+              limits.files.map_or("unknown".to_owned(), |(soft, _)| soft.to_string()));
+        info!("Budget: Memory: {} MB Physical: {}",
+              self.memory() / MEGABYTE,
+              limits.memory.map_or("unknown".to_owned(), |m| format!("{} MB", m / MEGABYTE)));
+        info!("Budget: Threads: {} Cores: {}",
+              self.threads(),
+              limits.cores.map_or("unknown".to_owned(), |c| c.to_string()));
+    }
+
+    /// Compare the budget with the limits, a limit which couldn't be read is never a problem
+    pub fn check(&self, limits: &Limits) -> Vec<Problem> {
+        let mut problems = Vec::new();
+
+        if let Some((soft, hard)) = limits.files {
+            let needed = self.file_descriptors();
+            if needed > soft {
+                let raise = if needed <= hard {
+                    format!("raise it with ulimit -n {}", needed)
+                } else {
+                    format!("the hard limit is {}", hard)
+                };
+                problems.push(Problem::Impossible(format!("{} file descriptors are needed but \
+                                                           the limit is {}, {}",
+                                                          needed,
+                                                          soft,
+                                                          raise)));
+            } else if is_tight(needed, soft) {
+                problems.push(Problem::Tight(format!("{} file descriptors are needed of a \
+                                                      limit of {}",
+                                                     needed,
+                                                     soft)));
+            }
+        }
+
+        // the stats are resident from the start, the connection buffers only as they fill
+        if let Some(physical) = limits.memory {
+            let needed = self.memory();
+            if self.stats_memory > physical {
+                problems.push(Problem::Impossible(format!("{} MB of memory is needed for the \
+                                                           stats but the host has {} MB",
+                                                          self.stats_memory / MEGABYTE,
+                                                          physical / MEGABYTE)));
+            } else if needed > physical {
+                problems.push(Problem::Tight(format!("{} MB of memory is needed if the \
+                                                      connection buffers fill but the host \
+                                                      has {} MB",
+                                                     needed / MEGABYTE,
+                                                     physical / MEGABYTE)));
+            } else if is_tight(needed, physical) {
+                problems.push(Problem::Tight(format!("{} MB of memory is needed of the host's \
+                                                      {} MB",
+                                                     needed / MEGABYTE,
+                                                     physical / MEGABYTE)));
+            }
+        }
+
+        // more threads than cores only slows the run, it doesn't stop it
+        if let Some(cores) = limits.cores {
+            let busy = self.client_threads + self.generator_threads;
+            if busy > cores {
+                problems.push(Problem::Tight(format!("{} client and workload threads share {} \
+                                                      cores",
+                                                     busy,
+                                                     cores)));
+            }
+        }
+
+        problems
+    }
+}
+
+fn is_tight(needed: usize, limit: usize) -> bool {
+    needed as f64 > limit as f64 * TIGHT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIGABYTE: usize = 1024 * MEGABYTE;
+
+    fn budget(connections: usize) -> Budget {
+        Budget {
+            connections: connections,
+            client_threads: 2,
+            generator_threads: 1,
+            listeners: 0,
+            outputs: 1,
+            connection_memory: 8 * MEGABYTE,
+            stats_memory: 100 * MEGABYTE,
+        }
+    }
+
+    fn limits() -> Limits {
+        Limits {
+            files: Some((1024, 4096)),
+            memory: Some(16 * GIGABYTE),
+            cores: Some(4),
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        let b = budget(100);
+        assert_eq!(b.file_descriptors(), 3 + 100 + 2 * 3 + 1);
+        assert_eq!(b.memory(), 900 * MEGABYTE);
+        assert_eq!(b.threads(), 4);
+    }
+
+    #[test]
+    fn fits() {
+        assert_eq!(budget(100).check(&limits()), Vec::new());
+        assert_eq!(budget(1_000_000).check(&Limits::default()), Vec::new());
+    }
+
+    #[test]
+    fn file_descriptors() {
+        match budget(1500).check(&limits()).first() {
+            Some(&Problem::Impossible(ref m)) => assert!(m.contains("ulimit -n 1510")),
+            p => panic!("unexpected: {:?}", p),
+        }
+        match budget(5000).check(&limits()).first() {
+            Some(&Problem::Impossible(ref m)) => assert!(m.contains("hard limit is 4096")),
+            p => panic!("unexpected: {:?}", p),
+        }
+        match budget(900).check(&limits()).first() {
+            Some(&Problem::Tight(_)) => {}
+            p => panic!("unexpected: {:?}", p),
+        }
+    }
+
+    #[test]
+    fn memory() {
+        let l = Limits { files: None, ..limits() };
+        // connection buffers which may never fill don't stop the run
+        match budget(2100).check(&l).first() {
+            Some(&Problem::Tight(ref m)) => assert!(m.contains("if the connection buffers fill")),
+            p => panic!("unexpected: {:?}", p),
+        }
+        let mut b = budget(10);
+        b.stats_memory = 17 * GIGABYTE;
+        match b.check(&l).first() {
+            Some(&Problem::Impossible(ref m)) => assert!(m.contains("for the stats")),
+            p => panic!("unexpected: {:?}", p),
+        }
+        match budget(1700).check(&l).first() {
+            Some(&Problem::Tight(_)) => {}
+            p => panic!("unexpected: {:?}", p),
+        }
+    }
+
+    #[test]
+    fn threads() {
+        let mut b = budget(10);
+        b.client_threads = 8;
+        assert_eq!(b.check(&limits()),
+                   vec![Problem::Tight("9 client and workload threads share 4 cores".to_owned())]);
+    }
+}
@@ -0,0 +1,340 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+extern crate time;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// Bytes of a capture without `--debug-capture-bytes`
+pub const DEFAULT_BYTES: usize = 1024 * 1024;
+/// Time a replay waits for more bytes after the last sent, in milliseconds
+pub const IDLE_WAIT: u64 = 1_000;
+
+const MAGIC: &'static [u8] = b"rpc-perf capture 1\n";
+// direction, offset and length
+const HEADER: usize = 1 + 8 + 4;
+const ONE_MILLISECOND: u64 = 1_000_000;
+
+/// Which way bytes crossed the connection
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn marker(&self) -> u8 {
+        match *self {
+            Direction::Sent => b'>',
+            Direction::Received => b'<',
+        }
+    }
+}
+
+/// Bytes sent or received in one call on the socket
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub direction: Direction,
+    /// nanoseconds since the capture started
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl Chunk {
+    /// The chunk as it's stored: direction, big-endian offset and length, then the bytes
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER + self.bytes.len());
+        encoded.push(self.direction.marker());
+        for i in (0..8).rev() {
+            encoded.push((self.offset >> (i * 8)) as u8);
+        }
+        for i in (0..4).rev() {
+            encoded.push((self.bytes.len() >> (i * 8)) as u8);
+        }
+        encoded.extend_from_slice(&self.bytes);
+        encoded
+    }
+
+    /// One line description, with the bytes escaped
+    pub fn describe(&self) -> String {
+        let escaped: Vec<u8> = self.bytes
+                                   .iter()
+                                   .flat_map(|b| ::std::ascii::escape_default(*b))
+                                   .collect();
+        format!("{} +{:.3} ms {} bytes: \"{}\"",
+                self.direction.marker() as char,
+                self.offset as f64 / ONE_MILLISECOND as f64,
+                self.bytes.len(),
+                String::from_utf8_lossy(&escaped))
+    }
+}
+
+/// Which connection to capture, and how much of it
+#[derive(Clone, Debug)]
+pub struct Target {
+    /// counted across the connections of every client thread and server
+    pub connection: usize,
+    pub bytes: usize,
+    pub path: String,
+}
+
+/// The stream of a single connection, written to a file as it's captured
+pub struct Capture {
+    writer: BufWriter<File>,
+    path: String,
+    start: u64,
+    // bytes which may still be captured
+    remaining: usize,
+}
+
+impl Capture {
+    pub fn create(target: &Target) -> Result<Capture, String> {
+        let file = match File::create(&target.path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening capture {}: {}", target.path, e)),
+        };
+        let mut capture = Capture {
+            writer: BufWriter::new(file),
+            path: target.path.clone(),
+            start: time::precise_time_ns(),
+            remaining: target.bytes,
+        };
+        capture.write(MAGIC);
+        Ok(capture)
+    }
+
+    /// Record bytes sent or received at `now`, cut short at the capture's limit
+    pub fn record(&mut self, direction: Direction, now: u64, bytes: &[u8]) {
+        if self.remaining == 0 || bytes.is_empty() {
+            return;
+        }
+        let length = bytes.len().min(self.remaining);
+        self.remaining -= length;
+        let chunk = Chunk {
+            direction: direction,
+            offset: now.saturating_sub(self.start),
+            bytes: bytes[..length].to_vec(),
+        };
+        self.write(&chunk.encode());
+        if self.remaining == 0 {
+            info!("capture: limit reached, written to {}", self.path);
+        }
+    }
+
+    // flushed as it goes, a run may end with the process before the connection is dropped
+    fn write(&mut self, bytes: &[u8]) {
+        if let Err(e) = self.writer.write_all(bytes).and_then(|_| self.writer.flush()) {
+            debug!("capture: write failed: {}", e);
+        }
+    }
+}
+
+/// Load the chunks of a capture file
+pub fn load(path: &str) -> Result<Vec<Chunk>, String> {
+    let mut bytes = Vec::new();
+    match File::open(path) {
+        Ok(mut f) => {
+            if let Err(e) = f.read_to_end(&mut bytes) {
+                return Err(format!("Error reading capture {}: {}", path, e));
+            }
+        }
+        Err(e) => return Err(format!("Error opening capture {}: {}", path, e)),
+    }
+    parse(&bytes)
+}
+
+/// Split a capture into its chunks
+pub fn parse(bytes: &[u8]) -> Result<Vec<Chunk>, String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err("capture: not an rpc-perf capture".to_owned());
+    }
+    let mut chunks = Vec::new();
+    let mut offset = MAGIC.len();
+    while offset < bytes.len() {
+        if bytes.len() - offset < HEADER {
+            return Err(format!("capture: truncated header at byte {}", offset));
+        }
+        let direction = match bytes[offset] {
+            b'>' => Direction::Sent,
+            b'<' => Direction::Received,
+            b => return Err(format!("capture: bad direction {} at byte {}", b, offset)),
+        };
+        let at = bytes[(offset + 1)..(offset + 9)].iter().fold(0, |v, b| (v << 8) | *b as u64);
+        let length = bytes[(offset + 9)..(offset + HEADER)]
+                         .iter()
+                         .fold(0, |len, b| (len << 8) | *b as usize);
+        offset += HEADER;
+        if bytes.len() - offset < length {
+            return Err(format!("capture: chunk at byte {} needs {} bytes, {} remain",
+                               offset - HEADER,
+                               length,
+                               bytes.len() - offset));
+        }
+        chunks.push(Chunk {
+            direction: direction,
+            offset: at,
+            bytes: bytes[offset..(offset + length)].to_vec(),
+        });
+        offset += length;
+    }
+    Ok(chunks)
+}
+
+/// The bytes of every chunk in one direction, in order
+pub fn stream(chunks: &[Chunk], direction: Direction) -> Vec<u8> {
+    chunks.iter()
+          .filter(|c| c.direction == direction)
+          .flat_map(|c| c.bytes.iter().cloned())
+          .collect()
+}
+
+/// Offset of the first byte where the streams differ, or where the shorter one ends
+pub fn difference(expected: &[u8], actual: &[u8]) -> Option<usize> {
+    match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(i) => Some(i),
+        None if expected.len() != actual.len() => Some(expected.len().min(actual.len())),
+        None => None,
+    }
+}
+
+/// Send the sent chunks of a capture with their original pacing, returning what came back
+///
+/// Bytes arriving between sends are read while waiting for the next, and after the last the
+/// replay waits until none have arrived for `IDLE_WAIT` or the server closes the connection.
+pub fn replay(stream: &mut TcpStream, chunks: &[Chunk]) -> io::Result<Vec<Chunk>> {
+    let start = time::precise_time_ns();
+    let mut received = Vec::new();
+    let mut open = true;
+    for chunk in chunks.iter().filter(|c| c.direction == Direction::Sent) {
+        while open {
+            let now = time::precise_time_ns();
+            if now >= start + chunk.offset {
+                break;
+            }
+            open = try!(receive(stream, start, start + chunk.offset - now, &mut received));
+        }
+        try!(stream.write_all(&chunk.bytes));
+    }
+    while open {
+        let before = received.len();
+        open = try!(receive(stream, start, IDLE_WAIT * ONE_MILLISECOND, &mut received));
+        if received.len() == before {
+            break;
+        }
+    }
+    Ok(received)
+}
+
+// read for up to `wait` ns, returning false once the server has closed the connection
+fn receive(stream: &mut TcpStream,
+           start: u64,
+           wait: u64,
+           received: &mut Vec<Chunk>)
+           -> io::Result<bool> {
+    let wait = wait.max(1);
+    try!(stream.set_read_timeout(Some(Duration::new(wait / 1_000_000_000,
+                                                    (wait % 1_000_000_000) as u32))));
+    let mut buf = [0; 16 * 1024];
+    match stream.read(&mut buf) {
+        Ok(0) => Ok(false),
+        Ok(n) => {
+            received.push(Chunk {
+                direction: Direction::Received,
+                offset: time::precise_time_ns() - start,
+                bytes: buf[..n].to_vec(),
+            });
+            Ok(true)
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                      e.kind() == io::ErrorKind::TimedOut => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn chunk(direction: Direction, offset: u64, bytes: &[u8]) -> Chunk {
+        Chunk {
+            direction: direction,
+            offset: offset,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    #[test]
+    fn encode_and_parse() {
+        let chunks = vec![chunk(Direction::Sent, 0, b"get a\r\n"),
+                          chunk(Direction::Received, 1_234_567, b"END\r\n"),
+                          chunk(Direction::Sent, 1 << 40, b"")];
+        let mut bytes = MAGIC.to_vec();
+        for c in &chunks {
+            bytes.extend(c.encode());
+        }
+        assert_eq!(parse(&bytes), Ok(chunks));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert!(parse(b"").is_err());
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(chunk(Direction::Sent, 0, b"get a\r\n").encode());
+        assert!(parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse(&bytes[..MAGIC.len() + 3]).is_err());
+        bytes[MAGIC.len()] = b'x';
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn capture_stops_at_limit() {
+        let path = env::temp_dir().join("rpc-perf-capture-test");
+        let target = Target {
+            connection: 0,
+            bytes: 10,
+            path: path.to_string_lossy().into_owned(),
+        };
+        {
+            let mut capture = Capture::create(&target).unwrap();
+            let now = time::precise_time_ns();
+            capture.record(Direction::Sent, now, b"get a\r\n");
+            capture.record(Direction::Received, now, b"END\r\n");
+            capture.record(Direction::Sent, now, b"get b\r\n");
+        }
+        let chunks = load(&target.path).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(stream(&chunks, Direction::Sent), b"get a\r\n".to_vec());
+        assert_eq!(stream(&chunks, Direction::Received), b"END".to_vec());
+        let _ = ::std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn first_difference() {
+        assert_eq!(difference(b"END\r\n", b"END\r\n"), None);
+        assert_eq!(difference(b"END\r\n", b"ERROR\r\n"), Some(1));
+        assert_eq!(difference(b"END\r\n", b"END"), Some(3));
+        assert_eq!(difference(b"", b"END"), Some(0));
+    }
+
+    #[test]
+    fn describe_escapes() {
+        assert_eq!(chunk(Direction::Received, 1_500_000, b"END\r\n").describe(),
+                   "< +1.500 ms 5 bytes: \"END\\r\\n\"");
+    }
+}
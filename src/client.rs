@@ -15,61 +15,304 @@
 
 extern crate mio;
 extern crate mpmc;
+extern crate time;
 
 use mio::util::Slab;
 use mpmc::Queue as BoundedQueue;
+use std::collections::VecDeque;
+use std::sync::mpsc;
 
 use connection::Connection;
+use interleave;
+use net::InternetProtocol;
+use request::Interleave;
+use signal;
 use state::State;
+use stats::{ConnectionSnapshot, TcpSample};
+use tcpinfo;
+use cfgtypes::WorkItem;
 
-const MAX_CONNECTIONS: usize = 1024;
+// connection slots allocated even when fewer connections are made
+const MIN_CONNECTIONS: usize = 1024;
+const SNAPSHOT_INTERVAL_MS: u64 = 1000;
+// requests taken from the queue looking for one the interleave policy allows on a connection
+const SCAN_LIMIT: usize = 64;
+// requests held back for other connections, beyond which they're dropped and counted
+const DEFERRED_LIMIT: usize = 1024;
+
+/// Periodic work scheduled on the event loop
+#[derive(Clone, Copy, Debug)]
+pub enum Tick {
+    TcpInfo,
+    Connections,
+    Timeouts,
+}
+
+/// Notifications sent to the event loop from other threads
+#[derive(Clone, Copy, Debug)]
+pub enum Message {
+    /// Stop the event loop once the current iteration completes
+    Shutdown,
+}
 
 pub struct Client {
     pub connections: Slab<Connection>,
-    work_rx: BoundedQueue<Vec<u8>>,
+    id: usize,
+    work_rx: BoundedQueue<WorkItem>,
+    tcp_tx: Option<mpsc::Sender<TcpSample>>,
+    tcp_interval: u64,
+    tcp_offset: usize,
+    connections_tx: Option<mpsc::Sender<ConnectionSnapshot>>,
+    timeout_interval: u64,
+    // number of connection slots, the tokens are below it
+    capacity: usize,
+    interleave: Interleave,
+    workloads: usize,
+    // requests taken from the queue which the connection that took them couldn't send
+    deferred: VecDeque<WorkItem>,
+    // used to replace connections closed to be reopened
+    internet_protocol: InternetProtocol,
 }
 
 impl Client {
-    pub fn new(work_rx: BoundedQueue<Vec<u8>>) -> Client {
-        let connections = Slab::new_starting_at(mio::Token(0), MAX_CONNECTIONS);
+    /// Create a client with room for at least `connections` connections
+    pub fn new(id: usize,
+               work_rx: BoundedQueue<WorkItem>,
+               connections: usize,
+               internet_protocol: InternetProtocol)
+               -> Client {
+        let capacity = connections.max(MIN_CONNECTIONS);
+        let connections = Slab::new_starting_at(mio::Token(0), capacity);
 
         Client {
             connections: connections,
+            id: id,
             work_rx: work_rx,
+            tcp_tx: None,
+            tcp_interval: 0,
+            tcp_offset: 0,
+            connections_tx: None,
+            timeout_interval: 0,
+            capacity: capacity,
+            interleave: Interleave::Free,
+            workloads: 0,
+            deferred: VecDeque::new(),
+            internet_protocol: internet_protocol,
         }
     }
-}
 
-impl mio::Handler for Client {
-    type Timeout = (); // timeouts not used
-    type Message = (); // cross-thread notifications not used
+    /// Order the requests of the `workloads` on each connection by the policy
+    pub fn interleave(&mut self, policy: Interleave, workloads: usize) {
+        self.interleave = policy;
+        self.workloads = workloads;
+    }
 
-    fn ready(&mut self,
-             event_loop: &mut mio::EventLoop<Client>,
-             token: mio::Token,
-             events: mio::EventSet) {
-        trace!("socket ready: token={:?} events={:?}", token, events);
+    /// Sample TCP_INFO from connections every interval, sending results to the stats receiver
+    pub fn sample_tcp_info(&mut self,
+                           event_loop: &mut mio::EventLoop<Client>,
+                           tcp_tx: mpsc::Sender<TcpSample>,
+                           interval_ms: u64) {
+        self.tcp_tx = Some(tcp_tx);
+        self.tcp_interval = interval_ms;
+        let _ = event_loop.timeout_ms(Tick::TcpInfo, interval_ms);
+    }
+
+    /// Periodically send a description of every connection to the stats receiver
+    pub fn report_connections(&mut self,
+                              event_loop: &mut mio::EventLoop<Client>,
+                              connections_tx: mpsc::Sender<ConnectionSnapshot>) {
+        self.connections_tx = Some(connections_tx);
+        self.snapshot();
+        let _ = event_loop.timeout_ms(Tick::Connections, SNAPSHOT_INTERVAL_MS);
+    }
+
+    /// Check for requests past their deadline every interval
+    pub fn expire_requests(&mut self, event_loop: &mut mio::EventLoop<Client>, interval_ms: u64) {
+        self.timeout_interval = interval_ms;
+        let _ = event_loop.timeout_ms(Tick::Timeouts, interval_ms);
+    }
+
+    fn expire(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        let now = time::precise_time_ns();
+        for token in (0..self.capacity).map(mio::Token) {
+            // a udp connection with requests forgotten has room to send more, a tcp connection
+            // with requests which timed out is replaced
+            if self.connections.contains(token) && self.connections[token].expire(now) {
+                let closed = match self.connections[token].state {
+                    State::Closed => true,
+                    _ => false,
+                };
+                if !closed {
+                    self.send(token);
+                }
+                self.update(event_loop, token);
+            }
+        }
+    }
+
+    fn snapshot(&self) {
+        if let Some(ref connections_tx) = self.connections_tx {
+            let connections = (0..self.capacity)
+                                  .map(mio::Token)
+                                  .filter(|t| self.connections.contains(*t))
+                                  .map(|t| self.connections[t].info())
+                                  .collect();
+            let _ = connections_tx.send(ConnectionSnapshot {
+                client: self.id,
+                connections: connections,
+            });
+        }
+    }
+
+    // sample a bounded number of connections, resuming where the last interval stopped so
+    // every connection is eventually covered
+    fn tcp_info(&mut self) {
+        if let Some(ref tcp_tx) = self.tcp_tx {
+            let start = self.tcp_offset;
+            let mut next = start;
+            let mut sampled = 0;
+            for i in 0..self.capacity {
+                if sampled == tcpinfo::MAX_SAMPLES {
+                    break;
+                }
+                let token = mio::Token((start + i) % self.capacity);
+                if !self.connections.contains(token) {
+                    continue;
+                }
+                if let Some(sample) = self.connections[token].tcp_info() {
+                    let _ = tcp_tx.send(sample);
+                }
+                sampled += 1;
+                next = token.as_usize() + 1;
+            }
+            self.tcp_offset = next;
+        }
+    }
 
+    // drop a closed connection, or replace it if it was closed to be, or wait for the events it
+    // now needs
+    fn update(&mut self, event_loop: &mut mio::EventLoop<Client>, token: mio::Token) {
         match self.connections[token].state {
             State::Closed => {
-                let _ = self.connections.remove(token);
+                if self.connections[token].reopens() &&
+                   self.connections[token].reconnect(self.internet_protocol) {
+                    self.connections[token].register(event_loop);
+                } else {
+                    let _ = self.connections.remove(token);
+                }
             }
-            State::Reading => {
-                self.connections[token].ready(event_loop, events, None);
+            _ => {
                 self.connections[token].reregister(event_loop);
             }
-            State::Writing => {
-                match self.work_rx.pop() {
-                    Some(work) => {
-                        trace!("sending: {:?}", work);
-                        self.connections[token].ready(event_loop, events, Some(work));
-                    }
-                    None => {
-                        trace!("work queue depleted: token: {:?}", token);
-                        self.connections[token].reregister(event_loop)
-                    }
+        }
+    }
+
+    // write as many requests as the connection will accept, none once interrupted. the rest of
+    // a sequence is written before any other request
+    fn send(&mut self, token: mio::Token) {
+        if self.connections[token].is_flushing() {
+            self.connections[token].flush();
+        }
+        while self.connections[token].is_writable() && !signal::stopping() {
+            let work = match self.connections[token].next_op() {
+                Some(work) => Some(work),
+                None => self.next_work(token),
+            };
+            match work {
+                Some(work) => {
+                    trace!("sending: {:?}", work);
+                    self.connections[token].write(work);
+                }
+                None => {
+                    trace!("work queue depleted: token: {:?}", token);
+                    break;
                 }
             }
         }
     }
+
+    // the next request the interleave policy allows on the connection. those it doesn't are
+    // held back for the client's other connections, oldest first, and the connection waits if
+    // none is found
+    fn next_work(&mut self, token: mio::Token) -> Option<WorkItem> {
+        if self.interleave == Interleave::Free {
+            return self.work_rx.pop();
+        }
+        let (policy, workloads) = (self.interleave, self.workloads);
+        let run = self.connections[token].run();
+        if let Some(i) = self.deferred
+                             .iter()
+                             .position(|w| run.allows(policy, workloads, w.workload)) {
+            return self.deferred.remove(i);
+        }
+        for _ in 0..SCAN_LIMIT {
+            let work = match self.work_rx.pop() {
+                Some(work) => work,
+                None => return None,
+            };
+            if run.allows(policy, workloads, work.workload) {
+                return Some(work);
+            }
+            if self.deferred.len() < DEFERRED_LIMIT {
+                self.deferred.push_back(work);
+            } else {
+                debug!("too many requests held back by the interleave policy, dropping one");
+                interleave::drop_request();
+            }
+        }
+        None
+    }
+}
+
+impl mio::Handler for Client {
+    type Timeout = Tick;
+    type Message = Message;
+
+    fn notify(&mut self, event_loop: &mut mio::EventLoop<Client>, msg: Message) {
+        match msg {
+            Message::Shutdown => {
+                debug!("client {}: shutting down", self.id);
+                event_loop.shutdown();
+            }
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut mio::EventLoop<Client>, tick: Tick) {
+        match tick {
+            Tick::TcpInfo => {
+                self.tcp_info();
+                let _ = event_loop.timeout_ms(tick, self.tcp_interval);
+            }
+            Tick::Connections => {
+                self.snapshot();
+                let _ = event_loop.timeout_ms(tick, SNAPSHOT_INTERVAL_MS);
+            }
+            Tick::Timeouts => {
+                self.expire(event_loop);
+                let _ = event_loop.timeout_ms(tick, self.timeout_interval);
+            }
+        }
+    }
+
+    fn ready(&mut self,
+             event_loop: &mut mio::EventLoop<Client>,
+             token: mio::Token,
+             events: mio::EventSet) {
+        trace!("socket ready: token={:?} events={:?}", token, events);
+
+        if events.is_readable() {
+            self.connections[token].read();
+        }
+
+        // a failed connect is also reported as writable
+        if events.is_writable() && !events.is_error() && !events.is_hup() {
+            self.connections[token].connected();
+        }
+
+        if events.is_writable() {
+            self.send(token);
+        }
+
+        self.update(event_loop, token);
+    }
 }
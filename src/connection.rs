@@ -14,225 +14,903 @@
 //  limitations under the License.
 
 extern crate mio;
+extern crate mpmc;
 extern crate time;
 
 use bytes::{Buf, ByteBuf, MutByteBuf};
 use mio::{TryRead, TryWrite};
 use mio::tcp::TcpStream;
+use mio::udp::UdpSocket;
+use mpmc::Queue as BoundedQueue;
+use std::collections::VecDeque;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::sync::mpsc;
 
+use capture::{Capture, Direction};
 use client::Client;
+use diagnose::Diagnosis;
+use establish::Progress;
+use experiment::{self, SocketOption};
+use interleave::Run;
+use net::{self, InternetProtocol};
 use state::State;
-use stats::{Stat, Status};
-use cfgtypes::{ParsedResponse, ProtocolParse};
+use stats::{ConnectionInfo, Stat, Status, TcpSample};
+use tcpinfo;
+use udp::{self, Reassembly};
+use cfgtypes::{ParsedResponse, ProtocolParse, WorkItem, tools};
 
 
 const MEGABYTE: usize = 1024 * 1024;
+// capacity of each of the send and receive buffers
+const BUFFER_SIZE: usize = 4 * MEGABYTE;
+
+// a request written to the connection and awaiting its response
+struct Pending {
+    // when the first attempt was sent
+    start: u64,
+    validate: Option<Vec<u8>>,
+    // attempts made, including this one
+    attempts: usize,
+    deadline: Option<u64>,
+    // request kept for resending, only while retries remain
+    bytes: Option<Vec<u8>>,
+    // timed out, its late response is discarded
+    expired: bool,
+    workload: Option<usize>,
+    // length of the run of its workload it ended when written
+    run: usize,
+    // position in its sequence, for the ops of a sequence workload
+    op: Option<usize>,
+    // the rest of its sequence waits for the response
+    serial: bool,
+    // when the first request of its sequence was sent, if it's the last
+    sequence: Option<u64>,
+    // matches a udp response to its request, which may be answered in any order
+    id: u16,
+    datagrams: Reassembly,
+}
+
+/// The transport requests are sent over
+pub enum Socket {
+    Tcp(TcpStream),
+    /// an unconnected datagram socket and the server it sends to
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// How long to wait for a response, and how often to resend a request which timed out
+#[derive(Clone)]
+pub struct Timeout {
+    /// nanoseconds
+    pub duration: u64,
+    pub retries: usize,
+    /// requests to be resent, paced by the workloads
+    pub retry_queue: Option<BoundedQueue<WorkItem>>,
+}
 
 pub struct Connection {
-    pub socket: TcpStream,
+    socket: Socket,
     pub token: mio::Token,
     pub state: State,
     buf: Option<ByteBuf>,
     mut_buf: Option<MutByteBuf>,
-    last_write: u64,
+    rx_buf: Option<MutByteBuf>,
+    pipeline: usize,
+    sent: VecDeque<Pending>,
+    retransmits: u32,
+    server: String,
+    server_id: usize,
+    tcp_nodelay: bool,
+    established: time::Timespec,
+    last_activity: u64,
+    requests: u64,
+    // id of the next udp request
+    next_id: u16,
+    // workloads of the requests most recently written
+    run: Run,
+    // requests of the current sequence yet to be written, no others are written until they are
+    sequence: VecDeque<WorkItem>,
+    // when the first request of the current sequence was sent
+    sequence_start: u64,
+    // the next request of the sequence waits for the response to the last one written
+    awaiting: bool,
+    // response to the last request of the sequence, for the next to follow
+    previous: Option<Vec<u8>>,
+    // closed to be replaced by a new connection to the server, rather than dropped
+    reopen: bool,
+    // arm of the socket option experiment, if there is one
+    arm: Option<usize>,
+    // records the bytes sent and received, for the one connection being captured
+    capture: Option<Capture>,
+    // counts the connection towards the connection ramp, and as lost once it closes for good
+    progress: Option<Arc<Progress>>,
+    // not yet counted as established or failed
+    connecting: bool,
+    timeout: Option<Timeout>,
+    // until the first response arrives
+    diagnosis: Option<Arc<Diagnosis>>,
     stats_tx: mpsc::Sender<Stat>,
     protocol: Box<ProtocolParse>,
 }
 
 impl Connection {
-    pub fn new(socket: TcpStream,
+    pub fn new(socket: Socket,
                token: mio::Token,
+               server: String,
+               server_id: usize,
                stats_tx: mpsc::Sender<Stat>,
                protocol: Box<ProtocolParse>,
-               tcp_nodelay: bool)
+               tcp_nodelay: bool,
+               pipeline: usize,
+               timeout: Option<Timeout>,
+               diagnosis: Arc<Diagnosis>)
                -> Connection {
 
-        let _ = socket.set_nodelay(tcp_nodelay);
+        if let Socket::Tcp(ref s) = socket {
+            let _ = s.set_nodelay(tcp_nodelay);
+        }
 
         Connection {
             socket: socket,
             token: token,
             state: State::Writing,
-            buf: Some(ByteBuf::none()),
-            mut_buf: Some(ByteBuf::mut_with_capacity(4 * MEGABYTE)),
-            last_write: time::precise_time_ns(),
+            buf: None,
+            mut_buf: Some(ByteBuf::mut_with_capacity(BUFFER_SIZE)),
+            rx_buf: Some(ByteBuf::mut_with_capacity(BUFFER_SIZE)),
+            pipeline: pipeline,
+            sent: VecDeque::with_capacity(pipeline),
+            retransmits: 0,
+            server: server,
+            server_id: server_id,
+            tcp_nodelay: tcp_nodelay,
+            established: time::get_time(),
+            last_activity: time::precise_time_ns(),
+            requests: 0,
+            next_id: 0,
+            run: Run::default(),
+            sequence: VecDeque::new(),
+            sequence_start: 0,
+            awaiting: false,
+            previous: None,
+            reopen: false,
+            arm: None,
+            capture: None,
+            progress: None,
+            connecting: false,
+            timeout: timeout,
+            diagnosis: Some(diagnosis),
             stats_tx: stats_tx,
             protocol: protocol,
         }
     }
 
-    pub fn ready(&mut self,
-                 event_loop: &mut mio::EventLoop<Client>,
-                 events: mio::EventSet,
-                 work: Option<Vec<u8>>) {
+    /// Whether another request may be written to the connection
+    pub fn is_writable(&self) -> bool {
+        match self.state {
+            State::Closed => false,
+            _ => self.buf.is_none() && self.sent.len() < self.pipeline && !self.awaiting,
+        }
+    }
 
-        trace!("    connection-state={:?}", self.state);
+    /// The next request of the sequence being written, completed from the response before it
+    /// if it follows that
+    pub fn next_op(&mut self) -> Option<WorkItem> {
+        let mut work = match self.sequence.pop_front() {
+            Some(w) => w,
+            None => return None,
+        };
+        if work.follows {
+            let previous = self.previous.take().unwrap_or_default();
+            work.bytes = self.protocol.follow(&work.bytes, &previous);
+        }
+        Some(work)
+    }
 
-        match self.state {
-            State::Reading => {
-                assert!(events.is_readable(),
-                        "unexpected events; events={:?}",
-                        events);
-                let now = time::precise_time_ns();
-                let response = self.read(event_loop);
-                match response {
-                    ParsedResponse::Hit => {
-                        let _ = self.stats_tx.send(Stat {
-                            start: self.last_write,
-                            stop: now,
-                            status: Status::Hit,
-                        });
-                    }
-                    ParsedResponse::Ok => {
-                        let _ = self.stats_tx.send(Stat {
-                            start: self.last_write,
-                            stop: now,
-                            status: Status::Ok,
-                        });
-                    }
-                    ParsedResponse::Miss => {
-                        let _ = self.stats_tx.send(Stat {
-                            start: self.last_write,
-                            stop: now,
-                            status: Status::Miss,
-                        });
-                    }
-                    ParsedResponse::Incomplete => {}
-                    ParsedResponse::Unknown => {
-                        let _ = self.stats_tx.send(Stat {
-                            start: self.last_write,
-                            stop: now,
-                            status: Status::Closed,
-                        });
-                    }
-                    _ => {
-                        let _ = self.stats_tx.send(Stat {
-                            start: self.last_write,
-                            stop: now,
-                            status: Status::Error,
-                        });
-                        debug!("unexpected response: {:?}", response);
-                    }
-                }
+    /// Workloads of the requests most recently written, which the interleave policy follows
+    pub fn run(&self) -> Run {
+        self.run
+    }
+
+    /// Whether a previous request is still partially written
+    pub fn is_flushing(&self) -> bool {
+        self.buf.is_some()
+    }
+
+    /// Sample kernel TCP statistics, with retransmits counted since the previous sample
+    pub fn tcp_info(&mut self) -> Option<TcpSample> {
+        let sample = match self.socket {
+            Socket::Tcp(ref s) => tcpinfo::sample(s),
+            Socket::Udp(..) => None,
+        };
+        match sample {
+            Some(info) => {
+                let retransmits = info.total_retrans.saturating_sub(self.retransmits);
+                self.retransmits = info.total_retrans;
+                Some(TcpSample {
+                    rtt: info.rtt,
+                    retransmits: retransmits,
+                })
             }
-            State::Writing => {
-                assert!(events.is_writable(),
-                        "unexpected events; events={:?}",
-                        events);
-                if let Some(w) = work {
-                    let mut buf = match self.mut_buf.take() {
-                        Some(b) => b,
-                        None => {
-                            panic!("no mut_buf to take");
-                        }
-                    };
-                    buf.clear();
-                    buf.write_slice(&*w);
-                    self.buf = Some(buf.flip());
-                    self.write(event_loop)
-                } else {
-                    panic!("no work");
-                }
+            None => None,
+        }
+    }
+
+    /// Put the connection in an arm of the socket option experiment, setting the option to match
+    pub fn join_arm(&mut self, option: SocketOption, arm: usize) {
+        let enabled = experiment::enabled(arm);
+        if let Err(e) = option.apply(&self.socket, enabled) {
+            debug!("failed to set {}: {}", option.name(), e);
+        }
+        match option {
+            SocketOption::NoDelay => self.tcp_nodelay = enabled,
+        }
+        self.arm = Some(arm);
+    }
+
+    /// Record the bytes sent and received from now on
+    pub fn capture(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+
+    /// Count the connection as established once it connects, a datagram socket at once
+    pub fn track(&mut self, progress: Arc<Progress>) {
+        match self.socket {
+            Socket::Tcp(_) => self.connecting = true,
+            Socket::Udp(..) => progress.establish(),
+        }
+        self.progress = Some(progress);
+    }
+
+    /// The socket has connected
+    pub fn connected(&mut self) {
+        if !self.connecting {
+            return;
+        }
+        self.connecting = false;
+        if let Some(ref progress) = self.progress {
+            progress.establish();
+        }
+    }
+
+    /// Describe the connection for introspection
+    pub fn info(&self) -> ConnectionInfo {
+        // activity is timed with the monotonic clock, convert it to wall-clock time
+        let idle = time::precise_time_ns().saturating_sub(self.last_activity);
+        ConnectionInfo {
+            token: self.token.as_usize(),
+            server: self.server.clone(),
+            local: self.local_addr().map(|a| format!("{}", a)),
+            established: self.established,
+            last_activity: time::get_time() - time::Duration::nanoseconds(idle as i64),
+            requests: self.requests,
+            in_flight: self.sent.len(),
+            tcp_nodelay: self.tcp_nodelay,
+            pipeline: self.pipeline,
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match self.socket {
+            Socket::Tcp(ref s) => s.local_addr().ok(),
+            Socket::Udp(ref s, _) => s.local_addr().ok(),
+        }
+    }
+
+    /// Whether the connection was closed to be replaced
+    pub fn reopens(&self) -> bool {
+        self.reopen
+    }
+
+    /// Replace the closed socket with a new connection to the same server, returning false if
+    /// it couldn't be made
+    pub fn reconnect(&mut self, internet_protocol: InternetProtocol) -> bool {
+        self.reopen = false;
+        let stream = match net::to_mio_tcp_stream(self.server.as_str(), internet_protocol) {
+            Ok(s) => s,
+            Err(e) => {
+                debug!("reconnect error: {}", e);
+                self.lose();
+                return false;
             }
-            _ => unimplemented!(),
+        };
+        let _ = stream.set_nodelay(self.tcp_nodelay);
+        self.socket = Socket::Tcp(stream);
+        self.state = State::Writing;
+        // any partly read response went with the old socket
+        if let Some(ref mut buf) = self.rx_buf {
+            buf.clear();
         }
+        self.retransmits = 0;
+        self.established = time::get_time();
+        self.last_activity = time::precise_time_ns();
+        self.run = Run::default();
+        self.previous = None;
+        true
     }
 
-    pub fn read(&mut self, event_loop: &mut mio::EventLoop<Client>) -> ParsedResponse {
+    /// Register the socket for its first write
+    pub fn register(&self, event_loop: &mut mio::EventLoop<Client>) {
+        let events = mio::EventSet::writable();
+        let opts = mio::PollOpt::edge() | mio::PollOpt::oneshot();
+        match self.socket {
+            Socket::Tcp(ref s) => event_loop.register(s, self.token, events, opts),
+            Socket::Udp(ref s, _) => event_loop.register(s, self.token, events, opts),
+        }
+        .unwrap();
+    }
 
+    pub fn read(&mut self) {
         trace!("read()");
 
-        // response unknown until parsed
-        let mut resp = ParsedResponse::Unknown;
+        if let Socket::Udp(..) = self.socket {
+            self.read_datagrams();
+            return;
+        }
 
-        let mut buf = match self.mut_buf.take() {
+        let mut buf = match self.rx_buf.take() {
             Some(b) => b,
             None => {
-                panic!("read() no mut_buf");
+                panic!("read() no rx_buf");
             }
         };
 
-        match self.socket.try_read_buf(&mut buf) {
+        let read = match self.socket {
+            Socket::Tcp(ref mut s) => s.try_read_buf(&mut buf),
+            Socket::Udp(..) => Ok(None),
+        };
+
+        match read {
             Ok(Some(0)) => {
                 trace!("read() closed");
-                self.state = State::Closed;
+                let buf = buf.flip();
+                // the close may be what ends the last response
+                if self.record_closed(buf.bytes()) {
+                    self.close_and_reopen();
+                } else {
+                    self.close();
+                }
+                let mut buf = buf.flip();
+                buf.clear();
+                self.rx_buf = Some(buf);
             }
             Ok(Some(n)) => {
                 // read bytes from connection
                 trace!("read() bytes {}", n);
+                let now = time::precise_time_ns();
+                self.last_activity = now;
 
                 let buf = buf.flip();
 
-                // protocol dependant parsing
-                resp = self.protocol.parse(buf.bytes());
+                // the bytes just read follow any incomplete response kept from before
+                if let Some(ref mut capture) = self.capture {
+                    let bytes = buf.bytes();
+                    capture.record(Direction::Received, now, &bytes[(bytes.len() - n)..]);
+                }
+
+                // protocol dependant parsing of each complete response
+                let consumed = self.parse(buf.bytes(), now);
+                let remaining = buf.bytes().len() - consumed;
 
-                // if incomplete replace the buffer contents, otherwise transition
-                match resp {
-                    ParsedResponse::Incomplete => {
-                        trace!("read() Incomplete");
-                        self.mut_buf = Some(buf.resume());
-                    }
-                    _ => {
-                        trace!("read() Complete");
-                        self.state = State::Writing;
-                        self.mut_buf = Some(buf.flip());
-                    }
+                // a server speaking another protocol may send nothing which frames
+                if consumed == 0 &&
+                   self.diagnosis.as_ref().map_or(false, |d| d.is_foreign(buf.bytes())) {
+                    self.diagnose(true, buf.bytes());
                 }
 
-                self.reregister(event_loop);
+                // keep any incomplete response at the front of the buffer
+                self.rx_buf = Some(if consumed == 0 {
+                    trace!("read() Incomplete");
+                    buf.resume()
+                } else if remaining == 0 {
+                    trace!("read() Complete");
+                    let mut buf = buf.flip();
+                    buf.clear();
+                    buf
+                } else {
+                    trace!("read() Complete with {} bytes remaining", remaining);
+                    let rest = buf.bytes()[consumed..].to_vec();
+                    let mut buf = buf.flip();
+                    buf.clear();
+                    buf.write_slice(&rest);
+                    buf
+                });
             }
             Ok(None) => {
                 trace!("read() spurious wake-up");
-                self.mut_buf = Some(buf);
-                self.reregister(event_loop);
+                self.rx_buf = Some(buf);
             }
             Err(e) => {
                 debug!("server has terminated: {}", e);
-                self.state = State::Closed;
+                self.rx_buf = Some(buf);
+                self.close();
+            }
+        }
+    }
+
+    // receive every datagram waiting on the socket
+    fn read_datagrams(&mut self) {
+        let mut datagram = vec![0; udp::MAX_DATAGRAM];
+        loop {
+            let received = match self.socket {
+                Socket::Udp(ref s, ref server) => {
+                    match s.recv_from(&mut datagram) {
+                        Ok(Some((_, from))) if from != *server => {
+                            debug!("udp: discarding datagram from {}", from);
+                            continue;
+                        }
+                        other => other,
+                    }
+                }
+                Socket::Tcp(_) => return,
+            };
+            match received {
+                Ok(Some((n, _))) => {
+                    let now = time::precise_time_ns();
+                    self.last_activity = now;
+                    self.receive(&datagram[..n], now);
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    debug!("udp: receive failed: {}", e);
+                    self.close();
+                    return;
+                }
+            }
+        }
+    }
+
+    // add a datagram to the response to its request, recording the response once it is whole
+    fn receive(&mut self, datagram: &[u8], now: u64) {
+        let (header, payload) = match udp::parse(datagram) {
+            Some(d) => d,
+            None => {
+                debug!("udp: datagram of {} bytes is too short", datagram.len());
+                return;
+            }
+        };
+        // a request which timed out was forgotten, its late response is discarded
+        let position = match self.sent.iter().position(|p| p.id == header.id) {
+            Some(i) => i,
+            None => {
+                trace!("udp: discarding datagram for request {}", header.id);
+                return;
             }
+        };
+        let response = self.sent[position].datagrams.add(&header, payload);
+        if let Some(response) = response {
+            let pending = self.sent.remove(position).unwrap();
+            self.record(pending, now, &response);
         }
-        resp
     }
 
-    pub fn write(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+    // parse complete responses from the buffer in the order their requests were sent,
+    // returning the number of bytes consumed
+    fn parse(&mut self, bytes: &[u8], now: u64) -> usize {
+        let mut consumed = 0;
+
+        while consumed < bytes.len() {
+            if self.sent.is_empty() {
+                debug!("unexpected bytes with no requests outstanding: {}",
+                       bytes.len() - consumed);
+                return bytes.len();
+            }
+
+            let length = match self.protocol.frame(&bytes[consumed..]) {
+                Some(0) | None => break,
+                Some(n) => n,
+            };
+
+            let response = &bytes[consumed..(consumed + length)];
+            let pending = self.sent.pop_front().unwrap();
+            if pending.expired {
+                trace!("discarding response to a request which timed out");
+            } else {
+                self.record(pending, now, response);
+            }
+
+            consumed += length;
+
+            // any requests sent after the response won't be answered
+            if self.protocol.closes(response) {
+                debug!("server closes the connection after the response, reconnecting");
+                self.close_and_reopen();
+                return bytes.len();
+            }
+        }
+
+        consumed
+    }
+
+    // record a response ended by the server closing the connection, returning whether there
+    // was one
+    fn record_closed(&mut self, bytes: &[u8]) -> bool {
+        let length = match self.protocol.frame_closed(bytes) {
+            Some(n) if !self.sent.is_empty() => n,
+            _ => return false,
+        };
+        let pending = self.sent.pop_front().unwrap();
+        if !pending.expired {
+            self.record(pending, time::precise_time_ns(), &bytes[..length]);
+        }
+        true
+    }
+
+    fn record(&mut self, pending: Pending, stop: u64, bytes: &[u8]) {
+        let status = {
+            let validate = pending.validate.as_ref().map(|k| &k[..]);
+            match (pending.op, validate) {
+                // a read in a sequence is checked for the value its sequence stored
+                (Some(_), Some(derived)) => classify_sequence(&*self.protocol, bytes, derived),
+                _ => classify(&*self.protocol, bytes, validate),
+            }
+        };
+        if pending.serial {
+            self.awaiting = false;
+            if self.sequence.front().map_or(false, |w| w.follows) {
+                self.previous = Some(bytes.to_vec());
+            }
+        }
+        let failed = match status {
+            Status::Error | Status::Closed => true,
+            _ => false,
+        };
+        self.diagnose(failed, bytes);
+        let _ = self.stats_tx.send(Stat {
+            start: pending.start,
+            stop: stop,
+            size: match status {
+                Status::Hit | Status::Ok | Status::Miss | Status::Stale => Some(bytes.len()),
+                _ => None,
+            },
+            status: status,
+            attempts: pending.attempts,
+            server: self.server_id,
+            workload: pending.workload,
+            run: pending.run,
+            op: pending.op,
+            sequence: pending.sequence.map(|start| stop.saturating_sub(start)),
+            arm: self.arm,
+        });
+    }
+
+    // only the first response on the connection is diagnosed
+    fn diagnose(&mut self, failed: bool, bytes: &[u8]) {
+        if let Some(diagnosis) = self.diagnosis.take() {
+            diagnosis.first_response(failed, bytes);
+        }
+    }
+
+    // close the connection for good
+    fn close(&mut self) {
+        self.drop_requests();
+        self.lose();
+    }
+
+    // count the connection as lost, once
+    fn lose(&mut self) {
+        if let Some(progress) = self.progress.take() {
+            progress.lose();
+        }
+    }
+
+    // close the connection to be replaced by a new one to the server
+    fn close_and_reopen(&mut self) {
+        self.drop_requests();
+        self.reopen = true;
+    }
+
+    // each outstanding request which hasn't timed out is reported as closed with the connection
+    fn drop_requests(&mut self) {
+        let now = time::precise_time_ns();
+        for pending in self.sent.drain(..).filter(|p| !p.expired) {
+            let _ = self.stats_tx.send(Stat {
+                start: pending.start,
+                stop: now,
+                status: Status::Closed,
+                size: None,
+                attempts: pending.attempts,
+                server: self.server_id,
+                workload: pending.workload,
+                run: pending.run,
+                op: pending.op,
+                sequence: None,
+                arm: self.arm,
+            });
+        }
+        self.sequence.clear();
+        self.awaiting = false;
+        // a partly written request isn't flushed to the closed socket
+        if let Some(buf) = self.buf.take() {
+            let mut buf = buf.flip();
+            buf.clear();
+            self.mut_buf = Some(buf);
+        }
+        if self.connecting {
+            self.connecting = false;
+            if let Some(ref progress) = self.progress {
+                progress.fail();
+            }
+        }
+        self.state = State::Closed;
+    }
+
+    /// Time out requests past their deadline, resending them while retries remain
+    ///
+    /// Over TCP responses are matched to requests in order, so a request which timed out would
+    /// hold its place until a late response arrived, which may be never. The connection is
+    /// closed to be replaced instead. Over UDP the response may never arrive either, so the
+    /// request is forgotten to make room for another. True is returned if either happened.
+    ///
+    /// The rest of a sequence waiting for a response which timed out is abandoned, and the
+    /// requests of a sequence aren't retried.
+    pub fn expire(&mut self, now: u64) -> bool {
+        let (expired, abandoned) = {
+            let timeout = match self.timeout {
+                Some(ref t) => t,
+                None => return false,
+            };
+            let mut expired = false;
+            let mut abandoned = false;
+            for pending in self.sent.iter_mut() {
+                match pending.deadline {
+                    Some(deadline) if !pending.expired && deadline <= now => {}
+                    _ => continue,
+                }
+                pending.expired = true;
+                expired = true;
+                abandoned = abandoned || pending.serial;
+                // a late response no longer releases the rest of the sequence
+                pending.serial = false;
+
+                // the request is only kept while retries remain
+                if let Some(ref queue) = timeout.retry_queue {
+                    if let Some(bytes) = pending.bytes.take() {
+                        let retry = WorkItem {
+                            bytes: bytes,
+                            validate: pending.validate.take(),
+                            attempts: pending.attempts,
+                            started: Some(pending.start),
+                            workload: pending.workload,
+                            op: None,
+                            then: Vec::new(),
+                            serial: false,
+                            follows: false,
+                        };
+                        match queue.push(retry) {
+                            Ok(_) => {
+                                trace!("retrying request after {} attempts", pending.attempts);
+                                continue;
+                            }
+                            Err(_) => debug!("retry queue full, giving up on request"),
+                        }
+                    }
+                }
+
+                let _ = self.stats_tx.send(Stat {
+                    start: pending.start,
+                    stop: now,
+                    status: Status::Timeout,
+                    size: None,
+                    attempts: pending.attempts,
+                    server: self.server_id,
+                    workload: pending.workload,
+                    run: pending.run,
+                    op: pending.op,
+                    sequence: None,
+                    arm: self.arm,
+                });
+            }
+            (expired, abandoned)
+        };
+        if abandoned {
+            debug!("abandoning {} requests of a sequence", self.sequence.len());
+            self.sequence.clear();
+            self.previous = None;
+            self.awaiting = false;
+        }
+        if !expired {
+            return false;
+        }
+        match self.socket {
+            Socket::Tcp(_) => {
+                debug!("reconnecting after requests timed out");
+                self.close_and_reopen();
+            }
+            Socket::Udp(..) => self.sent.retain(|p| !p.expired),
+        }
+        true
+    }
+
+    pub fn write(&mut self, mut work: WorkItem) {
         trace!("write()");
-        self.state = State::Writing;
-        self.last_write = time::precise_time_ns(); // mark time of write
-        let mut buf = self.buf.take().unwrap();
-        match self.socket.try_write_buf(&mut buf) {
-            Ok(Some(_)) => {
+        let mut buf = match self.mut_buf.take() {
+            Some(b) => b,
+            None => {
+                panic!("no mut_buf to take");
+            }
+        };
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        buf.clear();
+        match self.socket {
+            Socket::Tcp(_) => buf.write_slice(&*work.bytes),
+            Socket::Udp(..) => buf.write_slice(&udp::frame(id, &work.bytes)),
+        };
+        self.buf = Some(buf.flip());
+        let now = time::precise_time_ns();
+        // the first request of a sequence brings the rest with it
+        if work.op == Some(0) {
+            self.sequence.extend(mem::replace(&mut work.then, Vec::new()));
+            self.sequence_start = now;
+        }
+        let in_sequence = work.op.is_some();
+        self.awaiting = work.serial && !self.sequence.is_empty();
+        // mark time of write, a retry is timed from its first attempt
+        let mut pending = Pending {
+            start: work.started.unwrap_or(now),
+            validate: work.validate,
+            attempts: work.attempts + 1,
+            deadline: None,
+            bytes: None,
+            expired: false,
+            workload: work.workload,
+            run: self.run.push(work.workload),
+            op: work.op,
+            serial: self.awaiting,
+            sequence: if in_sequence && self.sequence.is_empty() {
+                Some(self.sequence_start)
+            } else {
+                None
+            },
+            id: id,
+            datagrams: Reassembly::default(),
+        };
+        if let Some(ref t) = self.timeout {
+            pending.deadline = Some(now + t.duration);
+            if pending.attempts <= t.retries && !in_sequence {
+                pending.bytes = Some(work.bytes);
+            }
+        }
+        self.sent.push_back(pending);
+        self.last_activity = now;
+        self.requests += 1;
+        self.flush();
+    }
+
+    /// Continue writing the current request
+    pub fn flush(&mut self) {
+        trace!("flush()");
+        let mut buf = match self.buf.take() {
+            Some(b) => b,
+            None => return,
+        };
+        // the buffer moves past the bytes as they're written
+        let unwritten = self.capture.as_ref().map(|_| buf.bytes().to_vec());
+        let written = match self.socket {
+            Socket::Tcp(ref mut s) => s.try_write_buf(&mut buf),
+            // a datagram is sent whole or not at all
+            Socket::Udp(ref s, ref server) => {
+                s.send_to(buf.bytes(), server).map(|sent| {
+                    sent.map(|n| {
+                        buf.advance(n);
+                        n
+                    })
+                })
+            }
+        };
+        match written {
+            Ok(Some(n)) => {
+                if let (Some(capture), Some(bytes)) = (self.capture.as_mut(), unwritten) {
+                    capture.record(Direction::Sent, time::precise_time_ns(), &bytes[..n]);
+                }
                 // successful write
-                if !buf.has_remaining() {
-                    self.state = State::Reading;
-                    trace!("switch to read()");
+                if buf.has_remaining() {
+                    self.buf = Some(buf);
+                    return;
                 }
-                self.reregister(event_loop);
+                trace!("write() complete");
             }
             Ok(None) => {
                 // socket wasn't ready
-                self.reregister(event_loop);
+                self.buf = Some(buf);
+                return;
             }
             Err(e) => {
                 // got some write error, abandon
                 debug!("got an error trying to write; err={:?}", e);
-                let _ = self.stats_tx.send(Stat {
-                    start: self.last_write,
-                    stop: time::precise_time_ns(),
-                    status: Status::Closed,
-                });
-                self.state = State::Closed
+                self.close();
             }
         }
         self.mut_buf = Some(buf.flip());
     }
 
-    pub fn reregister(&self, event_loop: &mut mio::EventLoop<Client>) {
-        event_loop.reregister(&self.socket,
-                              self.token,
-                              event_set(self.state.clone()),
-                              mio::PollOpt::edge())
-                  .unwrap();
+    pub fn reregister(&mut self, event_loop: &mut mio::EventLoop<Client>) {
+        self.state = match self.state {
+            State::Closed => State::Closed,
+            _ if self.buf.is_some() || (self.sent.len() < self.pipeline && !self.awaiting) => {
+                State::Writing
+            }
+            _ => State::Reading,
+        };
+
+        // responses may arrive while further requests are being written
+        let events = match self.state {
+            State::Writing if !self.sent.is_empty() => {
+                mio::EventSet::writable() | mio::EventSet::readable()
+            }
+            _ => event_set(self.state.clone()),
+        };
+
+        match self.socket {
+            Socket::Tcp(ref s) => {
+                event_loop.reregister(s, self.token, events, mio::PollOpt::edge())
+            }
+            Socket::Udp(ref s, _) => {
+                event_loop.reregister(s, self.token, events, mio::PollOpt::edge())
+            }
+        }
+        .unwrap();
+    }
+}
+
+/// Bytes of buffer each connection allocates
+pub fn buffer_memory() -> usize {
+    2 * BUFFER_SIZE
+}
+
+/// Result of a complete response, checking a hit against the key its value is derived from
+pub fn classify(protocol: &ProtocolParse, bytes: &[u8], validate: Option<&[u8]>) -> Status {
+    let response = protocol.parse(bytes);
+    match response {
+        ParsedResponse::Hit => {
+            match validate {
+                Some(key) if !protocol.validate(key, bytes) => {
+                    debug!("corrupt response: {:?}", String::from_utf8_lossy(bytes));
+                    Status::Corrupt
+                }
+                Some(key) if !protocol.validate_flags(key, bytes) => {
+                    debug!("flag mismatch: {:?}", String::from_utf8_lossy(bytes));
+                    Status::FlagMismatch
+                }
+                _ => Status::Hit,
+            }
+        }
+        ParsedResponse::Ok => Status::Ok,
+        ParsedResponse::Miss => Status::Miss,
+        ParsedResponse::Unknown => Status::Closed,
+        ParsedResponse::Status(code) if code / 100 == 4 => Status::ClientError,
+        ParsedResponse::Status(code) if code / 100 == 5 => Status::ServerError,
+        _ => {
+            debug!("unexpected response: {:?}", response);
+            Status::Error
+        }
+    }
+}
+
+// result of a complete response to a read in a sequence, a hit carrying the value an earlier
+// sequence on the key stored is stale
+fn classify_sequence(protocol: &ProtocolParse, bytes: &[u8], derived: &[u8]) -> Status {
+    let (key, number) = match tools::split_sequence_key(derived) {
+        Some(k) => k,
+        None => return classify(protocol, bytes, Some(derived)),
+    };
+    match classify(protocol, bytes, None) {
+        Status::Hit => {
+            match protocol.sequence_number(key, bytes) {
+                Some(n) if n == number && !protocol.validate_flags(derived, bytes) => {
+                    debug!("flag mismatch: {:?}", String::from_utf8_lossy(bytes));
+                    Status::FlagMismatch
+                }
+                Some(n) if n == number => Status::Hit,
+                Some(n) if n < number => Status::Stale,
+                _ => {
+                    debug!("corrupt response: {:?}", String::from_utf8_lossy(bytes));
+                    Status::Corrupt
+                }
+            }
+        }
+        status => status,
     }
 }
 
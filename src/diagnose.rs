@@ -0,0 +1,150 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// What a server's response bytes look like
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speaks {
+    Redis,
+    Memcache,
+    Http,
+    /// a TLS alert, sent by a server expecting a TLS handshake
+    Tls,
+}
+
+impl Speaks {
+    fn name(&self) -> &'static str {
+        match *self {
+            Speaks::Redis => "redis",
+            Speaks::Memcache => "memcache",
+            Speaks::Http => "http",
+            Speaks::Tls => "tls",
+        }
+    }
+}
+
+/// Guess the protocol of a response from its first bytes
+pub fn identify(bytes: &[u8]) -> Option<Speaks> {
+    const MEMCACHE: [&'static [u8]; 7] = [b"ERROR",
+                                          b"CLIENT_ERROR",
+                                          b"SERVER_ERROR",
+                                          b"VALUE ",
+                                          b"END\r\n",
+                                          b"STORED\r\n",
+                                          b"NOT_FOUND\r\n"];
+
+    if bytes.len() >= 3 && bytes[0] == 0x15 && bytes[1] == 0x03 && bytes[2] <= 0x04 {
+        return Some(Speaks::Tls);
+    }
+    if bytes.starts_with(b"HTTP/1.") {
+        return Some(Speaks::Http);
+    }
+    if MEMCACHE.iter().any(|m| bytes.starts_with(m)) {
+        return Some(Speaks::Memcache);
+    }
+    match bytes.first() {
+        Some(&b'+') | Some(&b'-') | Some(&b':') | Some(&b'$') | Some(&b'*') => {
+            Some(Speaks::Redis)
+        }
+        _ => None,
+    }
+}
+
+/// Watches the first response on every connection, and explains the likely cause when they
+/// are all protocol errors
+pub struct Diagnosis {
+    protocol: String,
+    // connections which must fail before the protocols are assumed to disagree
+    threshold: usize,
+    abort: bool,
+    failed: AtomicUsize,
+    succeeded: AtomicUsize,
+    reported: AtomicBool,
+    speaks: Mutex<Option<Speaks>>,
+}
+
+impl Diagnosis {
+    pub fn new(protocol: &str, connections: usize, abort: bool) -> Diagnosis {
+        Diagnosis {
+            protocol: protocol.to_owned(),
+            threshold: connections.min(2).max(1),
+            abort: abort,
+            failed: AtomicUsize::new(0),
+            succeeded: AtomicUsize::new(0),
+            reported: AtomicBool::new(false),
+            speaks: Mutex::new(None),
+        }
+    }
+
+    /// Whether the bytes look like a protocol other than the one selected
+    pub fn is_foreign(&self, bytes: &[u8]) -> bool {
+        identify(bytes).map_or(false, |s| s.name() != self.protocol)
+    }
+
+    /// Record the first response on a connection, which failed to parse if `failed`
+    pub fn first_response(&self, failed: bool, bytes: &[u8]) {
+        if !failed {
+            self.succeeded.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if let Some(speaks) = identify(bytes) {
+            if let Ok(mut s) = self.speaks.lock() {
+                *s = Some(speaks);
+            }
+        }
+        let failed = self.failed.fetch_add(1, Ordering::Relaxed) + 1;
+        if failed >= self.threshold && self.succeeded.load(Ordering::Relaxed) == 0 &&
+           !self.reported.swap(true, Ordering::Relaxed) {
+            self.report(failed);
+        }
+    }
+
+    fn report(&self, failed: usize) {
+        error!("The first responses on {} connections were all protocol errors", failed);
+        let speaks = self.speaks.lock().ok().and_then(|s| *s);
+        match speaks {
+            Some(Speaks::Tls) => error!("Server requires TLS"),
+            Some(s) if s.name() != self.protocol => {
+                error!("Server appears to speak {}; you selected {}",
+                       s.name(),
+                       self.protocol)
+            }
+            _ => error!("Check that the protocol {} is the one the server speaks", self.protocol),
+        }
+        if self.abort {
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_servers() {
+        assert_eq!(identify(b"-ERR unknown command 'get'\r\n"), Some(Speaks::Redis));
+        assert_eq!(identify(b"$-1\r\n"), Some(Speaks::Redis));
+        assert_eq!(identify(b"ERROR\r\n"), Some(Speaks::Memcache));
+        assert_eq!(identify(b"HTTP/1.1 400 Bad Request\r\n"), Some(Speaks::Http));
+        assert_eq!(identify(&[0x15, 0x03, 0x01, 0x00, 0x02, 0x02, 0x46]),
+                   Some(Speaks::Tls));
+        assert_eq!(identify(b"\x00\x00\x00\x10"), None);
+        assert_eq!(identify(b""), None);
+    }
+}
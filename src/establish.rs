@@ -0,0 +1,148 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+extern crate time;
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Connections established, failed and lost so far, shared by the clients and the stats
+/// receiver
+///
+/// Windows which complete before the threshold of connections is established are the
+/// connection ramp, and are kept out of the run totals unless asked for. A connection is lost
+/// once it closes without being replaced.
+pub struct Progress {
+    expected: usize,
+    // connections which must be established before the ramp ends
+    threshold: usize,
+    start: u64,
+    established: AtomicUsize,
+    failed: AtomicUsize,
+    lost: AtomicUsize,
+    // nanoseconds from the start until every connection was established
+    full: Mutex<Option<u64>>,
+}
+
+impl Progress {
+    /// Track `expected` connections, the ramp ending once `percent` of them are established
+    pub fn new(expected: usize, percent: f64) -> Progress {
+        Progress {
+            expected: expected,
+            threshold: (expected as f64 * percent / 100.0).ceil() as usize,
+            start: time::precise_time_ns(),
+            established: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            lost: AtomicUsize::new(0),
+            full: Mutex::new(None),
+        }
+    }
+
+    /// A connection has connected
+    pub fn establish(&self) {
+        let established = self.established.fetch_add(1, Ordering::Relaxed) + 1;
+        if established == self.expected {
+            if let Ok(mut full) = self.full.lock() {
+                *full = Some(time::precise_time_ns() - self.start);
+            }
+        }
+    }
+
+    /// A connection failed before it connected
+    pub fn fail(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection has closed and won't be replaced
+    pub fn lose(&self) {
+        self.lost.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn expected(&self) -> usize {
+        self.expected
+    }
+
+    pub fn established(&self) -> usize {
+        self.established.load(Ordering::Relaxed)
+    }
+
+    pub fn lost(&self) -> usize {
+        self.lost.load(Ordering::Relaxed)
+    }
+
+    /// Whether the ramp is over: the threshold is established, or every connection has
+    /// connected or failed and it never will be
+    pub fn is_complete(&self) -> bool {
+        let established = self.established();
+        established >= self.threshold ||
+        established + self.failed.load(Ordering::Relaxed) >= self.expected
+    }
+
+    /// Nanoseconds taken to establish every connection, once they all have
+    pub fn time_to_full(&self) -> Option<u64> {
+        self.full.lock().ok().and_then(|f| *f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_at_threshold() {
+        let progress = Progress::new(10, 50.0);
+        for _ in 0..4 {
+            progress.establish();
+        }
+        assert!(!progress.is_complete());
+        progress.establish();
+        assert!(progress.is_complete());
+        assert_eq!(progress.time_to_full(), None);
+        for _ in 0..5 {
+            progress.establish();
+        }
+        assert!(progress.time_to_full().is_some());
+    }
+
+    #[test]
+    fn complete_once_settled() {
+        let progress = Progress::new(4, 100.0);
+        progress.establish();
+        progress.establish();
+        progress.fail();
+        assert!(!progress.is_complete());
+        progress.fail();
+        assert!(progress.is_complete());
+        assert_eq!(progress.time_to_full(), None);
+        assert_eq!(progress.established(), 2);
+    }
+
+    #[test]
+    fn lost_connections() {
+        let progress = Progress::new(2, 100.0);
+        progress.establish();
+        progress.lose();
+        progress.fail();
+        progress.lose();
+        assert_eq!(progress.lost(), 2);
+        assert!(progress.is_complete());
+    }
+
+    #[test]
+    fn nothing_to_establish() {
+        assert!(Progress::new(0, 100.0).is_complete());
+        assert!(!Progress::new(3, 1.0).is_complete());
+    }
+}
@@ -0,0 +1,281 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::io;
+use std::str::FromStr;
+
+use histogram::Histogram;
+
+use connection::Socket;
+
+/// Connections are split between two arms, the option enabled in the first and disabled in the
+/// second
+pub const ARMS: usize = 2;
+
+/// Boolean socket options which can be compared with `--ab-socket-option`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SocketOption {
+    NoDelay,
+}
+
+impl FromStr for SocketOption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SocketOption, String> {
+        match s {
+            "nodelay" => Ok(SocketOption::NoDelay),
+            _ => Err(format!("unknown socket option: {} must be nodelay", s)),
+        }
+    }
+}
+
+impl SocketOption {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SocketOption::NoDelay => "nodelay",
+        }
+    }
+
+    /// Set the option on a connection's socket
+    pub fn apply(&self, socket: &Socket, enabled: bool) -> io::Result<()> {
+        match (*self, socket) {
+            (SocketOption::NoDelay, &Socket::Tcp(ref s)) => s.set_nodelay(enabled),
+            (SocketOption::NoDelay, &Socket::Udp(..)) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "nodelay is only for TCP"))
+            }
+        }
+    }
+}
+
+/// Whether the option is enabled in the arm
+pub fn enabled(arm: usize) -> bool {
+    arm == 0
+}
+
+/// Arm of the connection with this index among those to a server, alternating so the arms
+/// split the connections evenly
+pub fn arm(index: usize) -> usize {
+    index % ARMS
+}
+
+/// Percentiles of each arm, in nanoseconds, and how far the second arm is from the first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub responses: Vec<u64>,
+    pub arms: Vec<Vec<u64>>,
+    pub deltas: Vec<i64>,
+}
+
+/// Spread of a delta across the windows, an estimate of the noise between runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+    pub mean: f64,
+    /// two standard errors of the mean, in nanoseconds
+    pub error: f64,
+    pub windows: usize,
+}
+
+/// Latency of the requests sent on the connections of each arm, compared each window and for
+/// the run
+///
+/// Both arms take their requests from the same queue, so they see the same mix of requests.
+pub struct Experiment {
+    pub option: SocketOption,
+    percentiles: Vec<f64>,
+    // latencies beyond the histograms are recorded at their maximum
+    max: u64,
+    window: Vec<Histogram>,
+    run: Vec<Histogram>,
+    // delta of each window, for each percentile
+    deltas: Vec<Vec<f64>>,
+}
+
+impl Experiment {
+    /// The histograms of each arm are configured like `histogram`
+    pub fn new(option: SocketOption,
+               histogram: &Histogram,
+               max: u64,
+               percentiles: Vec<f64>)
+               -> Experiment {
+        let mut empty = histogram.clone();
+        let _ = empty.clear();
+        Experiment {
+            option: option,
+            deltas: vec![Vec::new(); percentiles.len()],
+            percentiles: percentiles,
+            max: max,
+            window: vec![empty.clone(); ARMS],
+            run: vec![empty; ARMS],
+        }
+    }
+
+    /// Label of the arm, eg: `A nodelay=on`
+    pub fn arm_name(&self, arm: usize) -> String {
+        format!("{} {}={}",
+                if arm == 0 {
+                    "A"
+                } else {
+                    "B"
+                },
+                self.option.name(),
+                if enabled(arm) {
+                    "on"
+                } else {
+                    "off"
+                })
+    }
+
+    pub fn percentiles(&self) -> &[f64] {
+        &self.percentiles
+    }
+
+    pub fn record(&mut self, arm: usize, latency: u64) {
+        let latency = latency.min(self.max);
+        if let Some(h) = self.window.get_mut(arm) {
+            let _ = h.increment(latency);
+        }
+        if let Some(h) = self.run.get_mut(arm) {
+            let _ = h.increment(latency);
+        }
+    }
+
+    /// Discard everything recorded, eg: in the warmup
+    pub fn clear(&mut self) {
+        for h in self.window.iter_mut().chain(self.run.iter_mut()) {
+            let _ = h.clear();
+        }
+    }
+
+    /// Compare the arms in the window which just ended, None unless both had responses
+    pub fn end_window(&mut self) -> Option<Comparison> {
+        let comparison = compare(&self.window, &self.percentiles);
+        if let Some(ref c) = comparison {
+            for (deltas, delta) in self.deltas.iter_mut().zip(c.deltas.iter()) {
+                deltas.push(*delta as f64);
+            }
+        }
+        for h in &mut self.window {
+            let _ = h.clear();
+        }
+        comparison
+    }
+
+    /// Compare the arms over the run
+    pub fn summary(&self) -> Option<Comparison> {
+        compare(&self.run, &self.percentiles)
+    }
+
+    /// Noise of the delta at each percentile, None with fewer than two windows compared
+    pub fn noise(&self) -> Vec<Option<Noise>> {
+        self.deltas.iter().map(|d| noise(d)).collect()
+    }
+}
+
+fn compare(histograms: &[Histogram], percentiles: &[f64]) -> Option<Comparison> {
+    if histograms.iter().any(|h| h.entries() == 0) {
+        return None;
+    }
+    let arms: Vec<Vec<u64>> = histograms.iter()
+                                        .map(|h| {
+                                            percentiles.iter()
+                                                       .map(|p| h.percentile(*p).unwrap_or(0))
+                                                       .collect()
+                                        })
+                                        .collect();
+    let deltas = arms[1].iter().zip(arms[0].iter()).map(|(b, a)| *b as i64 - *a as i64).collect();
+    Some(Comparison {
+        responses: histograms.iter().map(|h| h.entries()).collect(),
+        arms: arms,
+        deltas: deltas,
+    })
+}
+
+fn noise(deltas: &[f64]) -> Option<Noise> {
+    let n = deltas.len();
+    if n < 2 {
+        return None;
+    }
+    let mean = deltas.iter().fold(0.0, |sum, d| sum + d) / n as f64;
+    let variance = deltas.iter().fold(0.0, |sum, d| sum + (d - mean) * (d - mean)) /
+                   (n - 1) as f64;
+    Some(Noise {
+        mean: mean,
+        error: 2.0 * (variance / n as f64).sqrt(),
+        windows: n,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::{Histogram, HistogramConfig};
+
+    fn experiment() -> Experiment {
+        let mut config = HistogramConfig::new();
+        config.precision(4).max_value(1_000_000);
+        let histogram = Histogram::configured(config).unwrap();
+        Experiment::new(SocketOption::NoDelay, &histogram, 1_000_000, vec![50.0, 99.0])
+    }
+
+    #[test]
+    fn parse_option() {
+        assert_eq!("nodelay".parse(), Ok(SocketOption::NoDelay));
+        assert!("reuseaddr".parse::<SocketOption>().is_err());
+    }
+
+    #[test]
+    fn arms() {
+        assert_eq!((0..4).map(arm).collect::<Vec<usize>>(), vec![0, 1, 0, 1]);
+        assert!(enabled(0));
+        assert!(!enabled(1));
+        let e = experiment();
+        assert_eq!(e.arm_name(0), "A nodelay=on");
+        assert_eq!(e.arm_name(1), "B nodelay=off");
+    }
+
+    #[test]
+    fn compare_windows() {
+        let mut e = experiment();
+        e.record(0, 1_000);
+        assert_eq!(e.end_window(), None);
+
+        for window in 0..3 {
+            for _ in 0..100 {
+                e.record(0, 1_000);
+                e.record(1, 2_000 + window * 10);
+            }
+            let c = e.end_window().unwrap();
+            assert_eq!(c.responses, vec![100, 100]);
+            assert_eq!(c.deltas[0], 1_000 + window as i64 * 10);
+        }
+
+        let noise = e.noise();
+        let n = noise[0].unwrap();
+        assert_eq!(n.windows, 3);
+        assert_eq!(n.mean, 1_010.0);
+        assert!(n.error > 0.0 && n.error < 20.0);
+
+        let summary = e.summary().unwrap();
+        assert_eq!(summary.responses, vec![301, 300]);
+    }
+
+    #[test]
+    fn noise_needs_windows() {
+        assert_eq!(noise(&[]), None);
+        assert_eq!(noise(&[5.0]), None);
+        let n = noise(&[5.0, 5.0]).unwrap();
+        assert_eq!(n.error, 0.0);
+    }
+}
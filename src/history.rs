@@ -0,0 +1,254 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use output::{self, Record};
+
+/// Entries shown by `--history-show` without a count
+pub const SHOW_DEFAULT: usize = 10;
+
+/// `~/.rpc-perf/history.jsonl`, when the home directory is known
+pub fn default_path() -> Option<String> {
+    env::var("HOME").ok().map(|home| format!("{}/.rpc-perf/history.jsonl", home))
+}
+
+/// Hash of the config file, or None if it can't be read
+pub fn file_hash(path: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    match File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)) {
+        Ok(_) => Some(config_hash(&bytes)),
+        Err(e) => {
+            warn!("history: can't hash config {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// 64-bit FNV-1a of the config file, so runs of the same config can be grouped
+pub fn config_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325, |hash: u64, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Appends one JSON line for each completed run
+pub struct History {
+    pub path: String,
+    pub config_hash: Option<String>,
+    pub tags: Vec<String>,
+    pub protocol: String,
+    pub servers: Vec<String>,
+    /// kind and path of each file written by the run, eg: `trace`
+    pub artifacts: Vec<(&'static str, String)>,
+}
+
+impl History {
+    pub fn append(&self, summary: &Record, status: i32) -> Result<(), String> {
+        if let Some(dir) = Path::new(&self.path).parent() {
+            if !dir.as_os_str().is_empty() {
+                if let Err(e) = fs::create_dir_all(dir) {
+                    return Err(format!("Error creating history directory {}: {}",
+                                       dir.display(),
+                                       e));
+                }
+            }
+        }
+        let mut file = match OpenOptions::new().create(true).append(true).open(&self.path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening history {}: {}", self.path, e)),
+        };
+        match writeln!(file, "{}", self.line(summary, status)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Error writing history {}: {}", self.path, e)),
+        }
+    }
+
+    fn line(&self, summary: &Record, status: i32) -> String {
+        // artifacts are recorded by absolute path, the history outlives the working directory
+        let artifacts: Vec<String> = self.artifacts
+                                         .iter()
+                                         .map(|&(kind, ref path)| {
+                                             let path = fs::canonicalize(path)
+                                                            .map(|p| p.display().to_string())
+                                                            .unwrap_or_else(|_| path.clone());
+                                             format!("{}:{}", quote(kind), quote(&path))
+                                         })
+                                         .collect();
+        let timestamp = output::timestamp(summary.stop);
+        let mut fields = vec![format!("\"timestamp\":{}", quote(&timestamp))];
+        if let Some(ref hash) = self.config_hash {
+            fields.push(format!("\"config_hash\":{}", quote(hash)));
+        }
+        fields.push(format!("\"tags\":{}", list(&self.tags)));
+        fields.push(format!("\"protocol\":{}", quote(&self.protocol)));
+        fields.push(format!("\"servers\":{}", list(&self.servers)));
+        fields.push(format!("\"windows\":{}", summary.window));
+        fields.push(format!("\"requests\":{}", summary.requests));
+        fields.push(format!("\"errors\":{}", summary.errors));
+        fields.push(format!("\"rate\":{:.*}", 2, summary.rate));
+        fields.push(format!("\"p50\":{}", summary.p50));
+        fields.push(format!("\"p99\":{}", summary.p99));
+        fields.push(format!("\"p999\":{}", summary.p999));
+        fields.push(format!("\"max\":{}", summary.max));
+        fields.push(format!("\"exit_status\":{}", status));
+        fields.push(format!("\"artifacts\":{{{}}}", artifacts.join(",")));
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+/// JSON string of `s`
+pub fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace("\\", "\\\\").replace("\"", "\\\""))
+}
+
+fn list(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|v| quote(v)).collect();
+    format!("[{}]", quoted.join(","))
+}
+
+/// Print the last `count` entries of the history as a table
+pub fn show(path: &str, count: usize) -> Result<(), String> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("Error opening history {}: {}", path, e)),
+    };
+    let lines: Vec<String> = BufReader::new(file).lines().filter_map(|l| l.ok()).collect();
+    let skip = lines.len().saturating_sub(count);
+
+    println!("{:<25} {:>4} {:>12} {:>10} {:>12} {:>16}  {}",
+             "timestamp",
+             "exit",
+             "rate",
+             "errors",
+             "p99 (ns)",
+             "config",
+             "tags");
+    for line in &lines[skip..] {
+        let value = |name: &str| field(line, name).unwrap_or("-");
+        println!("{:<25} {:>4} {:>12} {:>10} {:>12} {:>16}  {}",
+                 value("timestamp"),
+                 value("exit_status"),
+                 value("rate"),
+                 value("errors"),
+                 value("p99"),
+                 value("config_hash"),
+                 value("tags").replace("\"", ""));
+    }
+    Ok(())
+}
+
+// raw value of a field in a history line: a string without its quotes, the contents of an
+// array, or a number
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let key = format!("\"{}\":", name);
+    let start = match line.find(&key) {
+        Some(i) => i + key.len(),
+        None => return None,
+    };
+    let rest = &line[start..];
+    if rest.starts_with('"') {
+        let mut escaped = false;
+        for (i, c) in rest[1..].char_indices() {
+            match c {
+                '\\' if !escaped => escaped = true,
+                '"' if !escaped => return Some(&rest[1..(i + 1)]),
+                _ => escaped = false,
+            }
+        }
+        None
+    } else if rest.starts_with('[') {
+        rest.find(']').map(|i| &rest[1..i])
+    } else {
+        rest.find(|c| c == ',' || c == '}').map(|i| &rest[..i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use output::Record;
+    use time;
+
+    fn summary() -> Record {
+        let now = time::get_time();
+        Record {
+            window: 5,
+            offered_rate: None,
+            start: now,
+            stop: now,
+            requests: 1000,
+            responses: 1000,
+            successes: 990,
+            errors: 10,
+            client_errors: 0,
+            server_errors: 0,
+            corrupt: 0,
+            flag_mismatches: 0,
+            stale: 0,
+            timeouts: 0,
+            retried: 0,
+            gave_up: 0,
+            slow: None,
+            rate: 200.0,
+            min: 1,
+            p50: 100,
+            p90: 200,
+            p99: 300,
+            p999: 400,
+            p9999: 500,
+            max: 600,
+            tcp_rtt_p50: None,
+            tcp_rtt_p99: None,
+            tcp_retransmits: None,
+            truncated: None,
+            interrupted: None,
+            connection_ramp: None,
+            establish_time: None,
+            server: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn lines_can_be_read_back() {
+        let history = History {
+            path: String::new(),
+            config_hash: Some(config_hash(b"[general]\n")),
+            tags: vec!["baseline".to_owned(), "say \"hi\"".to_owned()],
+            protocol: "memcache".to_owned(),
+            servers: vec!["127.0.0.1:11211".to_owned()],
+            artifacts: Vec::new(),
+        };
+        let line = history.line(&summary(), 2);
+        assert_eq!(field(&line, "exit_status"), Some("2"));
+        assert_eq!(field(&line, "rate"), Some("200.00"));
+        assert_eq!(field(&line, "p99"), Some("300"));
+        assert_eq!(field(&line, "config_hash"), Some(config_hash(b"[general]\n").as_str()));
+        assert_eq!(field(&line, "tags"), Some("\"baseline\",\"say \\\"hi\\\"\""));
+        assert_eq!(field(&line, "servers"), Some("\"127.0.0.1:11211\""));
+        assert_eq!(field(&line, "missing"), None);
+    }
+
+    #[test]
+    fn config_hash_is_stable() {
+        assert_eq!(config_hash(b""), "cbf29ce484222325");
+        assert_eq!(config_hash(b"a"), "af63dc4c8601ec8c");
+    }
+}
@@ -0,0 +1,180 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use request::Interleave;
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+// requests dropped because too many were held back by the policy, counted by the clients and
+// taken by the stats receiver each window
+static DROPPED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Count a request dropped because the client held back too many the policy didn't allow
+pub fn drop_request() {
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Requests dropped since they were last taken
+pub fn take_dropped() -> u64 {
+    DROPPED.swap(0, Ordering::Relaxed) as u64
+}
+
+/// Consecutive requests of one workload at the end of those sent on a connection
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Run {
+    pub workload: Option<usize>,
+    pub length: usize,
+}
+
+impl Run {
+    /// Whether the policy lets a request of the workload follow the run, out of `workloads`
+    ///
+    /// Requests without a workload, eg: those of a replay, may always be sent.
+    pub fn allows(&self, policy: Interleave, workloads: usize, workload: Option<usize>) -> bool {
+        let (last, next) = match (self.workload, workload) {
+            (Some(last), Some(next)) => (last, next),
+            _ => return true,
+        };
+        match policy {
+            Interleave::Free => true,
+            Interleave::RoundRobin => next == (last + 1) % workloads,
+            Interleave::MaxRun(max) => next != last || self.length < max,
+        }
+    }
+
+    /// Extend the run with a request, returning the length of the run it ends, zero without a
+    /// workload
+    pub fn push(&mut self, workload: Option<usize>) -> usize {
+        match workload {
+            None => 0,
+            Some(w) if self.workload == Some(w) => {
+                self.length += 1;
+                self.length
+            }
+            Some(w) => {
+                self.workload = Some(w);
+                self.length = 1;
+                1
+            }
+        }
+    }
+}
+
+/// Longest run of one workload on any connection, in the current window and the whole test
+#[derive(Default)]
+pub struct Longest {
+    // length and workload
+    window: Option<(usize, usize)>,
+    // length, workload and window
+    worst: Option<(usize, usize, usize)>,
+}
+
+impl Longest {
+    /// Note the run a request of the workload ended
+    pub fn record(&mut self, length: usize, workload: usize) {
+        if self.window.map_or(true, |(l, _)| length > l) {
+            self.window = Some((length, workload));
+        }
+    }
+
+    /// Discard the current window, eg: the warmup
+    pub fn clear(&mut self) {
+        self.window = None;
+    }
+
+    /// Longest run in the window which just ended, as its length and workload
+    pub fn end_window(&mut self, window: usize) -> Option<(usize, usize)> {
+        let longest = self.window.take();
+        if let Some((length, workload)) = longest {
+            if self.worst.map_or(true, |(l, _, _)| length > l) {
+                self.worst = Some((length, workload, window));
+            }
+        }
+        longest
+    }
+
+    /// Longest run in any window, as its length, workload and window
+    pub fn worst(&self) -> Option<(usize, usize, usize)> {
+        self.worst
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use request::Interleave;
+
+    fn run(policy: Interleave, workloads: usize, sequence: &[usize], next: usize) -> bool {
+        let mut run = Run::default();
+        for w in sequence {
+            run.push(Some(*w));
+        }
+        run.allows(policy, workloads, Some(next))
+    }
+
+    #[test]
+    fn free_allows_anything() {
+        assert!(run(Interleave::Free, 2, &[0, 0, 0, 0], 0));
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        assert!(run(Interleave::RoundRobin, 3, &[], 2));
+        assert!(run(Interleave::RoundRobin, 3, &[0], 1));
+        assert!(!run(Interleave::RoundRobin, 3, &[0], 2));
+        assert!(!run(Interleave::RoundRobin, 3, &[0], 0));
+        assert!(run(Interleave::RoundRobin, 3, &[0, 1, 2], 0));
+    }
+
+    #[test]
+    fn max_run_limits_consecutive() {
+        assert!(run(Interleave::MaxRun(2), 2, &[0], 0));
+        assert!(!run(Interleave::MaxRun(2), 2, &[1, 0, 0], 0));
+        assert!(run(Interleave::MaxRun(2), 2, &[1, 0, 0], 1));
+        assert!(!run(Interleave::MaxRun(1), 2, &[1], 1));
+    }
+
+    #[test]
+    fn run_lengths() {
+        let mut run = Run::default();
+        assert_eq!(run.push(Some(0)), 1);
+        assert_eq!(run.push(Some(0)), 2);
+        // a request without a workload neither breaks nor extends the run
+        assert_eq!(run.push(None), 0);
+        assert_eq!(run.push(Some(0)), 3);
+        assert_eq!(run.push(Some(1)), 1);
+        assert!(run.allows(Interleave::MaxRun(1), 2, None));
+    }
+
+    #[test]
+    fn longest_by_window() {
+        let mut longest = Longest::default();
+        longest.record(2, 0);
+        longest.record(5, 1);
+        longest.record(3, 0);
+        assert_eq!(longest.end_window(1), Some((5, 1)));
+        longest.record(4, 0);
+        assert_eq!(longest.end_window(2), Some((4, 0)));
+        assert_eq!(longest.end_window(3), None);
+        assert_eq!(longest.worst(), Some((5, 1, 1)));
+    }
+
+    #[test]
+    fn dropped_requests() {
+        drop_request();
+        drop_request();
+        assert_eq!(take_dropped(), 2);
+        assert_eq!(take_dropped(), 0);
+    }
+}
@@ -20,6 +20,8 @@ extern crate bytes;
 extern crate getopts;
 extern crate heatmap;
 extern crate histogram;
+extern crate libc;
+extern crate lodepng;
 extern crate tiny_http;
 extern crate time;
 extern crate mio;
@@ -31,12 +33,27 @@ extern crate shuteye;
 extern crate toml;
 extern crate waterfall;
 
+mod annotate;
+mod budget;
+mod capture;
 mod client;
 mod connection;
+mod diagnose;
+mod establish;
+mod experiment;
+mod history;
+mod interleave;
 mod logger;
+mod mix;
 mod net;
+mod output;
+mod probe;
+mod signal;
+mod sla;
 mod state;
 mod stats;
+mod tcpinfo;
+mod udp;
 
 use getopts::Options;
 use log::LogLevelFilter;
@@ -46,78 +63,182 @@ use std::env;
 use std::net::ToSocketAddrs;
 use std::thread;
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc;
 use std::process;
 
 
-use client::Client;
-use connection::Connection;
+use budget::{Budget, Limits, Problem};
+use capture::{Capture, Direction, Target};
+use client::{Client, Message};
+use connection::{Connection, Socket, Timeout};
+use diagnose::Diagnosis;
+use establish::Progress;
+use experiment::SocketOption;
+use history::History;
 use logger::SimpleLogger;
+use mix::Mix;
 use net::InternetProtocol;
-use stats::Stat;
-use request::workload;
+use output::{Output, WindowCsv};
+use sla::Assertions;
+use cfgtypes::WorkItem;
+use stats::{ConnectionSnapshot, RunConfig, Stat, TcpSample};
+use request::{Interleave, Mode, replay, workload};
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
 const BUCKET_SIZE: usize = 10_000;
 
-struct ClientConfig {
-    servers: Vec<String>,
+#[derive(Clone)]
+struct Server {
+    address: String,
+    weight: usize,
     connections: usize,
+    udp: bool,
+}
+
+struct ClientConfig {
+    id: usize,
+    servers: Vec<Server>,
     stats_tx: mpsc::Sender<Stat>,
+    tcp_tx: Option<mpsc::Sender<TcpSample>>,
+    tcp_interval: u64,
+    connections_tx: Option<mpsc::Sender<ConnectionSnapshot>>,
     client_protocol: Arc<cfgtypes::ProtocolParseFactory>,
     internet_protocol: InternetProtocol,
-    work_rx: BoundedQueue<Vec<u8>>,
+    work_rx: BoundedQueue<WorkItem>,
     tcp_nodelay: bool,
+    pipeline: usize,
+    interleave: Interleave,
+    workloads: usize,
+    ab: Option<SocketOption>,
+    capture: Option<Target>,
+    progress: Arc<Progress>,
+    timeout: Option<Timeout>,
+    diagnosis: Arc<Diagnosis>,
     mio_config: mio::EventLoopConfig,
+    control_tx: mpsc::Sender<mio::Sender<Message>>,
 }
 
 fn start(config: ClientConfig) {
     let mut event_loop = mio::EventLoop::configured(config.mio_config.clone()).unwrap();
-    let mut client = Client::new(config.work_rx.clone());
+    let connections = config.servers.iter().fold(0, |sum, s| sum + s.connections);
+    let mut client = Client::new(config.id,
+                                 config.work_rx.clone(),
+                                 connections,
+                                 config.internet_protocol);
+    client.interleave(config.interleave, config.workloads);
 
     let mut failures = 0;
     let mut connects = 0;
+    // connects and failures of each server
+    let mut breakdown = Vec::new();
+    // numbered across every client, each of which has the same connections
+    let mut number = config.id * connections;
 
-    for server in &config.servers {
-        let address = &server.to_socket_addrs().unwrap().next().unwrap();
-        for _ in 0..config.connections {
-            match net::to_mio_tcp_stream(address, config.internet_protocol) {
-                Ok(stream) => {
+    for (id, server) in config.servers.iter().enumerate() {
+        let mut server_connects = 0;
+        let mut server_failures = 0;
+        for i in 0..server.connections {
+            let socket = if server.udp {
+                net::to_mio_udp_socket(server.address.as_str(), config.internet_protocol)
+                    .map(|(s, address)| Socket::Udp(s, address))
+            } else {
+                net::to_mio_tcp_stream(server.address.as_str(), config.internet_protocol)
+                    .map(Socket::Tcp)
+            };
+            match socket {
+                Ok(socket) => {
                     match client.connections.insert_with(|token| {
-                        Connection::new(stream,
+                        Connection::new(socket,
                                         token,
+                                        server.address.clone(),
+                                        id,
                                         config.stats_tx.clone(),
                                         config.client_protocol.new(),
-                                        config.tcp_nodelay)
+                                        config.tcp_nodelay,
+                                        config.pipeline,
+                                        config.timeout.clone(),
+                                        config.diagnosis.clone())
                     }) {
                         Some(token) => {
-                            event_loop.register(&client.connections[token].socket,
-                                                token,
-                                                mio::EventSet::writable(),
-                                                mio::PollOpt::edge() | mio::PollOpt::oneshot())
-                                      .unwrap();
-                            connects += 1;
+                            // the arms alternate across every client's connections to the server
+                            if let Some(option) = config.ab {
+                                let index = config.id * server.connections + i;
+                                client.connections[token].join_arm(option, experiment::arm(index));
+                            }
+                            client.connections[token].track(config.progress.clone());
+                            if let Some(ref target) = config.capture {
+                                if target.connection == number {
+                                    capture_connection(&mut client.connections[token],
+                                                       target,
+                                                       &server.address);
+                                }
+                            }
+                            client.connections[token].register(&mut event_loop);
+                            server_connects += 1;
+                        }
+                        _ => {
+                            debug!("too many established connections");
+                            config.progress.fail();
                         }
-                        _ => debug!("too many established connections"),
                     }
                 }
                 Err(e) => {
-                    failures += 1;
+                    server_failures += 1;
                     debug!("connect error: {}", e);
+                    config.progress.fail();
                 }
             }
+            number += 1;
         }
+        connects += server_connects;
+        failures += server_failures;
+        breakdown.push((server.address.clone(), server_connects, server_failures));
     }
     info!("Connections: {} Failures: {}", connects, failures);
-    if failures == config.connections {
+    if breakdown.len() > 1 {
+        for (address, connects, failures) in breakdown {
+            info!("Server: {} Connections: {} Failures: {}",
+                  address,
+                  connects,
+                  failures);
+        }
+    }
+    if let Some(tcp_tx) = config.tcp_tx {
+        client.sample_tcp_info(&mut event_loop, tcp_tx, config.tcp_interval);
+    }
+    if let Some(connections_tx) = config.connections_tx {
+        client.report_connections(&mut event_loop, connections_tx);
+    }
+    if let Some(ref timeout) = config.timeout {
+        // check deadlines several times per timeout, so requests expire close to it
+        let interval = timeout.duration / 1_000_000 / 4;
+        client.expire_requests(&mut event_loop, interval.max(1));
+    }
+    if connects == 0 {
         error!("All connections have failed");
         process::exit(1);
     } else {
+        // lets main stop the event loop once the final stats are written
+        let _ = config.control_tx.send(event_loop.channel());
         event_loop.run(&mut client).unwrap();
     }
 }
 
+fn capture_connection(connection: &mut Connection, target: &Target, server: &str) {
+    match Capture::create(target) {
+        Ok(capture) => {
+            info!("Capture: connection {} to {} into {}",
+                  target.connection,
+                  server,
+                  target.path);
+            connection.capture(capture);
+        }
+        Err(e) => error!("{}", e),
+    }
+}
+
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} [options]", program);
     print!("{}", opts.usage(&brief));
@@ -126,18 +247,69 @@ fn print_usage(program: &str, opts: Options) {
 pub fn opts() -> Options {
     let mut opts = Options::new();
 
-    opts.optmulti("s", "server", "server address", "HOST:PORT");
+    opts.optmulti("s", "server", "server address", "[udp://]HOST:PORT[:WEIGHT]");
     opts.optopt("t", "threads", "number of threads", "INTEGER");
     opts.optopt("c", "connections", "connections per thread", "INTEGER");
     opts.optopt("d", "duration", "number of seconds per window", "INTEGER");
     opts.optopt("w", "windows", "number of windows in test", "INTEGER");
+    opts.optopt("", "max-duration", "end the run after this many seconds", "INTEGER");
     opts.optopt("p", "protocol", "client protocol", "STRING");
+    opts.optopt("", "seed", "seed for generated requests", "INTEGER");
     opts.optopt("", "config", "TOML config file", "FILE");
+    opts.optopt("", "replay", "send the requests captured in a file", "FILE");
+    opts.optflag("", "replay-loop", "replay from the start when the file ends");
     opts.optopt("", "listen", "listen address for stats", "HOST:PORT");
     opts.optopt("", "trace", "write histogram data to file", "FILE");
+    opts.optopt("", "size-trace", "write response size data to file", "FILE");
     opts.optopt("", "waterfall", "output waterfall PNG", "FILE");
+    opts.optopt("", "output", "write per-window results to file", "FILE");
+    opts.optopt("", "output-format", "results file format (json, csv)", "STRING");
+    opts.optopt("", "csv", "append throughput and latency per window to file", "FILE");
+    opts.optflagopt("", "history", "append a record of the run to file", "FILE");
+    opts.optflagopt("", "history-show", "print the last N runs in the history", "N");
+    opts.optmulti("", "tag", "label the run in the history", "STRING");
+    opts.optopt("", "assert-p99", "fail if p99 latency exceeds limit", "MICROS");
+    opts.optopt("", "assert-p999", "fail if p999 latency exceeds limit", "MICROS");
+    opts.optopt("", "assert-error-rate", "fail if error rate exceeds limit", "PCT");
+    opts.optopt("", "assert-min-rate", "fail if request rate is below limit", "RPS");
+    opts.optopt("", "assert-slow-rate", "fail if slow responses exceed limit", "PCT");
+    opts.optopt("", "pipeline", "requests in flight per connection", "INTEGER");
+    opts.optopt("", "event-capacity", "event loop notify queue size", "INTEGER");
+    opts.optopt("", "timer-capacity", "event loop timer capacity", "INTEGER");
+    opts.optopt("", "ramp-rate", "step the rate each window", "START:END:STEP");
+    opts.optopt("", "percentiles", "latency percentiles to report", "LIST");
+    opts.optopt("", "slow-threshold", "count responses slower than this", "MILLIS");
+    opts.optopt("", "histogram-max", "largest latency the histograms track", "MILLIS");
+    opts.optopt("", "heatmap-max", "largest latency the heatmap tracks", "MILLIS");
+    opts.optopt("", "timeout", "fail requests without a response in time", "MILLIS");
+    opts.optopt("", "retries", "resend timed out requests up to N times", "INTEGER");
     opts.optflag("", "tcp-nodelay", "enable tcp nodelay");
+    opts.optflag("", "udp", "send requests over udp to every server (memcache)");
+    opts.optopt("", "interleave", "order of workloads on a connection", "POLICY");
+    opts.optopt("",
+                "ab-socket-option",
+                "compare connections with a socket option on and off (nodelay)",
+                "OPTION");
+    opts.optopt("", "mix-tolerance", "warn if a workload's share drifts this far", "PCT");
+    opts.optopt("",
+                "establish-threshold",
+                "connections established before windows count, default 100",
+                "PCT");
+    opts.optflag("", "include-connection-ramp", "count windows before the threshold");
+    opts.optflag("", "slow-log", "log each response over the slow threshold");
+    opts.optflag("", "abort-on-mismatch", "exit if the server speaks another protocol");
+    opts.optflag("", "tcp-info", "sample TCP_INFO from connections (Linux)");
     opts.optflag("", "flush", "flush cache prior to test");
+    opts.optflagopt("", "probe", "send N requests on one connection, print JSON", "N");
+    opts.optopt("", "probe-deadline", "time allowed for the probe", "MILLIS");
+    opts.optopt("",
+                "debug-capture-connection",
+                "record the bytes one connection sends and receives",
+                "INDEX");
+    opts.optopt("", "debug-capture-bytes", "bytes to capture, default 1 MB", "INTEGER");
+    opts.optopt("", "debug-capture-file", "file to capture into", "FILE");
+    opts.optopt("", "replay-bytes", "send a capture on one connection and exit", "FILE");
+    opts.optflag("", "check-invariants", "fail if result totals disagree");
     opts.optflag("", "ipv4", "force IPv4 only");
     opts.optflag("", "ipv6", "force IPv6 only");
     opts.optflag("", "version", "show version and exit");
@@ -147,9 +319,13 @@ pub fn opts() -> Options {
     opts
 }
 
-fn set_log_level(level: usize) {
+// quiet shows only errors without -v
+fn set_log_level(level: usize, quiet: bool) {
     let log_filter;
     match level {
+        0 if quiet => {
+            log_filter = LogLevelFilter::Error;
+        }
         0 => {
             log_filter = LogLevelFilter::Info;
         }
@@ -182,6 +358,275 @@ fn choose_layer_3(ipv4: bool, ipv6: bool) -> Result<InternetProtocol, String> {
     Err("No InternetProtocols remaining! Bad config/options".to_owned())
 }
 
+/// History file from `--history`, or the default when no file is given
+fn history_path(matches: &getopts::Matches) -> Result<String, String> {
+    match matches.opt_str("history").or_else(history::default_path) {
+        Some(path) => Ok(path),
+        None => Err("No history file given and HOME is not set".to_owned()),
+    }
+}
+
+/// Split the optional transport and weight from a server: [udp://]HOST:PORT[:WEIGHT]
+fn parse_server(server: &str) -> Result<Server, String> {
+    let (server, udp) = if server.starts_with("udp://") {
+        (&server[6..], true)
+    } else {
+        (server, false)
+    };
+    // IPv6 hosts are bracketed, only look for the port and weight after them
+    let host_end = server.rfind(']').unwrap_or(0);
+    let (address, weight) = match server[host_end..].split(':').count() {
+        2 => (server, 1),
+        3 => {
+            let i = server.rfind(':').unwrap();
+            match server[(i + 1)..].parse::<usize>() {
+                Ok(w) if w > 0 => (&server[..i], w),
+                _ => {
+                    return Err(format!("Bad server weight: {} must be a positive integer",
+                                       server));
+                }
+            }
+        }
+        _ => return Err(format!("Bad server: {} expected [udp://]HOST:PORT[:WEIGHT]", server)),
+    };
+
+    match address.to_socket_addrs() {
+        Ok(mut addrs) => {
+            if addrs.next().is_none() {
+                return Err(format!("Bad server: {} did not resolve", address));
+            }
+        }
+        Err(e) => return Err(format!("Bad server: {} cause: {}", address, e)),
+    }
+
+    Ok(Server {
+        address: address.to_owned(),
+        weight: weight,
+        connections: 0,
+        udp: udp,
+    })
+}
+
+// the connection to capture and its limits, if one was chosen
+fn capture_target(matches: &getopts::Matches) -> Result<Option<Target>, String> {
+    let connection = match matches.opt_str("debug-capture-connection") {
+        Some(n) => {
+            match n.parse() {
+                Ok(n) => n,
+                Err(_) => return Err(format!("Bad capture connection: {} must be an integer", n)),
+            }
+        }
+        None => {
+            if matches.opt_present("debug-capture-bytes") ||
+               matches.opt_present("debug-capture-file") {
+                warn!("--debug-capture-bytes and --debug-capture-file are ignored without \
+                       --debug-capture-connection");
+            }
+            return Ok(None);
+        }
+    };
+    let bytes = match matches.opt_str("debug-capture-bytes") {
+        Some(b) => {
+            match b.parse() {
+                Ok(b) if b > 0 => b,
+                _ => return Err(format!("Bad capture bytes: {} must be a positive integer", b)),
+            }
+        }
+        None => capture::DEFAULT_BYTES,
+    };
+    Ok(Some(Target {
+        connection: connection,
+        bytes: bytes,
+        path: matches.opt_str("debug-capture-file")
+                     .unwrap_or_else(|| format!("connection-{}.capture", connection)),
+    }))
+}
+
+/// Send a few requests on a single connection and print the results as JSON, returning an exit
+/// status for monitoring checks
+fn run_probe(matches: &getopts::Matches) -> i32 {
+    let requests = match matches.opt_str("probe") {
+        Some(n) => {
+            match n.parse() {
+                Ok(n) if n > 0 => n,
+                _ => {
+                    error!("Bad probe count: {} must be a positive integer", n);
+                    return probe::UNKNOWN;
+                }
+            }
+        }
+        None => probe::DEFAULT_REQUESTS,
+    };
+    let deadline = match matches.opt_str("probe-deadline") {
+        Some(ms) => {
+            match ms.parse() {
+                Ok(ms) if ms > 0 => ms,
+                _ => {
+                    error!("Bad probe deadline: {} must be a positive integer", ms);
+                    return probe::UNKNOWN;
+                }
+            }
+        }
+        None => probe::DEFAULT_DEADLINE,
+    };
+
+    let servers = matches.opt_strs("server");
+    if servers.len() != 1 {
+        error!("--probe requires a single server");
+        return probe::UNKNOWN;
+    }
+    let server = match parse_server(&servers[0]) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{}", e);
+            return probe::UNKNOWN;
+        }
+    };
+    if server.udp || matches.opt_present("udp") {
+        error!("--probe only supports TCP");
+        return probe::UNKNOWN;
+    }
+
+    let config = match config::load_config(matches) {
+        Ok(cfg) => cfg,
+        Err(reason) => {
+            error!("{}", reason);
+            return probe::UNKNOWN;
+        }
+    };
+    if config.protocol_config.workloads.is_empty() {
+        error!("--probe requires at least one workload");
+        return probe::UNKNOWN;
+    }
+    let internet_protocol = match choose_layer_3(matches.opt_present("ipv4"),
+                                                 matches.opt_present("ipv6")) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("{}", e);
+            return probe::UNKNOWN;
+        }
+    };
+
+    let parser = config.protocol_config.protocol.new();
+    let mut workloads: Vec<Box<cfgtypes::ProtocolGen>> = config.protocol_config
+                                                               .workloads
+                                                               .into_iter()
+                                                               .map(|w| w.gen)
+                                                               .collect();
+    let report = probe::run(&server.address,
+                            internet_protocol,
+                            &mut workloads,
+                            &*parser,
+                            requests,
+                            deadline);
+    println!("{}", report.json());
+    report.status()
+}
+
+/// Send the bytes a capture sent on a single connection with their original pacing, and log
+/// what came back, returning 0 when it's identical to what the capture received
+fn run_replay_bytes(matches: &getopts::Matches, file: &str) -> i32 {
+    let servers = matches.opt_strs("server");
+    if servers.len() != 1 {
+        error!("--replay-bytes requires a single server");
+        return 1;
+    }
+    let server = match parse_server(&servers[0]) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+    if server.udp || matches.opt_present("udp") {
+        error!("--replay-bytes only supports TCP");
+        return 1;
+    }
+    let chunks = match capture::load(file) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+    let internet_protocol = match choose_layer_3(matches.opt_present("ipv4"),
+                                                 matches.opt_present("ipv6")) {
+        Ok(i) => i,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+
+    let sent = capture::stream(&chunks, Direction::Sent);
+    let expected = capture::stream(&chunks, Direction::Received);
+    info!("Replay: {} Sent: {} bytes Received: {} bytes",
+          file,
+          sent.len(),
+          expected.len());
+    let deadline = time::precise_time_ns() + probe::DEFAULT_DEADLINE * 1_000_000;
+    let mut stream = match probe::connect(&server.address, internet_protocol, deadline) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    };
+    let received = match capture::replay(&mut stream, &chunks) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Replay: connection failed: {}", e);
+            return 1;
+        }
+    };
+    for chunk in &received {
+        info!("{}", chunk.describe());
+    }
+
+    let actual = capture::stream(&received, Direction::Received);
+    match capture::difference(&expected, &actual) {
+        None => {
+            info!("Replay: received {} bytes, identical to the capture", actual.len());
+            0
+        }
+        Some(at) => {
+            warn!("Replay: received {} bytes, the capture {}, first difference at byte {}",
+                  actual.len(),
+                  expected.len(),
+                  at);
+            1
+        }
+    }
+}
+
+// clients still connecting have no event loop to stop, they end with the process
+fn stop_clients(clients: Vec<(thread::JoinHandle<()>, mpsc::Receiver<mio::Sender<Message>>)>) {
+    for (handle, control_rx) in clients {
+        if let Ok(control) = control_rx.try_recv() {
+            let _ = control.send(Message::Shutdown);
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Share connections between servers in proportion to their weights
+fn allocate_connections(servers: &mut [Server], total: usize) {
+    let weights = servers.iter().fold(0, |acc, s| acc + s.weight);
+    let mut allocated = 0;
+    for server in servers.iter_mut() {
+        server.connections = total * server.weight / weights;
+        allocated += server.connections;
+    }
+    // hand out any remainder to the largest shares first
+    let mut order: Vec<usize> = (0..servers.len()).collect();
+    order.sort_by(|a, b| {
+        (total * servers[*b].weight % weights).cmp(&(total * servers[*a].weight % weights))
+    });
+    for i in order.into_iter().take(total - allocated) {
+        servers[i].connections += 1;
+    }
+}
+
 
 
 pub fn main() {
@@ -210,7 +655,35 @@ pub fn main() {
     }
 
     // defaults
-    set_log_level(matches.opt_count("verbose"));
+    set_log_level(matches.opt_count("verbose"), matches.opt_present("probe"));
+
+    if matches.opt_present("history-show") {
+        let count = match matches.opt_str("history-show") {
+            Some(n) => {
+                match n.parse() {
+                    Ok(n) => n,
+                    Err(_) => {
+                        error!("Bad history count: {} must be an integer", n);
+                        return;
+                    }
+                }
+            }
+            None => history::SHOW_DEFAULT,
+        };
+        let shown = history_path(&matches).and_then(|path| history::show(&path, count));
+        if let Err(e) = shown {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    if matches.opt_present("probe") {
+        process::exit(run_probe(&matches));
+    }
+
+    if let Some(file) = matches.opt_str("replay-bytes") {
+        process::exit(run_replay_bytes(&matches, &file));
+    }
 
     info!("rpc-perf {} initializing...", VERSION);
 
@@ -220,11 +693,67 @@ pub fn main() {
         return;
     };
 
+    let mut servers = Vec::new();
+    for server in matches.opt_strs("server") {
+        match parse_server(&server) {
+            Ok(mut s) => {
+                s.udp |= matches.opt_present("udp");
+                servers.push(s);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    }
+
     let waterfall = matches.opt_str("waterfall");
     let trace = matches.opt_str("trace");
+    let size_trace = matches.opt_str("size-trace");
 
     let listen = matches.opt_str("listen");
 
+    let tcp_info = matches.opt_present("tcp-info");
+    if tcp_info && !tcpinfo::SUPPORTED {
+        error!("--tcp-info is only supported on Linux");
+        return;
+    }
+
+    let output = match matches.opt_str("output") {
+        Some(file) => {
+            let format = match matches.opt_str("output-format")
+                                      .unwrap_or_else(|| "json".to_owned())
+                                      .parse() {
+                Ok(f) => f,
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            };
+            match Output::open(&file, format) {
+                Ok(o) => Some(o),
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let csv = match matches.opt_str("csv") {
+        Some(file) => {
+            match WindowCsv::open(&file) {
+                Ok(c) => Some(c),
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
     // Load workload configuration
     let config = match config::load_config(&matches) {
         Ok(cfg) => cfg,
@@ -234,6 +763,38 @@ pub fn main() {
         }
     };
 
+    // only memcache is framed for udp, and a lost datagram is only noticed by a timeout
+    if servers.iter().any(|s| s.udp) {
+        let protocol = config.protocol_config.protocol.name();
+        if protocol != "memcache" {
+            error!("UDP transport is only supported for memcache, not {}", protocol);
+            return;
+        }
+        if config.timeout.is_none() {
+            error!("UDP transport requires a request timeout to recover from lost datagrams");
+            return;
+        }
+    }
+
+    let ab = match matches.opt_str("ab-socket-option") {
+        Some(option) => {
+            match option.parse::<SocketOption>() {
+                Ok(o) => Some(o),
+                Err(e) => {
+                    error!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    if let Some(option) = ab {
+        if servers.iter().any(|s| s.udp) {
+            error!("--ab-socket-option {} is only supported over TCP", option.name());
+            return;
+        }
+    }
+
     let internet_protocol = match choose_layer_3(matches.opt_present("ipv4"),
                                                  matches.opt_present("ipv6")) {
         Ok(i) => i,
@@ -243,74 +804,540 @@ pub fn main() {
         }
     };
 
-    let work_queue = BoundedQueue::<Vec<u8>>::with_capacity(BUCKET_SIZE);
+    let work_queue = BoundedQueue::<WorkItem>::with_capacity(BUCKET_SIZE);
 
     // Let the protocol push some initial data if it wants too
-    match config.protocol_config.protocol.prepare() {
-        Ok(bs) => {
-            for b in bs {
-                work_queue.push(b).unwrap();
-            }
+    let prepared = match config.protocol_config.protocol.prepare() {
+        Ok(bs) => bs,
+        Err(e) => {
+            error!("{}", e);
+            return;
         }
+    };
+
+    let mut evconfig = mio::EventLoopConfig::default();
+    if let Some(capacity) = config.event_capacity {
+        evconfig.notify_capacity(capacity);
+    }
+    if let Some(capacity) = config.timer_capacity {
+        evconfig.timer_capacity(capacity);
+    }
+
+    let total_connections = config.connections * servers.len();
+    allocate_connections(&mut servers, total_connections);
+    for server in &servers {
+        if server.connections == 0 && total_connections > 0 {
+            warn!("Server: {} has too small a weight to be allocated any connections",
+                  server.address);
+        }
+    }
+    let connections = servers.iter().fold(0, |sum, s| sum + s.connections) * config.threads;
+
+    let mode = match Mode::pick(connections,
+                                config.protocol_config.workloads.len(),
+                                config.replay.is_some(),
+                                prepared.len(),
+                                listen.is_some()) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let preload = prepared.len();
+    // a scrape-only run has no connection to send them on
+    if mode != Mode::Scrape {
+        for b in prepared {
+            work_queue.push(WorkItem::new(b)).unwrap();
+        }
+    }
+
+    let capture = match capture_target(&matches) {
+        Ok(c) => c,
         Err(e) => {
             error!("{}", e);
             return;
         }
+    };
+    if let Some(ref target) = capture {
+        if servers.iter().any(|s| s.udp) {
+            error!("--debug-capture-connection is only supported over TCP");
+            return;
+        }
+        if target.connection >= connections {
+            error!("--debug-capture-connection {} is not one of the {} connections",
+                   target.connection,
+                   connections);
+            return;
+        }
     }
 
-    let evconfig = mio::EventLoopConfig::default();
+    // each server needs a connection in both arms
+    if let Some(option) = ab {
+        if let Some(server) = servers.iter().find(|s| s.connections * config.threads < 2) {
+            error!("--ab-socket-option {} needs at least 2 connections to server {}",
+                   option.name(),
+                   server.address);
+            return;
+        }
+    }
 
     info!("-----");
     info!("Config:");
-    for server in matches.opt_strs("server") {
-        info!("Config: Server: {} Protocol: {}",
-              server,
-              config.protocol_config.protocol.name());
+    if mode != Mode::Measure {
+        info!("Config: Mode: {}", mode.name());
+    }
+    for server in &servers {
+        info!("Config: Server: {} Protocol: {} Transport: {} Weight: {} Connections: {}",
+              server.address,
+              config.protocol_config.protocol.name(),
+              if server.udp {
+                  "udp"
+              } else {
+                  "tcp"
+              },
+              server.weight,
+              server.connections);
     }
     info!("Config: IP: {:?} TCP_NODELAY: {}",
           internet_protocol,
           config.tcp_nodelay);
-    info!("Config: Threads: {} Connections: {}",
+    info!("Config: Threads: {} Connections: {} Pipeline: {}",
           config.threads,
-          config.connections);
+          config.connections,
+          config.pipeline);
     info!("Config: Windows: {} Duration: {}",
           config.windows,
           config.duration);
+    if let Some(max_duration) = config.max_duration {
+        info!("Config: Max Duration: {}", max_duration);
+        // warmup is one extra window on top of the configured windows
+        if (config.windows + 1) * config.duration > max_duration {
+            warn!("Config: run of {} s will be truncated by max duration of {} s",
+                  (config.windows + 1) * config.duration,
+                  max_duration);
+        }
+    }
+    info!("Config: Seed: {}", config.seed);
+    if let Some(ramp) = config.ramp {
+        info!("Config: Ramp: Start: {} End: {} Step: {}",
+              ramp.start,
+              ramp.end,
+              ramp.step);
+        if ramp.steps() > config.windows {
+            warn!("Config: ramp of {} steps will be cut short by {} windows",
+                  ramp.steps(),
+                  config.windows);
+        }
+    }
+    if config.event_capacity.is_some() || config.timer_capacity.is_some() {
+        info!("Config: Event Capacity: {} Timer Capacity: {}",
+              config.event_capacity.map_or("default".to_owned(), |c| c.to_string()),
+              config.timer_capacity.map_or("default".to_owned(), |c| c.to_string()));
+    }
+    if let Some(slow) = config.slow_threshold {
+        info!("Config: Slow Threshold: {} ms", slow);
+    }
+    if let Some(timeout) = config.timeout {
+        info!("Config: Timeout: {} ms Retries: {}", timeout, config.retries);
+    }
+    info!("Config: Histogram Max: {} ms Heatmap Max: {} ms",
+          config.histogram_max,
+          config.heatmap_max);
+    if config.interleave != Interleave::Free {
+        info!("Config: Interleave: {}", config.interleave);
+    }
+    if let Some(option) = ab {
+        info!("Config: A/B: {} on in arm A, off in arm B", option.name());
+    }
+    if config.establish_threshold < 100.0 || config.include_connection_ramp {
+        info!("Config: Establish Threshold: {} % Include Connection Ramp: {}",
+              config.establish_threshold,
+              config.include_connection_ramp);
+    }
+    if let Some(ref target) = capture {
+        info!("Config: Capture: connection {} up to {} bytes into {}",
+              target.connection,
+              target.bytes,
+              target.path);
+    }
+
+    // a scrape-only run serves the listen address without any clients
+    let threads = if mode == Mode::Scrape {
+        0
+    } else {
+        config.threads
+    };
+    // names of the ops of each workload which is a sequence, broken down in the results
+    let sequences: Vec<Vec<String>> = config.protocol_config
+                                            .workloads
+                                            .iter()
+                                            .map(|w| w.gen.ops())
+                                            .collect();
+    let budget = Budget {
+        connections: connections,
+        client_threads: threads,
+        generator_threads: if mode != Mode::Measure {
+            0
+        } else if config.replay.is_some() {
+            1
+        } else {
+            config.protocol_config.workloads.len()
+        },
+        listeners: if listen.is_some() {
+            1
+        } else {
+            0
+        },
+        outputs: ["trace",
+                  "size-trace",
+                  "waterfall",
+                  "output",
+                  "csv",
+                  "history",
+                  "debug-capture-connection"]
+                     .iter()
+                     .filter(|&&o| matches.opt_present(o))
+                     .count(),
+        connection_memory: connection::buffer_memory(),
+        stats_memory: stats::memory(config.duration,
+                                    config.windows,
+                                    config.histogram_max as u64 * 1_000_000,
+                                    config.heatmap_max as u64 * 1_000_000,
+                                    servers.len(),
+                                    sequences.iter()
+                                             .filter(|ops| !ops.is_empty())
+                                             .map(|ops| ops.len() + 1)
+                                             .sum()),
+    };
+    let limits = Limits::host();
+    info!("-----");
+    budget.report(&limits);
+    let mut impossible = false;
+    for problem in budget.check(&limits) {
+        match problem {
+            Problem::Impossible(reason) => {
+                error!("Budget: {}", reason);
+                impossible = true;
+            }
+            Problem::Tight(reason) => warn!("Budget: {}", reason),
+        }
+    }
+    if impossible {
+        return;
+    }
+
     info!("-----");
     info!("Workload:");
 
-    workload::launch_workloads(config.protocol_config.workloads, work_queue.clone());
+    // aggregate rate offered by the workloads, stepped by the stats receiver when ramping
+    let ramp = config.ramp.map(|r| (r, Arc::new(AtomicUsize::new(r.rate(0)))));
+
+    // timed out requests waiting to be resent, only with retries enabled
+    let retry_queue = if config.retries > 0 {
+        Some(BoundedQueue::<WorkItem>::with_capacity(BUCKET_SIZE))
+    } else {
+        None
+    };
+
+    let workloads: Vec<String> = config.protocol_config
+                                       .workloads
+                                       .iter()
+                                       .map(|w| w.name.clone())
+                                       .collect();
+
+    // the share of requests each workload should make, checked against those it did make
+    let mix = Mix::new(config.protocol_config
+                             .workloads
+                             .iter()
+                             .map(|w| (w.name.clone(), w.rate))
+                             .collect(),
+                       config.mix_tolerance);
+
+    if mode != Mode::Measure {
+        info!("Workload: none, {}", mode.name());
+    } else if let Some(ref file) = config.replay {
+        match replay::load(file) {
+            Ok(requests) => {
+                info!("Replay: {} Requests: {} Loop: {}",
+                      file,
+                      requests.len(),
+                      config.replay_loop);
+                replay::launch_replay(requests, work_queue.clone(), config.replay_loop);
+            }
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        }
+    } else {
+        workload::launch_workloads(config.protocol_config.workloads,
+                                   work_queue.clone(),
+                                   retry_queue.clone(),
+                                   config.seed,
+                                   ramp.as_ref().map(|&(_, ref offered)| offered.clone()));
+    }
+
+    let timeout = config.timeout.map(|t| {
+        Timeout {
+            duration: t as u64 * 1_000_000,
+            retries: config.retries,
+            retry_queue: retry_queue,
+        }
+    });
+
+    // explains a run of protocol errors on the first responses
+    let diagnosis = Arc::new(Diagnosis::new(config.protocol_config.protocol.name(),
+                                            connections,
+                                            matches.opt_present("abort-on-mismatch")));
+
+    // appended once the run completes, with its exit status
+    let history = if matches.opt_present("history") {
+        let path = match history_path(&matches) {
+            Ok(p) => p,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        let artifacts = ["trace", "size-trace", "waterfall", "output", "csv"]
+                            .iter()
+                            .filter_map(|&a| matches.opt_str(a).map(|file| (a, file)))
+                            .collect();
+        Some(History {
+            path: path,
+            config_hash: matches.opt_str("config").and_then(|file| history::file_hash(&file)),
+            tags: matches.opt_strs("tag"),
+            protocol: config.protocol_config.protocol.name().to_owned(),
+            servers: servers.iter().map(|s| s.address.clone()).collect(),
+            artifacts: artifacts,
+        })
+    } else {
+        if matches.opt_present("tag") {
+            warn!("--tag only labels the run in the --history file");
+        }
+        None
+    };
 
     let (stats_sender, stats_receiver) = mpsc::channel();
+    let (tcp_sender, tcp_receiver) = mpsc::channel();
 
-    let receiver = stats::Receiver::new(stats_receiver);
+    let (connections_sender, connections_receiver) = mpsc::channel();
+
+    let receiver = stats::Receiver::new(stats_receiver,
+                                        tcp_receiver,
+                                        connections_receiver,
+                                        servers.iter().map(|s| s.address.clone()).collect(),
+                                        workloads.clone(),
+                                        sequences);
+
+    let assertions = Assertions {
+        p99: config.assert_p99,
+        p999: config.assert_p999,
+        error_rate: config.assert_error_rate,
+        min_rate: config.assert_min_rate,
+        slow_rate: config.assert_slow_rate,
+    };
+
+    signal::install();
 
     info!("-----");
     info!("Connecting...");
+    // the connection ramp is timed from the first client starting to connect
+    let progress = Arc::new(Progress::new(if mode == Mode::Scrape {
+                                              0
+                                          } else {
+                                              connections
+                                          },
+                                          config.establish_threshold));
     // spawn client threads
-    for i in 0..config.threads {
+    let mut clients = Vec::new();
+    for i in 0..threads {
         info!("Client: {}", i);
 
+        let (control_tx, control_rx) = mpsc::channel();
         let client_config = ClientConfig {
-            servers: matches.opt_strs("server"),
-            connections: config.connections,
+            id: i,
+            servers: servers.clone(),
             stats_tx: stats_sender.clone(),
+            tcp_tx: if tcp_info {
+                Some(tcp_sender.clone())
+            } else {
+                None
+            },
+            tcp_interval: config.duration as u64 * 1000,
+            // only reported when they can be fetched from the listen address
+            connections_tx: if listen.is_some() {
+                Some(connections_sender.clone())
+            } else {
+                None
+            },
             client_protocol: config.protocol_config.protocol.clone(),
             internet_protocol: internet_protocol,
             work_rx: work_queue.clone(),
             tcp_nodelay: config.tcp_nodelay,
+            pipeline: config.pipeline,
+            interleave: config.interleave,
+            workloads: workloads.len(),
+            ab: ab,
+            capture: capture.clone(),
+            progress: progress.clone(),
+            timeout: timeout.clone(),
+            diagnosis: diagnosis.clone(),
             mio_config: evconfig.clone(),
+            control_tx: control_tx,
         };
 
-        thread::spawn(move || {
+        let handle = thread::spawn(move || {
             start(client_config);
         });
+        clients.push((handle, control_rx));
+    }
+
+    if mode == Mode::Preload {
+        // a prepare request may be slow, such as a flush, so it has a whole window to finish
+        let answered = receiver.preload(preload, config.duration as u64 * 1_000_000_000);
+        stop_clients(clients);
+        if !answered {
+            process::exit(1);
+        }
+        return;
     }
 
-    receiver.run(config.duration,
-                 config.windows,
-                 trace,
-                 waterfall,
-                 (config.threads * config.connections * matches.opt_count("server")),
-                 listen);
+    let run_config = RunConfig {
+        duration: config.duration,
+        windows: config.windows,
+        max_duration: config.max_duration,
+        trace: trace,
+        size_trace: size_trace,
+        waterfall: waterfall,
+        max_closed: config.threads * config.connections * servers.len(),
+        listen: listen,
+        output: output,
+        csv: csv,
+        assertions: assertions,
+        ramp: ramp,
+        percentiles: config.percentiles,
+        slow_threshold: config.slow_threshold.map(|t| t as u64 * 1_000_000),
+        slow_log: config.slow_log,
+        histogram_max: config.histogram_max as u64 * 1_000_000,
+        heatmap_max: config.heatmap_max as u64 * 1_000_000,
+        mix: mix,
+        ab: ab,
+        progress: progress,
+        include_ramp: config.include_connection_ramp,
+        check_invariants: matches.opt_present("check-invariants"),
+    };
+    let (outcome, summary) = receiver.run(run_config);
+
+    stop_clients(clients);
+
+    let status = outcome.status();
+    if let Some(history) = history {
+        if let Err(e) = history.append(&summary, status) {
+            error!("{}", e);
+        }
+    }
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(weight: usize) -> Server {
+        Server {
+            address: "127.0.0.1:11211".to_owned(),
+            weight: weight,
+            connections: 0,
+            udp: false,
+        }
+    }
+
+    fn connections(servers: &[Server]) -> Vec<usize> {
+        servers.iter().map(|s| s.connections).collect()
+    }
+
+    #[test]
+    fn server_weights() {
+        let s = parse_server("127.0.0.1:11211").unwrap();
+        assert_eq!((s.address.as_str(), s.weight, s.udp), ("127.0.0.1:11211", 1, false));
+
+        let s = parse_server("udp://127.0.0.1:11211:3").unwrap();
+        assert_eq!((s.address.as_str(), s.weight, s.udp), ("127.0.0.1:11211", 3, true));
+
+        assert!(parse_server("127.0.0.1:11211:0").is_err());
+        assert!(parse_server("127.0.0.1:11211:x").is_err());
+        assert!(parse_server("127.0.0.1:11211:2:3").is_err());
+        assert!(parse_server("127.0.0.1").is_err());
+    }
+
+    #[test]
+    fn server_ipv6() {
+        // the colons inside the brackets aren't mistaken for a weight
+        let s = parse_server("[::1]:11211").unwrap();
+        assert_eq!((s.address.as_str(), s.weight), ("[::1]:11211", 1));
+
+        let s = parse_server("[::1]:11211:2").unwrap();
+        assert_eq!((s.address.as_str(), s.weight), ("[::1]:11211", 2));
+
+        assert!(parse_server("[::1]:11211:0").is_err());
+    }
+
+    fn capture(args: Vec<&str>) -> Result<Option<Target>, String> {
+        capture_target(&opts().parse(args).unwrap())
+    }
+
+    #[test]
+    fn capture_targets() {
+        assert!(capture(vec![]).unwrap().is_none());
+        // the limits mean nothing without a connection to capture
+        assert!(capture(vec!["--debug-capture-bytes", "10"]).unwrap().is_none());
+
+        let target = capture(vec!["--debug-capture-connection", "3"]).unwrap().unwrap();
+        assert_eq!(target.connection, 3);
+        assert_eq!(target.bytes, capture::DEFAULT_BYTES);
+        assert_eq!(target.path, "connection-3.capture");
+
+        let target = capture(vec!["--debug-capture-connection",
+                                  "0",
+                                  "--debug-capture-bytes",
+                                  "512",
+                                  "--debug-capture-file",
+                                  "first.capture"])
+                         .unwrap()
+                         .unwrap();
+        assert_eq!((target.connection, target.bytes), (0, 512));
+        assert_eq!(target.path, "first.capture");
+
+        assert!(capture(vec!["--debug-capture-connection", "x"]).is_err());
+        assert!(capture(vec!["--debug-capture-connection", "1", "--debug-capture-bytes", "0"])
+                    .is_err());
+    }
+
+    #[test]
+    fn connections_by_weight() {
+        let mut servers = vec![server(1), server(3)];
+        allocate_connections(&mut servers, 8);
+        assert_eq!(connections(&servers), vec![2, 6]);
+
+        allocate_connections(&mut servers, 0);
+        assert_eq!(connections(&servers), vec![0, 0]);
+    }
+
+    #[test]
+    fn connections_remainder() {
+        // the remainder goes to the largest fractional shares
+        let mut servers = vec![server(1), server(2)];
+        allocate_connections(&mut servers, 10);
+        assert_eq!(connections(&servers), vec![3, 7]);
+
+        // and is never more than one connection per server
+        let mut servers = vec![server(1), server(1), server(1)];
+        allocate_connections(&mut servers, 5);
+        assert_eq!(connections(&servers), vec![2, 2, 1]);
+        assert_eq!(connections(&servers).iter().fold(0, |a, c| a + c), 5);
+    }
 }
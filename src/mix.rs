@@ -0,0 +1,198 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+/// Share of the requests in a window which one workload made, against the share intended by
+/// the configured rates, both in percent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drift {
+    pub workload: usize,
+    pub window: usize,
+    pub intended: f64,
+    pub realized: f64,
+}
+
+impl Drift {
+    /// Distance between the realized and intended shares, in percentage points
+    pub fn points(&self) -> f64 {
+        (self.realized - self.intended).abs()
+    }
+}
+
+/// Compares the realized mix of requests each window with the mix the workload rates intend
+///
+/// A workload starved of queue space, or a generator slower than its rate, makes fewer requests
+/// than its share and shifts the mix towards the others.
+pub struct Mix {
+    names: Vec<String>,
+    // fraction of the requests each workload should make
+    intended: Vec<f64>,
+    // percentage points a share may drift before it is reported
+    tolerance: f64,
+    counts: Vec<u64>,
+    worst: Option<Drift>,
+}
+
+impl Mix {
+    /// Mix of the workloads by name and rate, None when there is no mix to compare
+    ///
+    /// Without rates the workloads share equally. A workload without a rate among others with
+    /// them is unlimited, so there is no intended share to compare against.
+    pub fn new(workloads: Vec<(String, usize)>, tolerance: f64) -> Option<Mix> {
+        if workloads.len() < 2 {
+            return None;
+        }
+        let total = workloads.iter().fold(0, |sum, &(_, rate)| sum + rate);
+        let intended = if total == 0 {
+            vec![1.0 / workloads.len() as f64; workloads.len()]
+        } else if workloads.iter().any(|&(_, rate)| rate == 0) {
+            debug!("mix: a workload without a rate has no intended share");
+            return None;
+        } else {
+            workloads.iter().map(|&(_, rate)| rate as f64 / total as f64).collect()
+        };
+        Some(Mix {
+            counts: vec![0; workloads.len()],
+            names: workloads.into_iter().map(|(name, _)| name).collect(),
+            intended: intended,
+            tolerance: tolerance,
+            worst: None,
+        })
+    }
+
+    /// Count a request made by the workload
+    pub fn record(&mut self, workload: usize) {
+        if let Some(count) = self.counts.get_mut(workload) {
+            *count += 1;
+        }
+    }
+
+    /// Discard the counts of the current window, eg: the warmup
+    pub fn clear(&mut self) {
+        for count in &mut self.counts {
+            *count = 0;
+        }
+    }
+
+    /// Shares of each workload in the window which just ended, empty if it had no requests
+    pub fn end_window(&mut self, window: usize) -> Vec<Drift> {
+        let total = self.counts.iter().fold(0, |sum, c| sum + c);
+        let mut drifts = Vec::new();
+        if total > 0 {
+            for (i, count) in self.counts.iter().enumerate() {
+                drifts.push(Drift {
+                    workload: i,
+                    window: window,
+                    intended: 100.0 * self.intended[i],
+                    realized: 100.0 * *count as f64 / total as f64,
+                });
+            }
+        }
+        for drift in &drifts {
+            if self.worst.as_ref().map_or(true, |w| drift.points() > w.points()) {
+                self.worst = Some(drift.clone());
+            }
+        }
+        self.clear();
+        drifts
+    }
+
+    /// Whether the share drifted further than the tolerance
+    pub fn exceeds(&self, drift: &Drift) -> bool {
+        drift.points() > self.tolerance
+    }
+
+    pub fn name(&self, workload: usize) -> &str {
+        &self.names[workload]
+    }
+
+    /// Largest drift of any workload in any window
+    pub fn worst(&self) -> Option<&Drift> {
+        self.worst.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workloads(rates: &[usize]) -> Vec<(String, usize)> {
+        rates.iter().enumerate().map(|(i, r)| (format!("w{}", i), *r)).collect()
+    }
+
+    fn record(mix: &mut Mix, workload: usize, count: usize) {
+        for _ in 0..count {
+            mix.record(workload);
+        }
+    }
+
+    #[test]
+    fn matching_mix_has_no_drift() {
+        let mut mix = Mix::new(workloads(&[9000, 1000]), 5.0).unwrap();
+        record(&mut mix, 0, 900);
+        record(&mut mix, 1, 100);
+        let drifts = mix.end_window(1);
+        assert_eq!(drifts.len(), 2);
+        assert!(drifts.iter().all(|d| d.points() < 1e-9));
+        assert!(!drifts.iter().any(|d| mix.exceeds(d)));
+    }
+
+    #[test]
+    fn starved_workload_drifts() {
+        // a 90:10 mix realized as 70:30, as when the larger workload can't get queue space
+        let mut mix = Mix::new(workloads(&[9000, 1000]), 5.0).unwrap();
+        record(&mut mix, 0, 700);
+        record(&mut mix, 1, 300);
+        let drifts = mix.end_window(1);
+        assert!((drifts[0].realized - 70.0).abs() < 1e-9);
+        assert!((drifts[1].intended - 10.0).abs() < 1e-9);
+        assert!(drifts.iter().all(|d| mix.exceeds(d)));
+        assert!((mix.worst().unwrap().points() - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fully_starved_workload_drifts_by_its_share() {
+        let mut mix = Mix::new(workloads(&[0, 0, 0, 0]), 10.0).unwrap();
+        record(&mut mix, 0, 100);
+        record(&mut mix, 1, 100);
+        record(&mut mix, 2, 100);
+        let drifts = mix.end_window(1);
+        assert!((drifts[3].points() - 25.0).abs() < 1e-9);
+        assert!(mix.exceeds(&drifts[3]));
+    }
+
+    #[test]
+    fn worst_drift_is_kept_across_windows() {
+        let mut mix = Mix::new(workloads(&[500, 500]), 5.0).unwrap();
+        record(&mut mix, 0, 80);
+        record(&mut mix, 1, 20);
+        mix.end_window(1);
+        record(&mut mix, 0, 50);
+        record(&mut mix, 1, 50);
+        mix.end_window(2);
+        // an empty window has no shares
+        assert!(mix.end_window(3).is_empty());
+
+        let worst = mix.worst().unwrap();
+        assert_eq!(worst.window, 1);
+        assert!((worst.points() - 30.0).abs() < 1e-9);
+        assert_eq!(mix.name(worst.workload), "w0");
+    }
+
+    #[test]
+    fn no_mix_to_compare() {
+        assert!(Mix::new(workloads(&[1000]), 5.0).is_none());
+        assert!(Mix::new(workloads(&[1000, 0]), 5.0).is_none());
+    }
+}
@@ -16,6 +16,7 @@
 extern crate mio;
 
 use mio::tcp::TcpStream;
+use mio::udp::UdpSocket;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::fmt;
 
@@ -75,3 +76,30 @@ pub fn to_mio_tcp_stream<T: ToSocketAddrs>(addr: T,
         Err(_) => Err("Could not resolve"),
     }
 }
+
+/// Bind a datagram socket to send to the server, returning it with the server's address
+pub fn to_mio_udp_socket<T: ToSocketAddrs>(addr: T,
+                                           proto: InternetProtocol)
+                                           -> Result<(UdpSocket, SocketAddr), &'static str> {
+    match addr.to_socket_addrs() {
+        Ok(r) => {
+            for a in r {
+                let local = match a {
+                    SocketAddr::V4(_) if proto != InternetProtocol::IpV6 => "0.0.0.0:0",
+                    SocketAddr::V6(_) if proto != InternetProtocol::IpV4 => "[::]:0",
+                    _ => continue,
+                };
+                match UdpSocket::bound(&local.parse().unwrap()) {
+                    Ok(s) => {
+                        return Ok((s, a));
+                    }
+                    Err(e) => {
+                        debug!("udp bind error: {}", e);
+                    }
+                }
+            }
+            Err("Could not bind")
+        }
+        Err(_) => Err("Could not resolve"),
+    }
+}
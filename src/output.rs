@@ -0,0 +1,406 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+extern crate time;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use annotate::Annotation;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Results for a single window, or for the whole run
+pub struct Record {
+    pub window: usize,
+    pub offered_rate: Option<usize>,
+    pub start: time::Timespec,
+    pub stop: time::Timespec,
+    pub requests: u64,
+    pub responses: u64,
+    pub successes: u64,
+    pub errors: u64,
+    /// errors with a 4xx status, counted in the errors
+    pub client_errors: u64,
+    /// errors with a 5xx status, counted in the errors
+    pub server_errors: u64,
+    pub corrupt: u64,
+    /// hits with a valid value but the wrong flags
+    pub flag_mismatches: u64,
+    /// reads in a sequence which found an earlier sequence's value
+    pub stale: u64,
+    pub timeouts: u64,
+    pub retried: u64,
+    pub gave_up: u64,
+    /// responses over the slow threshold, when one is set
+    pub slow: Option<u64>,
+    pub rate: f64,
+    pub min: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub p9999: u64,
+    pub max: u64,
+    pub tcp_rtt_p50: Option<u64>,
+    pub tcp_rtt_p99: Option<u64>,
+    pub tcp_retransmits: Option<u64>,
+    pub truncated: Option<bool>,
+    pub interrupted: Option<bool>,
+    /// whether the window ended before the connections were established
+    pub connection_ramp: Option<bool>,
+    /// nanoseconds taken to establish every connection, in the summary once they all were
+    pub establish_time: Option<u64>,
+    /// address of the server, for the results of one server of a multi-server run
+    pub server: Option<String>,
+    /// name of the sequence workload or of one of its ops, for the results of just that
+    pub sequence: Option<String>,
+}
+
+impl Record {
+    // named values in output order, None where a value wasn't collected
+    fn fields(&self, kind: &str) -> Vec<(&'static str, Option<String>)> {
+        vec![("type", Some(format!("\"{}\"", kind))),
+             ("window", Some(format!("{}", self.window))),
+             ("offered_rate", self.offered_rate.map(|v| format!("{}", v))),
+             ("start", Some(format!("\"{}\"", timestamp(self.start)))),
+             ("stop", Some(format!("\"{}\"", timestamp(self.stop)))),
+             ("requests", Some(format!("{}", self.requests))),
+             ("responses", Some(format!("{}", self.responses))),
+             ("successes", Some(format!("{}", self.successes))),
+             ("errors", Some(format!("{}", self.errors))),
+             ("client_errors", Some(format!("{}", self.client_errors))),
+             ("server_errors", Some(format!("{}", self.server_errors))),
+             ("corrupt", Some(format!("{}", self.corrupt))),
+             ("flag_mismatches", Some(format!("{}", self.flag_mismatches))),
+             ("stale", Some(format!("{}", self.stale))),
+             ("timeouts", Some(format!("{}", self.timeouts))),
+             ("retried", Some(format!("{}", self.retried))),
+             ("gave_up", Some(format!("{}", self.gave_up))),
+             ("slow", self.slow.map(|v| format!("{}", v))),
+             ("rate", Some(format!("{:.*}", 2, self.rate))),
+             ("min", Some(format!("{}", self.min))),
+             ("p50", Some(format!("{}", self.p50))),
+             ("p90", Some(format!("{}", self.p90))),
+             ("p99", Some(format!("{}", self.p99))),
+             ("p999", Some(format!("{}", self.p999))),
+             ("p9999", Some(format!("{}", self.p9999))),
+             ("max", Some(format!("{}", self.max))),
+             ("tcp_rtt_p50", self.tcp_rtt_p50.map(|v| format!("{}", v))),
+             ("tcp_rtt_p99", self.tcp_rtt_p99.map(|v| format!("{}", v))),
+             ("tcp_retransmits", self.tcp_retransmits.map(|v| format!("{}", v))),
+             ("truncated", self.truncated.map(|v| format!("{}", v))),
+             ("interrupted", self.interrupted.map(|v| format!("{}", v))),
+             ("connection_ramp", self.connection_ramp.map(|v| format!("{}", v))),
+             ("establish_time", self.establish_time.map(|v| format!("{}", v))),
+             ("server", self.server.as_ref().map(|v| format!("\"{}\"", v))),
+             ("sequence", self.sequence.as_ref().map(|v| format!("\"{}\"", v)))]
+    }
+}
+
+/// Machine-readable results file, one record per window plus a summary
+pub struct Output {
+    writer: BufWriter<File>,
+    format: Format,
+    header: bool,
+}
+
+impl Output {
+    pub fn open(path: &str, format: Format) -> Result<Output, String> {
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening output {}: {}", path, e)),
+        };
+        Ok(Output {
+            writer: BufWriter::new(file),
+            format: format,
+            header: false,
+        })
+    }
+
+    /// Write the record for a completed window
+    pub fn window(&mut self, record: &Record) {
+        self.write_record(record.fields("window"));
+        self.flush();
+    }
+
+    /// Write the record for the whole run
+    pub fn summary(&mut self, record: &Record) {
+        self.write_record(record.fields("summary"));
+        self.flush();
+    }
+
+    /// Write the record of one server for a completed window
+    pub fn server_window(&mut self, record: &Record) {
+        self.write_record(record.fields("server_window"));
+        self.flush();
+    }
+
+    /// Write the record of one server for the whole run
+    pub fn server_summary(&mut self, record: &Record) {
+        self.write_record(record.fields("server_summary"));
+        self.flush();
+    }
+
+    /// Write the record of a sequence workload, or one of its ops, for a completed window
+    pub fn sequence_window(&mut self, record: &Record) {
+        self.write_record(record.fields("sequence_window"));
+        self.flush();
+    }
+
+    /// Write the record of a sequence workload, or one of its ops, for the whole run
+    pub fn sequence_summary(&mut self, record: &Record) {
+        self.write_record(record.fields("sequence_summary"));
+        self.flush();
+    }
+
+    /// Write an annotation as it's posted, only JSON has room for a record of its own
+    pub fn annotation(&mut self, annotation: &Annotation) {
+        if self.format == Format::Json {
+            self.write_line(annotation.json());
+            self.flush();
+        }
+    }
+
+    fn write_record(&mut self, fields: Vec<(&'static str, Option<String>)>) {
+        match self.format {
+            Format::Json => {
+                let values: Vec<String> = fields.into_iter()
+                                                .filter_map(|(name, value)| {
+                                                    value.map(|v| format!("\"{}\":{}", name, v))
+                                                })
+                                                .collect();
+                let line = format!("{{{}}}", values.join(","));
+                self.write_line(line);
+            }
+            Format::Csv => {
+                if !self.header {
+                    let names: Vec<&str> = fields.iter().map(|&(name, _)| name).collect();
+                    let header = names.join(",");
+                    self.write_line(header);
+                    self.header = true;
+                }
+                // strings are quoted for JSON, CSV fields don't need it
+                let values: Vec<String> = fields.into_iter()
+                                                .map(|(_, value)| {
+                                                    value.unwrap_or_default().replace("\"", "")
+                                                })
+                                                .collect();
+                let line = values.join(",");
+                self.write_line(line);
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: String) {
+        if let Err(e) = writeln!(self.writer, "{}", line) {
+            debug!("output: write failed: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            debug!("output: flush failed: {}", e);
+        }
+    }
+}
+
+/// Spreadsheet-friendly CSV of throughput and latency, one row per window
+pub struct WindowCsv {
+    writer: BufWriter<File>,
+}
+
+impl WindowCsv {
+    /// Create the file and write the header, so it can be followed while the test runs
+    pub fn open(path: &str) -> Result<WindowCsv, String> {
+        let file = match File::create(path) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Error opening csv {}: {}", path, e)),
+        };
+        let mut csv = WindowCsv { writer: BufWriter::new(file) };
+        csv.write_line("window,timestamp,requests,successes,errors,p50,p90,p99,max,\
+                        connection_ramp");
+        Ok(csv)
+    }
+
+    /// Append the row for a completed window
+    pub fn window(&mut self, record: &Record) {
+        let line = format!("{},{},{},{},{},{},{},{},{},{}",
+                           record.window,
+                           timestamp(record.stop),
+                           record.requests,
+                           record.successes,
+                           record.errors,
+                           record.p50,
+                           record.p90,
+                           record.p99,
+                           record.max,
+                           record.connection_ramp.unwrap_or(false));
+        self.write_line(&line);
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.writer, "{}", line) {
+            debug!("csv: write failed: {}", e);
+        }
+        if let Err(e) = self.writer.flush() {
+            debug!("csv: flush failed: {}", e);
+        }
+    }
+}
+
+/// Wall-clock time in RFC 3339 form
+pub fn timestamp(t: time::Timespec) -> String {
+    format!("{}", time::at_utc(t).rfc3339())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use time;
+
+    fn record() -> Record {
+        let start = time::Timespec::new(1_500_000_000, 0);
+        Record {
+            window: 2,
+            offered_rate: None,
+            start: start,
+            stop: start + time::Duration::seconds(60),
+            requests: 1000,
+            responses: 998,
+            successes: 990,
+            errors: 8,
+            client_errors: 3,
+            server_errors: 1,
+            corrupt: 0,
+            flag_mismatches: 0,
+            stale: 0,
+            timeouts: 2,
+            retried: 0,
+            gave_up: 0,
+            slow: Some(3),
+            rate: 16.666,
+            min: 1,
+            p50: 100,
+            p90: 200,
+            p99: 300,
+            p999: 400,
+            p9999: 500,
+            max: 600,
+            tcp_rtt_p50: None,
+            tcp_rtt_p99: None,
+            tcp_retransmits: None,
+            truncated: None,
+            interrupted: None,
+            connection_ramp: Some(false),
+            establish_time: None,
+            server: Some("127.0.0.1:11211".to_owned()),
+            sequence: None,
+        }
+    }
+
+    fn lines(path: &str) -> Vec<String> {
+        let mut contents = String::new();
+        fs::File::open(path).unwrap().read_to_string(&mut contents).unwrap();
+        let _ = fs::remove_file(path);
+        contents.lines().map(|l| l.to_owned()).collect()
+    }
+
+    fn temp(name: &str) -> String {
+        env::temp_dir().join(name).to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn json_records() {
+        let path = temp("rpc-perf-output-test.json");
+        {
+            let mut output = Output::open(&path, Format::Json).unwrap();
+            output.window(&record());
+            output.server_summary(&record());
+        }
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("{\"type\":\"window\",\"window\":2,\"start\":"));
+        assert!(lines[0].contains("\"start\":\"2017-07-14T02:40:00Z\""));
+        assert!(lines[0].contains(",\"requests\":1000,"));
+        assert!(lines[0].contains(",\"errors\":8,\"client_errors\":3,\"server_errors\":1,"));
+        assert!(lines[0].contains(",\"rate\":16.67,"));
+        assert!(lines[0].contains(",\"slow\":3,"));
+        assert!(lines[0].ends_with(",\"server\":\"127.0.0.1:11211\"}"));
+        // values which weren't collected are left out
+        assert!(!lines[0].contains("offered_rate"));
+        assert!(!lines[0].contains("tcp_rtt_p50"));
+        assert!(lines[1].starts_with("{\"type\":\"server_summary\","));
+    }
+
+    #[test]
+    fn csv_records() {
+        let path = temp("rpc-perf-output-test.csv");
+        {
+            let mut output = Output::open(&path, Format::Csv).unwrap();
+            output.window(&record());
+            output.summary(&record());
+        }
+        let lines = lines(&path);
+        assert_eq!(lines.len(), 3);
+        let header: Vec<&str> = lines[0].split(',').collect();
+        let row: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(header.len(), row.len());
+        assert_eq!(header[0], "type");
+        assert_eq!(row[0], "window");
+        let value = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(value("requests"), "1000");
+        assert_eq!(value("client_errors"), "3");
+        assert_eq!(value("server_errors"), "1");
+        assert_eq!(value("offered_rate"), "");
+        assert_eq!(value("server"), "127.0.0.1:11211");
+        assert!(lines[2].starts_with("summary,2,"));
+    }
+
+    #[test]
+    fn window_csv_rows() {
+        let path = temp("rpc-perf-window-test.csv");
+        {
+            let mut csv = WindowCsv::open(&path).unwrap();
+            csv.window(&record());
+        }
+        let lines = lines(&path);
+        assert_eq!(lines,
+                   vec!["window,timestamp,requests,successes,errors,p50,p90,p99,max,\
+                         connection_ramp"
+                            .to_owned(),
+                        "2,2017-07-14T02:41:00Z,1000,990,8,100,200,300,600,false".to_owned()]);
+    }
+}
@@ -0,0 +1,294 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+extern crate time;
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use cfgtypes::{ProtocolGen, ProtocolParse};
+use connection;
+use net::InternetProtocol;
+use stats::Status;
+
+/// Exit status of a check which passed, in the Nagios plugin convention
+pub const OK: i32 = 0;
+/// Some of the requests failed
+pub const WARNING: i32 = 1;
+/// None of the requests succeeded, or the server couldn't be reached
+pub const CRITICAL: i32 = 2;
+/// The probe couldn't be run, eg: a bad config
+pub const UNKNOWN: i32 = 3;
+
+/// Requests sent by `--probe` without a count
+pub const DEFAULT_REQUESTS: usize = 10;
+/// Time allowed for the whole probe, including the connect, in milliseconds
+pub const DEFAULT_DEADLINE: u64 = 1_000;
+
+const ONE_MILLISECOND: u64 = 1_000_000;
+
+/// Results of a probe
+#[derive(Debug, Default)]
+pub struct Report {
+    pub server: String,
+    pub requests: usize,
+    pub successes: usize,
+    pub errors: usize,
+    pub timeouts: usize,
+    /// nanoseconds from each request being written to its response
+    pub latencies: Vec<u64>,
+    /// nanoseconds taken by the whole probe
+    pub elapsed: u64,
+    /// why the probe stopped early
+    pub error: Option<String>,
+}
+
+impl Report {
+    /// Exit status of the check
+    pub fn status(&self) -> i32 {
+        if self.requests > 0 && self.successes == self.requests {
+            OK
+        } else if self.successes > 0 {
+            WARNING
+        } else {
+            CRITICAL
+        }
+    }
+
+    /// Latency in nanoseconds at the percentile of the responses, by nearest rank
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies.clone();
+        sorted.sort();
+        let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.max(1) - 1])
+    }
+
+    /// The results as a single line JSON object
+    pub fn json(&self) -> String {
+        let status = match self.status() {
+            OK => "OK",
+            WARNING => "WARNING",
+            _ => "CRITICAL",
+        };
+        let latency = |p| self.percentile(p).map_or("null".to_owned(), |v| v.to_string());
+        let mut json = format!("{{\"status\":\"{}\",\"server\":\"{}\",\"requests\":{},\
+                                \"successes\":{},\"errors\":{},\"timeouts\":{},\"p50\":{},\
+                                \"p99\":{},\"elapsed\":{}",
+                               status,
+                               self.server,
+                               self.requests,
+                               self.successes,
+                               self.errors,
+                               self.timeouts,
+                               latency(50.0),
+                               latency(99.0),
+                               self.elapsed);
+        if let Some(ref error) = self.error {
+            json.push_str(&format!(",\"error\":\"{}\"", error.replace("\"", "'")));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// Send `requests` requests one at a time on a single connection, stopping at the deadline
+///
+/// The requests are taken from each workload in turn. Requests which couldn't be sent or
+/// answered before the deadline count as timeouts, and any other failure of the connection
+/// ends the probe with the remaining requests counted as errors.
+pub fn run(server: &str,
+           proto: InternetProtocol,
+           workloads: &mut [Box<ProtocolGen>],
+           protocol: &ProtocolParse,
+           requests: usize,
+           deadline_ms: u64)
+           -> Report {
+    let start = time::precise_time_ns();
+    let deadline = start + deadline_ms * ONE_MILLISECOND;
+    let mut report = Report {
+        server: server.to_owned(),
+        requests: requests,
+        ..Report::default()
+    };
+
+    let mut stream = match connect(server, proto, deadline) {
+        Ok(s) => s,
+        Err(e) => {
+            report.errors = requests;
+            report.error = Some(e);
+            report.elapsed = time::precise_time_ns() - start;
+            return report;
+        }
+    };
+
+    let mut buf = Vec::new();
+    for i in 0..requests {
+        let work = workloads[i % workloads.len()].generate_work();
+        let sent = time::precise_time_ns();
+        let response = remaining(deadline)
+                           .and_then(|t| stream.set_write_timeout(Some(t)))
+                           .and_then(|_| stream.write_all(&work.bytes))
+                           .and_then(|_| {
+                               read_response(&mut stream, protocol, &mut buf, deadline)
+                           });
+        match response {
+            Ok(length) => {
+                report.latencies.push(time::precise_time_ns() - sent);
+                match connection::classify(protocol,
+                                           &buf[..length],
+                                           work.validate.as_ref().map(|k| &k[..])) {
+                    Status::Hit | Status::Miss | Status::Ok => report.successes += 1,
+                    _ => report.errors += 1,
+                }
+                buf.drain(..length);
+            }
+            Err(e) => {
+                if is_timeout(&e) {
+                    report.timeouts += requests - i;
+                } else {
+                    report.errors += requests - i;
+                }
+                report.error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    report.elapsed = time::precise_time_ns() - start;
+    report
+}
+
+/// Connect to the first address of the server of the right family
+pub fn connect(server: &str, proto: InternetProtocol, deadline: u64) -> Result<TcpStream, String> {
+    let address = match server.to_socket_addrs() {
+        Ok(addrs) => {
+            addrs.filter(|a| {
+                     match *a {
+                         SocketAddr::V4(_) => proto != InternetProtocol::IpV6,
+                         SocketAddr::V6(_) => proto != InternetProtocol::IpV4,
+                     }
+                 })
+                 .next()
+        }
+        Err(e) => return Err(format!("Could not resolve {}: {}", server, e)),
+    };
+    let address = match address {
+        Some(a) => a,
+        None => return Err(format!("No {:?} address for {}", proto, server)),
+    };
+    let timeout = match remaining(deadline) {
+        Ok(t) => t,
+        Err(e) => return Err(e.to_string()),
+    };
+    match TcpStream::connect_timeout(&address, timeout) {
+        Ok(s) => {
+            let _ = s.set_nodelay(true);
+            Ok(s)
+        }
+        Err(e) => Err(format!("Could not connect to {}: {}", server, e)),
+    }
+}
+
+// read until the buffer holds a complete response, returning its length
+fn read_response(stream: &mut TcpStream,
+                 protocol: &ProtocolParse,
+                 buf: &mut Vec<u8>,
+                 deadline: u64)
+                 -> io::Result<usize> {
+    let mut chunk = [0; 16 * 1024];
+    loop {
+        if let Some(length) = protocol.frame(buf) {
+            if length > 0 {
+                return Ok(length);
+            }
+        }
+        try!(stream.set_read_timeout(Some(try!(remaining(deadline)))));
+        match try!(stream.read(&mut chunk)) {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+// time left before the deadline as a socket timeout, an error once it has passed
+fn remaining(deadline: u64) -> io::Result<Duration> {
+    let now = time::precise_time_ns();
+    if now >= deadline {
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline reached"));
+    }
+    let left = deadline - now;
+    Ok(Duration::new(left / 1_000_000_000, (left % 1_000_000_000) as u32))
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(successes: usize, errors: usize, timeouts: usize) -> Report {
+        Report {
+            server: "127.0.0.1:11211".to_owned(),
+            requests: successes + errors + timeouts,
+            successes: successes,
+            errors: errors,
+            timeouts: timeouts,
+            ..Report::default()
+        }
+    }
+
+    #[test]
+    fn status_by_successes() {
+        assert_eq!(report(10, 0, 0).status(), OK);
+        assert_eq!(report(9, 1, 0).status(), WARNING);
+        assert_eq!(report(5, 0, 5).status(), WARNING);
+        assert_eq!(report(0, 10, 0).status(), CRITICAL);
+        assert_eq!(report(0, 0, 0).status(), CRITICAL);
+    }
+
+    #[test]
+    fn percentiles_by_nearest_rank() {
+        let mut r = report(10, 0, 0);
+        r.latencies = (1..11).rev().map(|v| v * 1_000).collect();
+        assert_eq!(r.percentile(50.0), Some(5_000));
+        assert_eq!(r.percentile(99.0), Some(10_000));
+        assert_eq!(r.percentile(0.0), Some(1_000));
+        assert_eq!(report(0, 0, 10).percentile(50.0), None);
+    }
+
+    #[test]
+    fn json_line() {
+        let mut r = report(1, 1, 0);
+        r.latencies = vec![2_000, 1_000];
+        r.elapsed = 5_000;
+        assert_eq!(r.json(),
+                   "{\"status\":\"WARNING\",\"server\":\"127.0.0.1:11211\",\"requests\":2,\
+                    \"successes\":1,\"errors\":1,\"timeouts\":0,\"p50\":1000,\"p99\":2000,\
+                    \"elapsed\":5000}");
+
+        let mut r = report(0, 2, 0);
+        r.error = Some("Could not connect".to_owned());
+        assert!(r.json().ends_with("\"p50\":null,\"p99\":null,\"elapsed\":0,\
+                                     \"error\":\"Could not connect\"}"));
+    }
+}
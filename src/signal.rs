@@ -0,0 +1,55 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+#[cfg(unix)]
+use libc;
+
+/// Exit status when a second signal forces an immediate exit
+pub const INTERRUPTED: i32 = 130;
+
+// number of SIGINT/SIGTERM received
+static RECEIVED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Catch SIGINT and SIGTERM so the run can stop after the current window
+///
+/// The handler only counts signals. Clients poll `stopping()` to stop sending new requests, and
+/// the stats receiver to end the run early. A second signal exits immediately.
+#[cfg(unix)]
+pub fn install() {
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Whether a signal has asked the run to stop
+pub fn stopping() -> bool {
+    RECEIVED.load(Ordering::Relaxed) > 0
+}
+
+// only async-signal-safe calls are allowed here
+#[cfg(unix)]
+extern "C" fn handler(_: libc::c_int) {
+    if RECEIVED.fetch_add(1, Ordering::SeqCst) > 0 {
+        unsafe {
+            libc::_exit(INTERRUPTED);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+extern crate histogram;
+
+use histogram::Histogram;
+
+const ONE_MICROSECOND: u64 = 1_000;
+
+/// Limits the overall results must meet for the run to pass
+#[derive(Clone, Default)]
+pub struct Assertions {
+    pub p99: Option<u64>,
+    pub p999: Option<u64>,
+    pub error_rate: Option<f64>,
+    pub min_rate: Option<f64>,
+    pub slow_rate: Option<f64>,
+}
+
+impl Assertions {
+    pub fn is_empty(&self) -> bool {
+        self.p99.is_none() && self.p999.is_none() && self.error_rate.is_none() &&
+        self.min_rate.is_none() && self.slow_rate.is_none()
+    }
+
+    /// Check every assertion, logging each result, and return true if all passed
+    pub fn evaluate(&self,
+                    histogram: &Histogram,
+                    error_rate: f64,
+                    rate: f64,
+                    slow_rate: f64)
+                    -> bool {
+        let mut passed = true;
+
+        if let Some(limit) = self.p99 {
+            passed &= check_latency("p99", histogram, 99.0, limit);
+        }
+        if let Some(limit) = self.p999 {
+            passed &= check_latency("p999", histogram, 99.9, limit);
+        }
+        if let Some(limit) = self.error_rate {
+            if error_rate > limit {
+                error!("SLA: error rate: FAIL actual: {:.*} % expected: <= {:.*} %",
+                       2,
+                       error_rate,
+                       2,
+                       limit);
+                passed = false;
+            } else {
+                info!("SLA: error rate: PASS actual: {:.*} % expected: <= {:.*} %",
+                      2,
+                      error_rate,
+                      2,
+                      limit);
+            }
+        }
+        if let Some(limit) = self.min_rate {
+            if rate < limit {
+                error!("SLA: rate: FAIL actual: {:.*} rps expected: >= {:.*} rps",
+                       2,
+                       rate,
+                       2,
+                       limit);
+                passed = false;
+            } else {
+                info!("SLA: rate: PASS actual: {:.*} rps expected: >= {:.*} rps",
+                      2,
+                      rate,
+                      2,
+                      limit);
+            }
+        }
+
+        if let Some(limit) = self.slow_rate {
+            if slow_rate > limit {
+                error!("SLA: slow rate: FAIL actual: {:.*} % expected: <= {:.*} %",
+                       2,
+                       slow_rate,
+                       2,
+                       limit);
+                passed = false;
+            } else {
+                info!("SLA: slow rate: PASS actual: {:.*} % expected: <= {:.*} %",
+                      2,
+                      slow_rate,
+                      2,
+                      limit);
+            }
+        }
+
+        passed
+    }
+}
+
+fn check_latency(name: &str, histogram: &Histogram, percentile: f64, limit: u64) -> bool {
+    match histogram.percentile(percentile) {
+        Ok(v) => {
+            let actual = v / ONE_MICROSECOND;
+            // no latency can exceed a limit too large to express in nanoseconds
+            if limit.checked_mul(ONE_MICROSECOND).map_or(false, |l| v > l) {
+                error!("SLA: {}: FAIL actual: {} us expected: <= {} us",
+                       name,
+                       actual,
+                       limit);
+                false
+            } else {
+                info!("SLA: {}: PASS actual: {} us expected: <= {} us",
+                      name,
+                      actual,
+                      limit);
+                true
+            }
+        }
+        Err(e) => {
+            error!("SLA: {}: FAIL no result: {}", name, e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use histogram::HistogramConfig;
+
+    fn histogram() -> Histogram {
+        let mut config = HistogramConfig::new();
+        config.precision(4).max_value(1_000_000_000);
+        let mut histogram = Histogram::configured(config).unwrap();
+        for _ in 0..1000 {
+            let _ = histogram.increment(100 * ONE_MICROSECOND);
+        }
+        for _ in 0..5 {
+            let _ = histogram.increment(5_000 * ONE_MICROSECOND);
+        }
+        histogram
+    }
+
+    fn assertions() -> Assertions {
+        Assertions {
+            p99: Some(200),
+            p999: Some(10_000),
+            error_rate: Some(1.0),
+            min_rate: Some(100.0),
+            slow_rate: Some(5.0),
+        }
+    }
+
+    #[test]
+    fn passes_within_limits() {
+        assert!(Assertions::default().is_empty());
+        assert!(!assertions().is_empty());
+        assert!(assertions().evaluate(&histogram(), 0.5, 1000.0, 1.0));
+    }
+
+    #[test]
+    fn fails_any_limit() {
+        let histogram = histogram();
+        let mut p999 = assertions();
+        p999.p999 = Some(1_000);
+        assert!(!p999.evaluate(&histogram, 0.5, 1000.0, 1.0));
+        assert!(!assertions().evaluate(&histogram, 2.0, 1000.0, 1.0));
+        assert!(!assertions().evaluate(&histogram, 0.5, 50.0, 1.0));
+        assert!(!assertions().evaluate(&histogram, 0.5, 1000.0, 10.0));
+    }
+
+    #[test]
+    fn latency_limits() {
+        let histogram = histogram();
+        assert!(check_latency("p99", &histogram, 99.0, 101));
+        assert!(!check_latency("p99", &histogram, 99.0, 50));
+        // a limit beyond nanoseconds can't be exceeded rather than overflowing
+        assert!(check_latency("p99", &histogram, 99.0, u64::max_value()));
+
+        let mut config = HistogramConfig::new();
+        config.precision(4).max_value(1_000_000_000);
+        let empty = Histogram::configured(config).unwrap();
+        assert!(!check_latency("p99", &empty, 99.0, 100));
+    }
+}
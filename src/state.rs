@@ -14,6 +14,9 @@
 //  limitations under the License.
 
 // The current state of the client connection
+//
+// A connection is Writing while it may send more requests, and Reading once it has as many
+// requests outstanding as the pipeline depth allows.
 #[derive(Clone,Debug)]
 pub enum State {
     Reading,
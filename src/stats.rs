@@ -22,17 +22,44 @@ extern crate tiny_http;
 
 use std::collections::HashMap;
 use std::fmt;
+use std::io::Read;
+use std::mem;
 use std::net::ToSocketAddrs;
 use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc;
 
 use heatmap::{Heatmap, HeatmapConfig};
 use histogram::{Histogram, HistogramConfig};
-use tiny_http::{Server, Response, Request};
+use tiny_http::{Method, Server, Response, Request, StatusCode};
 use waterfall::Waterfall;
 
+use annotate::{self, Annotation, Annotations};
+use cfgtypes::compression;
+use establish::Progress;
+use experiment::{Comparison, Experiment, Noise, SocketOption};
+use history;
+use interleave::{self, Longest};
+use mix::Mix;
+use output::{Output, Record, WindowCsv};
+use request::{Percentiles, Ramp};
+use signal;
+use sla::Assertions;
+
 const ONE_MILISECOND: i64 = 1_000_000;
 const ONE_SECOND: u64 = 1_000_000_000;
+const MAX_RESPONSE_SIZE: u64 = 4 * 1024 * 1024;
+// tcp rtt is sampled in microseconds
+const MAX_TCP_RTT: u64 = 60 * ONE_SECOND / 1_000;
+const HISTOGRAM_PRECISION: u32 = 4;
+const HEATMAP_PRECISION: u32 = 2;
+const CONNECTIONS_LIMIT: usize = 100;
+const CONNECTIONS_MAX_LIMIT: usize = 1000;
+// after an interrupt, time allowed for responses to requests in flight
+const INTERRUPT_GRACE: u64 = ONE_SECOND / 2;
+// longest body read from a POST
+const MAX_BODY: u64 = 4096;
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Counter {
@@ -42,15 +69,49 @@ pub enum Counter {
     Hit,
     Miss,
     Closed,
+    Corrupt,
+    /// hits whose flags weren't those stored with the key
+    FlagMismatch,
+    /// reads in a sequence which found a value stored before the sequence's own
+    Stale,
+    Timeout,
+    /// successes which needed at least one retry
+    Retried,
+    /// timeouts after every retry was used
+    GaveUp,
+    /// responses slower than the slow threshold
+    Slow,
+    /// errors with a 4xx status
+    ClientError,
+    /// errors with a 5xx status
+    ServerError,
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 pub enum Gauge {
-    Percentile50,
-    Percentile90,
-    Percentile99,
-    Percentile999,
-    Percentile9999,
+    /// latency at a percentile, named like `p999` for 99.9
+    Percentile(String),
+}
+
+/// How a run ended, which determines the exit status
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Complete,
+    AssertionFailed,
+    Truncated,
+    InvariantFailed,
+}
+
+impl Outcome {
+    /// Exit status of the process
+    pub fn status(&self) -> i32 {
+        match *self {
+            Outcome::Complete => 0,
+            Outcome::AssertionFailed => 1,
+            Outcome::Truncated => 2,
+            Outcome::InvariantFailed => 3,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Hash)]
@@ -60,6 +121,426 @@ pub enum Status {
     Miss,
     Ok,
     Closed,
+    /// a hit whose value failed validation
+    Corrupt,
+    /// a hit with a valid value, but not the flags stored with it
+    FlagMismatch,
+    /// a hit in a sequence with the value an earlier sequence stored, not its own
+    Stale,
+    /// no response before the request timeout
+    Timeout,
+    /// an error response with a 4xx status
+    ClientError,
+    /// an error response with a 5xx status
+    ServerError,
+}
+
+// aggregated TCP_INFO samples, rtt in microseconds
+struct TcpStats {
+    rtt: Histogram,
+    retransmits: u64,
+}
+
+impl TcpStats {
+    fn new() -> TcpStats {
+        TcpStats {
+            rtt: Histogram::configured(histogram_config(MAX_TCP_RTT)).unwrap(),
+            retransmits: 0,
+        }
+    }
+
+    fn increment(&mut self, sample: &TcpSample) {
+        let _ = self.rtt.increment(sample.rtt as u64);
+        self.retransmits += sample.retransmits as u64;
+    }
+
+    fn clear(&mut self) {
+        let _ = self.rtt.clear();
+        self.retransmits = 0;
+    }
+}
+
+// latencies beyond the range of a histogram or heatmap, which are recorded at its maximum so
+// they still count towards the percentiles
+struct Overflow {
+    // what is tracked, as in the name of its `-max` option
+    name: &'static str,
+    max: u64,
+    window: u64,
+    total: u64,
+    warned: bool,
+}
+
+impl Overflow {
+    fn new(name: &'static str, max: u64) -> Overflow {
+        Overflow {
+            name: name,
+            max: max,
+            window: 0,
+            total: 0,
+            warned: false,
+        }
+    }
+
+    // the latency to record, counting it if it is beyond the maximum
+    fn clamp(&mut self, latency: u64) -> u64 {
+        if latency <= self.max {
+            return latency;
+        }
+        self.window += 1;
+        self.total += 1;
+        if !self.warned {
+            warn!("latency of {} ns exceeded the {} max of {} ms and is recorded at the max, \
+                   consider a larger {}-max",
+                  latency,
+                  self.name,
+                  self.max / ONE_MILISECOND as u64,
+                  self.name);
+            self.warned = true;
+        }
+        self.max
+    }
+
+    fn report(&self, count: u64) {
+        if count > 0 {
+            info!("Overflow: {} samples exceeded {} max of {} ms",
+                  count,
+                  self.name,
+                  self.max / ONE_MILISECOND as u64);
+        }
+    }
+}
+
+// every sink fed by completed requests, recorded together from each `Stat` so their
+// totals always agree
+struct Samples {
+    window_counters: Counters,
+    histogram: Histogram,
+    global_histogram: Histogram,
+    heatmap: Heatmap,
+    size_histogram: Histogram,
+    size_heatmap: Heatmap,
+    histogram_overflow: Overflow,
+    heatmap_overflow: Overflow,
+    // responses slower than this many nanoseconds are counted as slow
+    slow_threshold: Option<u64>,
+    // samples recorded since the run started, and how many each heatmap accepted
+    recorded: u64,
+    sized: u64,
+    heatmap_entries: u64,
+    size_heatmap_entries: u64,
+}
+
+impl Samples {
+    fn new(duration: usize, windows: usize, histogram_max: u64, heatmap_max: u64) -> Samples {
+        let histogram = Histogram::configured(histogram_config(histogram_max)).unwrap();
+        let slices = heatmap_slices(duration, windows);
+
+        Samples {
+            window_counters: Counters::new(),
+            global_histogram: histogram.clone(),
+            histogram: histogram,
+            heatmap: Heatmap::configured(heatmap_config(heatmap_max, slices)).unwrap(),
+            size_histogram: Histogram::configured(histogram_config(MAX_RESPONSE_SIZE)).unwrap(),
+            size_heatmap: Heatmap::configured(heatmap_config(MAX_RESPONSE_SIZE, slices))
+                              .unwrap(),
+            histogram_overflow: Overflow::new("histogram", histogram_max),
+            heatmap_overflow: Overflow::new("heatmap", heatmap_max),
+            slow_threshold: None,
+            recorded: 0,
+            sized: 0,
+            heatmap_entries: 0,
+            size_heatmap_entries: 0,
+        }
+    }
+
+    // values beyond the range of a sink are recorded at its maximum rather than dropped
+    fn record(&mut self, stat: &Stat) {
+        count(&mut self.window_counters, stat);
+        if let Some(threshold) = self.slow_threshold {
+            if stat.is_slow(threshold) {
+                self.window_counters.increment(Counter::Slow);
+            }
+        }
+        self.recorded += 1;
+
+        let latency = stat.stop.saturating_sub(stat.start);
+        let clamped = self.histogram_overflow.clamp(latency);
+        let _ = self.histogram.increment(clamped);
+        let _ = self.global_histogram.increment(clamped);
+        if self.heatmap.increment(stat.start, self.heatmap_overflow.clamp(latency)).is_ok() {
+            self.heatmap_entries += 1;
+        }
+
+        if let Some(size) = stat.size {
+            let size = (size as u64).min(MAX_RESPONSE_SIZE);
+            self.sized += 1;
+            let _ = self.size_histogram.increment(size);
+            if self.size_heatmap.increment(stat.start, size).is_ok() {
+                self.size_heatmap_entries += 1;
+            }
+        }
+    }
+
+    // start of a new window
+    fn clear_window(&mut self) {
+        let _ = self.histogram.clear();
+        self.window_counters.clear();
+        self.histogram_overflow.window = 0;
+        self.heatmap_overflow.window = 0;
+    }
+
+    // start of the run, discarding the warmup
+    fn clear_run(&mut self) {
+        let _ = self.global_histogram.clear();
+        let _ = self.heatmap.clear();
+        let _ = self.size_histogram.clear();
+        let _ = self.size_heatmap.clear();
+        self.recorded = 0;
+        self.sized = 0;
+        self.heatmap_entries = 0;
+        self.size_heatmap_entries = 0;
+        self.histogram_overflow.total = 0;
+        self.heatmap_overflow.total = 0;
+    }
+
+    fn overflow_stats(&self, window: bool) {
+        for overflow in &[&self.histogram_overflow, &self.heatmap_overflow] {
+            overflow.report(if window {
+                overflow.window
+            } else {
+                overflow.total
+            });
+        }
+    }
+
+    /// Compare the totals of every sink for the run, returning a description of each mismatch
+    fn check(&self, global_counters: &Counters) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        {
+            let mut expect = |name: &str, count: u64, expected: u64| {
+                if count != expected {
+                    mismatches.push(format!("{}: {} expected: {}", name, count, expected));
+                }
+            };
+            expect("summary total", global_counters.get(Counter::Total), self.recorded);
+            expect("histogram entries", self.global_histogram.entries(), self.recorded);
+            expect("trace entries", self.heatmap_entries, self.recorded);
+            expect("size histogram entries", self.size_histogram.entries(), self.sized);
+            expect("size trace entries", self.size_heatmap_entries, self.sized);
+        }
+        mismatches
+    }
+}
+
+// results of one server when there is more than one, or of one part of a sequence workload
+struct Breakdown {
+    name: String,
+    window_counters: Counters,
+    counters: Counters,
+    histogram: Histogram,
+    global_histogram: Histogram,
+    // overflow is counted by the `Samples` of every server
+    histogram_max: u64,
+}
+
+impl Breakdown {
+    fn new(name: String, histogram_max: u64) -> Breakdown {
+        let histogram = Histogram::configured(histogram_config(histogram_max)).unwrap();
+        Breakdown {
+            name: name,
+            window_counters: Counters::new(),
+            counters: Counters::new(),
+            global_histogram: histogram.clone(),
+            histogram: histogram,
+            histogram_max: histogram_max,
+        }
+    }
+
+    fn record(&mut self, stat: &Stat) {
+        count(&mut self.window_counters, stat);
+        let latency = stat.stop.saturating_sub(stat.start).min(self.histogram_max);
+        let _ = self.histogram.increment(latency);
+        let _ = self.global_histogram.increment(latency);
+    }
+
+    // end of a window, folding it into the run totals
+    fn end_window(&mut self) {
+        for (counter, value) in &self.window_counters.counts {
+            self.counters.add(counter.clone(), *value);
+        }
+        self.window_counters.clear();
+        let _ = self.histogram.clear();
+    }
+
+    // start of the run, discarding the warmup
+    fn clear_run(&mut self) {
+        self.window_counters.clear();
+        self.counters.clear();
+        let _ = self.histogram.clear();
+        let _ = self.global_histogram.clear();
+    }
+}
+
+// results of a sequence workload: of its sequences from the first request to the last response,
+// and of each of its ops
+struct SequenceStats {
+    workload: usize,
+    whole: Breakdown,
+    ops: Vec<Breakdown>,
+}
+
+impl SequenceStats {
+    fn new(workload: usize, name: &str, ops: &[String], histogram_max: u64) -> SequenceStats {
+        SequenceStats {
+            workload: workload,
+            whole: Breakdown::new(name.to_owned(), histogram_max),
+            ops: ops.iter()
+                    .map(|op| Breakdown::new(format!("{}.{}", name, op), histogram_max))
+                    .collect(),
+        }
+    }
+
+    // a sequence ends with the response to its last request, and takes that one's status
+    fn record(&mut self, stat: &Stat) {
+        if let Some(op) = stat.op.and_then(|i| self.ops.get_mut(i)) {
+            op.record(stat);
+        }
+        if let Some(latency) = stat.sequence {
+            self.whole.record(&Stat { start: stat.stop.saturating_sub(latency), ..stat.clone() });
+        }
+    }
+
+    fn parts(&self) -> Vec<&Breakdown> {
+        let mut parts = vec![&self.whole];
+        parts.extend(self.ops.iter());
+        parts
+    }
+
+    fn end_window(&mut self) {
+        self.whole.end_window();
+        for op in &mut self.ops {
+            op.end_window();
+        }
+    }
+
+    fn clear_run(&mut self) {
+        self.whole.clear_run();
+        for op in &mut self.ops {
+            op.clear_run();
+        }
+    }
+}
+
+fn histogram_config(max: u64) -> HistogramConfig {
+    let mut config = HistogramConfig::new();
+    config.precision(HISTOGRAM_PRECISION).max_value(max);
+    config
+}
+
+// the heatmaps also span the warmup window, which is cleared when it completes
+fn heatmap_slices(duration: usize, windows: usize) -> usize {
+    duration * (windows + 1)
+}
+
+fn heatmap_config(max: u64, slices: usize) -> HeatmapConfig {
+    let mut config = HeatmapConfig::new();
+    config.precision(HEATMAP_PRECISION).max_value(max);
+    config.slice_duration(ONE_SECOND as u64).num_slices(slices);
+    config
+}
+
+// each slice of a heatmap is a histogram of the heatmap's precision
+fn slice_config(max: u64) -> HistogramConfig {
+    let mut config = HistogramConfig::new();
+    config.precision(HEATMAP_PRECISION).max_value(max);
+    config
+}
+
+// bytes of bucket counts in a histogram of the configuration
+fn histogram_memory(config: HistogramConfig) -> usize {
+    Histogram::configured(config).map_or(0, |h| h.buckets_total() as usize * mem::size_of::<u64>())
+}
+
+/// Bytes of latency and size samples kept for the run, including the breakdown of each server
+/// when there's more than one, and of the `sequences` and their ops
+pub fn memory(duration: usize,
+              windows: usize,
+              histogram_max: u64,
+              heatmap_max: u64,
+              servers: usize,
+              sequences: usize)
+              -> usize {
+    let histogram = histogram_memory(histogram_config(histogram_max));
+    let slices = heatmap_slices(duration, windows);
+    // window and run histograms, and the size histogram
+    let samples = 2 * histogram + histogram_memory(histogram_config(MAX_RESPONSE_SIZE));
+    let heatmaps = slices *
+                   (histogram_memory(slice_config(heatmap_max)) +
+                    histogram_memory(slice_config(MAX_RESPONSE_SIZE)));
+    let tcp = 2 * histogram_memory(histogram_config(MAX_TCP_RTT));
+    let servers = if servers > 1 {
+        servers * 2 * histogram
+    } else {
+        0
+    };
+    samples + heatmaps + tcp + servers + sequences * 2 * histogram
+}
+
+// increment the counters matching the result of a request
+fn count(counters: &mut Counters, stat: &Stat) {
+    match stat.status {
+        Status::Ok => {
+            counters.increment(Counter::Ok);
+        }
+        Status::Hit => {
+            counters.increment(Counter::Ok);
+            counters.increment(Counter::Hit);
+        }
+        Status::Miss => {
+            counters.increment(Counter::Ok);
+            counters.increment(Counter::Miss);
+        }
+        Status::Error => {
+            counters.increment(Counter::Error);
+        }
+        Status::Closed => {
+            counters.increment(Counter::Closed);
+        }
+        Status::Corrupt => {
+            counters.increment(Counter::Corrupt);
+        }
+        Status::FlagMismatch => {
+            counters.increment(Counter::FlagMismatch);
+        }
+        Status::Stale => {
+            counters.increment(Counter::Ok);
+            counters.increment(Counter::Stale);
+        }
+        Status::Timeout => {
+            counters.increment(Counter::Timeout);
+        }
+        Status::ClientError => {
+            counters.increment(Counter::Error);
+            counters.increment(Counter::ClientError);
+        }
+        Status::ServerError => {
+            counters.increment(Counter::Error);
+            counters.increment(Counter::ServerError);
+        }
+    }
+    if stat.attempts > 1 {
+        match stat.status {
+            Status::Ok | Status::Hit | Status::Miss => {
+                counters.increment(Counter::Retried);
+            }
+            Status::Timeout => {
+                counters.increment(Counter::GaveUp);
+            }
+            _ => {}
+        }
+    }
+    counters.increment(Counter::Total);
 }
 
 pub struct Counters {
@@ -75,10 +556,104 @@ pub struct Stat {
     pub start: u64,
     pub stop: u64,
     pub status: Status,
+    /// bytes in the response, only for successfully parsed responses
+    pub size: Option<usize>,
+    /// times the request was sent, `start` is the first of them
+    pub attempts: usize,
+    /// index of the server the request was sent to
+    pub server: usize,
+    /// index of the workload which generated the request, if one did
+    pub workload: Option<usize>,
+    /// consecutive requests of its workload on the connection, ending with this one
+    pub run: usize,
+    /// position of the request in its sequence, for the ops of a sequence workload
+    pub op: Option<usize>,
+    /// nanoseconds since the first request of its sequence was sent, if it ends one
+    pub sequence: Option<u64>,
+    /// arm of the socket option experiment the connection is in
+    pub arm: Option<usize>,
+}
+
+impl Stat {
+    /// Whether a successful response arrived, but only after the threshold in nanoseconds
+    fn is_slow(&self, threshold: u64) -> bool {
+        match self.status {
+            Status::Ok | Status::Hit | Status::Miss | Status::Stale => {
+                self.stop.saturating_sub(self.start) > threshold
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Kernel TCP statistics sampled from one connection
+#[derive(Clone)]
+pub struct TcpSample {
+    pub rtt: u32,
+    pub retransmits: u32,
+}
+
+/// Bookkeeping of one live connection, reported for introspection
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    pub token: usize,
+    pub server: String,
+    pub local: Option<String>,
+    pub established: time::Timespec,
+    pub last_activity: time::Timespec,
+    pub requests: u64,
+    pub in_flight: usize,
+    pub tcp_nodelay: bool,
+    pub pipeline: usize,
+}
+
+/// All live connections of one client thread
+pub struct ConnectionSnapshot {
+    pub client: usize,
+    pub connections: Vec<ConnectionInfo>,
+}
+
+/// Settings of a run, for the receiver to collect and report its stats by
+pub struct RunConfig {
+    /// seconds in each window
+    pub duration: usize,
+    pub windows: usize,
+    /// seconds after which the run is truncated
+    pub max_duration: Option<usize>,
+    pub trace: Option<String>,
+    pub size_trace: Option<String>,
+    pub waterfall: Option<String>,
+    /// lost connections which end the run, zero if none are expected
+    pub max_closed: usize,
+    pub listen: Option<String>,
+    pub output: Option<Output>,
+    pub csv: Option<WindowCsv>,
+    pub assertions: Assertions,
+    /// the ramp, and the rate offered by the workloads which it sets each window
+    pub ramp: Option<(Ramp, Arc<AtomicUsize>)>,
+    pub percentiles: Percentiles,
+    /// nanoseconds
+    pub slow_threshold: Option<u64>,
+    pub slow_log: bool,
+    /// nanoseconds
+    pub histogram_max: u64,
+    /// nanoseconds
+    pub heatmap_max: u64,
+    pub mix: Option<Mix>,
+    pub ab: Option<SocketOption>,
+    pub progress: Arc<Progress>,
+    pub include_ramp: bool,
+    pub check_invariants: bool,
 }
 
 pub struct Receiver {
     queue: mpsc::Receiver<Stat>,
+    tcp_queue: mpsc::Receiver<TcpSample>,
+    connections_queue: mpsc::Receiver<ConnectionSnapshot>,
+    servers: Vec<String>,
+    workloads: Vec<String>,
+    // names of the ops of each workload, empty unless it's a sequence
+    sequences: Vec<Vec<String>>,
 }
 
 impl fmt::Display for Status {
@@ -89,6 +664,12 @@ impl fmt::Display for Status {
             Status::Hit => write!(f, "hit"),
             Status::Miss => write!(f, "miss"),
             Status::Closed => write!(f, "closed"),
+            Status::Corrupt => write!(f, "corrupt"),
+            Status::FlagMismatch => write!(f, "flag_mismatch"),
+            Status::Stale => write!(f, "stale"),
+            Status::Timeout => write!(f, "timeout"),
+            Status::ClientError => write!(f, "client_error"),
+            Status::ServerError => write!(f, "server_error"),
         }
     }
 }
@@ -96,11 +677,7 @@ impl fmt::Display for Status {
 impl fmt::Display for Gauge {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Gauge::Percentile50 => write!(f, "p50"),
-            Gauge::Percentile90 => write!(f, "p90"),
-            Gauge::Percentile99 => write!(f, "p99"),
-            Gauge::Percentile999 => write!(f, "p999"),
-            Gauge::Percentile9999 => write!(f, "p9999"),
+            Gauge::Percentile(ref name) => write!(f, "{}", name),
         }
     }
 }
@@ -114,6 +691,15 @@ impl fmt::Display for Counter {
             Counter::Hit => write!(f, "hit"),
             Counter::Miss => write!(f, "miss"),
             Counter::Closed => write!(f, "closed"),
+            Counter::Corrupt => write!(f, "corrupt"),
+            Counter::FlagMismatch => write!(f, "flag_mismatch"),
+            Counter::Stale => write!(f, "stale"),
+            Counter::Timeout => write!(f, "timeout"),
+            Counter::Retried => write!(f, "retried"),
+            Counter::GaveUp => write!(f, "gave_up"),
+            Counter::Slow => write!(f, "slow"),
+            Counter::ClientError => write!(f, "client_error"),
+            Counter::ServerError => write!(f, "server_error"),
         }
     }
 }
@@ -162,14 +748,56 @@ impl Gauges {
 }
 
 fn response_stats(counters: &Counters) {
-    info!("Responses: {} Ok: {} Error: {} Closed: {} Hit: {} Miss: {} ",
+    info!("Responses: {} Ok: {} Error: {} Closed: {} Hit: {} Miss: {} Corrupt: {} Timeout: {}",
                           counters.get(Counter::Total),
                           counters.get(Counter::Ok),
                           counters.get(Counter::Error),
                           counters.get(Counter::Closed),
                           counters.get(Counter::Hit),
                           counters.get(Counter::Miss),
+                          counters.get(Counter::Corrupt),
+                          counters.get(Counter::Timeout),
                         );
+    if counters.get(Counter::Retried) > 0 || counters.get(Counter::GaveUp) > 0 {
+        info!("Retries: Succeeded: {} Gave Up: {}",
+              counters.get(Counter::Retried),
+              counters.get(Counter::GaveUp));
+    }
+    if counters.get(Counter::FlagMismatch) > 0 {
+        info!("Flags: mismatches: {}", counters.get(Counter::FlagMismatch));
+    }
+    if counters.get(Counter::Stale) > 0 {
+        info!("Sequences: stale reads: {}", counters.get(Counter::Stale));
+    }
+    if counters.get(Counter::ClientError) > 0 || counters.get(Counter::ServerError) > 0 {
+        info!("Status: 4xx: {} 5xx: {}",
+              counters.get(Counter::ClientError),
+              counters.get(Counter::ServerError));
+    }
+}
+
+fn establish_stats(progress: &Progress, ramp_windows: usize, included: bool) {
+    match progress.time_to_full() {
+        Some(t) => {
+            info!("Connections: {} established in {} ms",
+                  progress.expected(),
+                  t / ONE_MILISECOND as u64)
+        }
+        None => {
+            info!("Connections: {} of {} established",
+                  progress.established(),
+                  progress.expected())
+        }
+    }
+    if ramp_windows > 0 {
+        info!("Connection Ramp: {} windows {} the summary",
+              ramp_windows,
+              if included {
+                  "included in"
+              } else {
+                  "excluded from"
+              });
+    }
 }
 
 fn pretty_percentile(histogram: &Histogram, percentile: f64) -> String {
@@ -179,14 +807,154 @@ fn pretty_percentile(histogram: &Histogram, percentile: f64) -> String {
     }
 }
 
-fn histogram_stats(histogram: &Histogram) {
-    info!("Percentiles: p50: {} p90: {} p99: {} p999: {} p9999: {}",
-        pretty_percentile(histogram, 50.0),
-        pretty_percentile(histogram, 90.0),
-        pretty_percentile(histogram, 99.0),
-        pretty_percentile(histogram, 99.9),
-        pretty_percentile(histogram, 99.99),
-    );
+// eg: `p50` for 50 and `p999` for 99.9
+fn percentile_name(percentile: f64) -> String {
+    format!("p{}", percentile).replace(".", "")
+}
+
+fn histogram_stats(histogram: &Histogram, percentiles: &Percentiles) {
+    let values: Vec<String> = percentiles.0
+                                         .iter()
+                                         .map(|p| {
+                                             format!("{}: {}",
+                                                     percentile_name(*p),
+                                                     pretty_percentile(histogram, *p))
+                                         })
+                                         .collect();
+    info!("Percentiles: {}", values.join(" "));
+}
+
+// corrupt responses and flag mismatches parse fine, but count as failures all the same, as do
+// timeouts
+fn failures(c: &Counters) -> u64 {
+    c.get(Counter::Error) + c.get(Counter::Corrupt) + c.get(Counter::FlagMismatch) +
+    c.get(Counter::Timeout)
+}
+
+fn success_percent(c: &Counters) -> f64 {
+    ratio_percent(c.get(Counter::Ok), failures(c))
+}
+
+fn error_percent(c: &Counters) -> f64 {
+    ratio_percent(failures(c), c.get(Counter::Ok))
+}
+
+// percent of responses which were slow
+fn slow_percent(c: &Counters) -> f64 {
+    let slow = c.get(Counter::Slow);
+    ratio_percent(slow, responses(c) - slow)
+}
+
+// requests which had a response
+fn responses(c: &Counters) -> u64 {
+    c.get(Counter::Total) - c.get(Counter::Closed) - c.get(Counter::Timeout)
+}
+
+fn slow_stats(counters: &Counters, threshold: u64) {
+    info!("Slow: {} ({:.*} %) over {} ms",
+          counters.get(Counter::Slow),
+          2,
+          slow_percent(counters),
+          threshold / ONE_MILISECOND as u64);
+}
+
+// realized share of each workload in the window, warning on those which drifted too far
+fn mix_stats(mix: &mut Mix, window: usize) {
+    for drift in mix.end_window(window) {
+        let line = format!("Mix: {} intended: {:.*} % realized: {:.*} %",
+                           mix.name(drift.workload),
+                           2,
+                           drift.intended,
+                           2,
+                           drift.realized);
+        if mix.exceeds(&drift) {
+            warn!("{} drift of {:.*} points is over the tolerance",
+                  line,
+                  2,
+                  drift.points());
+        } else {
+            info!("{}", line);
+        }
+    }
+}
+
+fn worst_drift(mix: &Mix) {
+    if let Some(drift) = mix.worst() {
+        info!("Mix: worst drift: {:.*} points {} intended: {:.*} % realized: {:.*} % window: {}",
+              2,
+              drift.points(),
+              mix.name(drift.workload),
+              2,
+              drift.intended,
+              2,
+              drift.realized,
+              drift.window);
+    }
+}
+
+// percentiles of each arm side by side, then the delta of the second arm from the first with
+// the noise across windows when there is an estimate of it
+fn ab_stats(experiment: &Experiment, comparison: &Comparison, noise: &[Option<Noise>]) {
+    let percentiles = experiment.percentiles();
+    for (arm, values) in comparison.arms.iter().enumerate() {
+        let values: Vec<String> = percentiles.iter()
+                                             .zip(values.iter())
+                                             .map(|(p, v)| {
+                                                 format!("{}: {} ns", percentile_name(*p), v)
+                                             })
+                                             .collect();
+        info!("A/B: {} Responses: {} {}",
+              experiment.arm_name(arm),
+              comparison.responses[arm],
+              values.join(" "));
+    }
+    let deltas: Vec<String> = percentiles.iter()
+                                         .zip(comparison.deltas.iter())
+                                         .enumerate()
+                                         .map(|(i, (p, d))| {
+                                             delta_text(*p, *d, noise.get(i).and_then(|n| *n))
+                                         })
+                                         .collect();
+    info!("A/B: {} vs {}: {}",
+          experiment.arm_name(1),
+          experiment.arm_name(0),
+          deltas.join(" "));
+    if let Some(n) = noise.iter().filter_map(|n| *n).next() {
+        info!("A/B: noise is two standard errors of the delta across {} windows",
+              n.windows);
+    }
+}
+
+// eg: `p99: +1500 ns noise: +/-200 ns`
+fn delta_text(percentile: f64, delta: i64, noise: Option<Noise>) -> String {
+    let text = format!("{}: {:+} ns", percentile_name(percentile), delta);
+    match noise {
+        Some(n) if (delta as f64).abs() <= n.error => {
+            format!("{} noise: +/-{:.0} ns (within noise)", text, n.error)
+        }
+        Some(n) => format!("{} noise: +/-{:.0} ns", text, n.error),
+        None => text,
+    }
+}
+
+// values compressed and decompressed by the client, and the wall-clock time spent on them
+fn compression_stats(totals: &compression::Totals) {
+    info!("Compression: compressed: {} ratio: {:.*} time: {} us decompressed: {} time: {} us",
+          totals.compressed,
+          2,
+          totals.ratio(),
+          totals.compress_ns / 1_000,
+          totals.decompressed,
+          totals.decompress_ns / 1_000);
+}
+
+// percent of a in the total of a and b
+fn ratio_percent(a: u64, b: u64) -> f64 {
+    let t = (a + b) as f64;
+    if t > 0.0 {
+        return 100_f64 * a as f64 / t;
+    }
+    0.0
 }
 
 fn counter_percent(c: &Counters, a: Counter, b: Counter) -> f64 {
@@ -201,10 +969,112 @@ fn counter_percent(c: &Counters, a: Counter, b: Counter) -> f64 {
     0.0
 }
 
+// a run which ended during warmup has measured no time
 fn counter_rate(c: &Counters, time: u64, counter: Counter) -> f64 {
+    if time == 0 {
+        return 0.0;
+    }
     (ONE_SECOND * c.get(counter)) as f64 / time as f64
 }
 
+fn size_stats(histogram: &Histogram) {
+    info!("Response Size: responses: {} min: {} max: {} bytes",
+          histogram.entries(),
+          histogram.minimum().unwrap_or(0),
+          histogram.maximum().unwrap_or(0));
+    info!("Response Size: p50: {} p90: {} p99: {} p999: {} p9999: {} bytes",
+          histogram.percentile(50.0).unwrap_or(0),
+          histogram.percentile(90.0).unwrap_or(0),
+          histogram.percentile(99.0).unwrap_or(0),
+          histogram.percentile(99.9).unwrap_or(0),
+          histogram.percentile(99.99).unwrap_or(0));
+}
+
+fn breakdown_stats(kind: &str,
+                   name: &str,
+                   counters: &Counters,
+                   histogram: &Histogram,
+                   elapsed: u64) {
+    info!("{}: {} Rate: {:.*} rps Success: {:.*} % Errors: {} p99: {}",
+          kind,
+          name,
+          2,
+          counter_rate(counters, elapsed, Counter::Total),
+          2,
+          success_percent(counters),
+          failures(counters),
+          pretty_percentile(histogram, 99.0));
+}
+
+fn tcp_stats(tcp: &TcpStats) {
+    info!("TCP: samples: {} rtt: p50: {} us p99: {} us max: {} us retransmits: {}",
+          tcp.rtt.entries(),
+          tcp.rtt.percentile(50.0).unwrap_or(0),
+          tcp.rtt.percentile(99.0).unwrap_or(0),
+          tcp.rtt.maximum().unwrap_or(0),
+          tcp.retransmits);
+}
+
+fn output_record(window: usize,
+                 start: time::Timespec,
+                 stop: time::Timespec,
+                 elapsed: u64,
+                 counters: &Counters,
+                 histogram: &Histogram,
+                 tcp: &TcpStats)
+                 -> Record {
+    let total = counters.get(Counter::Total);
+    let sampled = tcp.rtt.entries() > 0;
+    Record {
+        window: window,
+        offered_rate: None,
+        start: start,
+        stop: stop,
+        requests: total,
+        responses: responses(counters),
+        successes: counters.get(Counter::Ok),
+        errors: counters.get(Counter::Error),
+        client_errors: counters.get(Counter::ClientError),
+        server_errors: counters.get(Counter::ServerError),
+        corrupt: counters.get(Counter::Corrupt),
+        flag_mismatches: counters.get(Counter::FlagMismatch),
+        stale: counters.get(Counter::Stale),
+        timeouts: counters.get(Counter::Timeout),
+        retried: counters.get(Counter::Retried),
+        gave_up: counters.get(Counter::GaveUp),
+        slow: None,
+        rate: counter_rate(counters, elapsed, Counter::Total),
+        min: histogram.minimum().unwrap_or(0),
+        p50: histogram.percentile(50.0).unwrap_or(0),
+        p90: histogram.percentile(90.0).unwrap_or(0),
+        p99: histogram.percentile(99.0).unwrap_or(0),
+        p999: histogram.percentile(99.9).unwrap_or(0),
+        p9999: histogram.percentile(99.99).unwrap_or(0),
+        max: histogram.maximum().unwrap_or(0),
+        tcp_rtt_p50: if sampled {
+            Some(tcp.rtt.percentile(50.0).unwrap_or(0))
+        } else {
+            None
+        },
+        tcp_rtt_p99: if sampled {
+            Some(tcp.rtt.percentile(99.0).unwrap_or(0))
+        } else {
+            None
+        },
+        tcp_retransmits: if sampled {
+            Some(tcp.retransmits)
+        } else {
+            None
+        },
+        truncated: None,
+        interrupted: None,
+        connection_ramp: None,
+        establish_time: None,
+        server: None,
+        sequence: None,
+    }
+}
+
 fn start_listener(listen: Option<String>) -> Option<Server> {
     if let Some(ref l) = listen {
         let http_socket = l.to_socket_addrs().unwrap().next().unwrap();
@@ -215,19 +1085,67 @@ fn start_listener(listen: Option<String>) -> Option<Server> {
     None
 }
 
-fn try_handle_http(server: &Option<Server>, mut histogram: &mut Histogram, gauges: &Gauges, counters: &Counters) {
+// an annotation posted to the listener is returned to be logged and written to the output
+fn try_handle_http(server: &Option<Server>,
+                   mut histogram: &mut Histogram,
+                   gauges: &Gauges,
+                   counters: &Counters,
+                   connections: &HashMap<usize, Vec<ConnectionInfo>>,
+                   servers: &[Breakdown],
+                   annotations: &mut Annotations)
+                   -> Option<Annotation> {
     if let Some(ref s) = *server {
         if let Ok(Some(request)) = s.try_recv() {
             debug!("stats: handle http request");
-            handle_http(request, &mut histogram, &gauges, &counters);
+            return handle_http(request,
+                               &mut histogram,
+                               &gauges,
+                               &counters,
+                               connections,
+                               servers,
+                               annotations);
         }
     }
+    None
 }
 
-fn handle_http(request: Request, histogram: &mut Histogram, gauges: &Gauges, counters: &Counters) {
+fn handle_http(mut request: Request,
+               histogram: &mut Histogram,
+               gauges: &Gauges,
+               counters: &Counters,
+               connections: &HashMap<usize, Vec<ConnectionInfo>>,
+               servers: &[Breakdown],
+               annotations: &mut Annotations)
+               -> Option<Annotation> {
     let mut output = "".to_owned();
+    let mut status = 200;
+    let mut annotation = None;
 
-    match request.url() {
+    let url = request.url().to_owned();
+    let (path, query) = match url.find('?') {
+        Some(i) => (&url[..i], &url[(i + 1)..]),
+        None => (url.as_str(), ""),
+    };
+
+    match path {
+        "/connections" => {
+            output = connections_json(connections, query);
+        }
+        "/servers" => {
+            output = servers_json(servers);
+        }
+        "/annotate" => {
+            match annotate_request(&mut request, query, annotations) {
+                Ok(a) => {
+                    output = a.json();
+                    annotation = Some(a);
+                }
+                Err((code, reason)) => {
+                    output = reason;
+                    status = code;
+                }
+            }
+        }
         "/histogram" => {
             for bucket in histogram {
                 if bucket.count() > 0 {
@@ -256,41 +1174,248 @@ fn handle_http(request: Request, histogram: &mut Histogram, gauges: &Gauges, cou
         }
     }
 
-    let response = Response::from_string(output);
+    let response = Response::from_string(output).with_status_code(StatusCode(status));
     let _ = request.respond(response);
+    annotation
+}
+
+// record the annotation in a POST, or the status and reason it was rejected
+fn annotate_request(request: &mut Request,
+                    query: &str,
+                    annotations: &mut Annotations)
+                    -> Result<Annotation, (u16, String)> {
+    if *request.method() != Method::Post {
+        return Err((405, "annotations must be POSTed".to_owned()));
+    }
+    let mut body = String::new();
+    if request.as_reader().take(MAX_BODY).read_to_string(&mut body).is_err() {
+        return Err((400, "body must be UTF-8".to_owned()));
+    }
+    let now = time::get_time();
+    annotate::parse(query, &body, now)
+        .and_then(|(label, time)| annotations.add(label, time, now))
+        .map_err(|e| (400, e))
+}
+
+// value of a numeric query parameter, eg: `limit` in `offset=100&limit=50`
+fn query_param(query: &str, name: &str) -> Option<usize> {
+    query.split('&')
+         .filter_map(|pair| {
+             let mut kv = pair.splitn(2, '=');
+             match (kv.next(), kv.next()) {
+                 (Some(k), Some(v)) if k == name => v.parse().ok(),
+                 _ => None,
+             }
+         })
+         .next()
+}
+
+// JSON array of live connections ordered by client and token, paginated with `offset` and
+// `limit` so huge connection counts don't produce huge responses
+fn connections_json(connections: &HashMap<usize, Vec<ConnectionInfo>>, query: &str) -> String {
+    let offset = query_param(query, "offset").unwrap_or(0);
+    let limit = query_param(query, "limit")
+                    .unwrap_or(CONNECTIONS_LIMIT)
+                    .min(CONNECTIONS_MAX_LIMIT);
+
+    let mut clients: Vec<&usize> = connections.keys().collect();
+    clients.sort();
+
+    let entries: Vec<String> = clients.into_iter()
+                                      .flat_map(move |c| {
+                                          connections[c].iter().map(move |i| (*c, i))
+                                      })
+                                      .skip(offset)
+                                      .take(limit)
+                                      .map(|(client, info)| connection_json(client, info))
+                                      .collect();
+
+    format!("[{}]", entries.join(","))
+}
+
+// JSON array of the run totals of each server, empty for a single server
+fn servers_json(servers: &[Breakdown]) -> String {
+    let entries: Vec<String> = servers.iter()
+                                      .map(|s| {
+                                          format!("{{\"server\":{},\"requests\":{},\
+                                                   \"successes\":{},\"errors\":{},\
+                                                   \"client_errors\":{},\
+                                                   \"server_errors\":{},\
+                                                   \"p50\":{},\"p99\":{}}}",
+                                                  history::quote(&s.name),
+                                                  s.counters.get(Counter::Total),
+                                                  s.counters.get(Counter::Ok),
+                                                  failures(&s.counters),
+                                                  s.counters.get(Counter::ClientError),
+                                                  s.counters.get(Counter::ServerError),
+                                                  s.global_histogram
+                                                   .percentile(50.0)
+                                                   .unwrap_or(0),
+                                                  s.global_histogram
+                                                   .percentile(99.0)
+                                                   .unwrap_or(0))
+                                      })
+                                      .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn connection_json(client: usize, info: &ConnectionInfo) -> String {
+    format!("{{\"client\":{},\"token\":{},\"server\":{},\"local\":{},\
+             \"established\":\"{}\",\"last_activity\":\"{}\",\"requests\":{},\
+             \"in_flight\":{},\"options\":{{\"tcp_nodelay\":{},\"pipeline\":{}}}}}",
+            client,
+            info.token,
+            history::quote(&info.server),
+            match info.local {
+                Some(ref l) => history::quote(l),
+                None => "null".to_owned(),
+            },
+            time::at_utc(info.established).rfc3339(),
+            time::at_utc(info.last_activity).rfc3339(),
+            info.requests,
+            info.in_flight,
+            info.tcp_nodelay,
+            info.pipeline)
 }
 
 impl Receiver {
-    pub fn new(queue: mpsc::Receiver<Stat>) -> Receiver {
-        Receiver { queue: queue }
+    pub fn new(queue: mpsc::Receiver<Stat>,
+               tcp_queue: mpsc::Receiver<TcpSample>,
+               connections_queue: mpsc::Receiver<ConnectionSnapshot>,
+               servers: Vec<String>,
+               workloads: Vec<String>,
+               sequences: Vec<Vec<String>>)
+               -> Receiver {
+        Receiver {
+            queue: queue,
+            tcp_queue: tcp_queue,
+            connections_queue: connections_queue,
+            servers: servers,
+            workloads: workloads,
+            sequences: sequences,
+        }
     }
 
-    pub fn run(&self,
-               duration: usize,
-               windows: usize,
-               trace: Option<String>,
-               waterfall: Option<String>,
-               max_closed: usize,
-               listen: Option<String>) {
+    /// Wait for the responses to `requests` preload requests, for at most `timeout` ns
+    ///
+    /// Returns whether every request was answered without error.
+    pub fn preload(&self, requests: usize, timeout: u64) -> bool {
+        let deadline = time::precise_time_ns() + timeout;
+        let mut counters = Counters::new();
+        let mut received = 0;
+        while received < requests {
+            match self.queue.try_recv() {
+                Ok(result) => {
+                    count(&mut counters, &result);
+                    received += 1;
+                }
+                Err(_) => {
+                    if time::precise_time_ns() >= deadline || signal::stopping() {
+                        break;
+                    }
+                    shuteye::sleep(shuteye::Timespec::from_nano(ONE_MILISECOND).unwrap());
+                }
+            }
+        }
+        info!("Preload: Requests: {} Responses: {}", requests, received);
+        response_stats(&counters);
+        if received < requests {
+            error!("Preload: {} requests were not answered", requests - received);
+        }
+        received == requests && failures(&counters) == 0 && counters.get(Counter::Closed) == 0
+    }
+
+    pub fn run(&self, config: RunConfig) -> (Outcome, Record) {
+        let RunConfig { duration,
+                        windows,
+                        max_duration,
+                        trace,
+                        size_trace,
+                        waterfall,
+                        max_closed,
+                        listen,
+                        mut output,
+                        mut csv,
+                        assertions,
+                        ramp,
+                        percentiles,
+                        slow_threshold,
+                        slow_log,
+                        histogram_max,
+                        heatmap_max,
+                        mut mix,
+                        ab,
+                        progress,
+                        include_ramp,
+                        check_invariants } = config;
 
         debug!("stats: initialize datastructures");
-        let mut histogram_config = HistogramConfig::new();
-        histogram_config.precision(4).max_value(60 as u64 * ONE_SECOND);
-        let mut histogram = Histogram::configured(histogram_config).unwrap();
-        let mut http_histogram = histogram.clone();
+        let mut samples = Samples::new(duration, windows, histogram_max, heatmap_max);
+        samples.slow_threshold = slow_threshold;
+        let mut http_histogram = samples.histogram.clone();
+        let mut window_tcp = TcpStats::new();
+        let mut global_tcp = TcpStats::new();
 
-        let mut heatmap_config = HeatmapConfig::new();
-        heatmap_config.precision(2).max_value(ONE_SECOND);
-        heatmap_config.slice_duration(ONE_SECOND as u64).num_slices((duration * windows));
-        let mut heatmap = Heatmap::configured(heatmap_config).unwrap();
+        // results of the whole run, once it ends
+        let summary;
 
         let mut printed_at = time::precise_time_ns();
-        let mut window_counters = Counters::new();
+        let deadline = max_duration.map(|d| printed_at + d as u64 * ONE_SECOND);
+        let mut window_start = time::get_time();
+        let mut run_start = window_start;
+        let mut annotations = Annotations::new(window_start);
+        let mut run_elapsed = 0;
         let mut global_counters = Counters::new();
+        // every window, the warmup and connection ramp included, as /vars has always reported
+        let mut vars_counters = Counters::new();
         let mut gauges = Gauges::new();
         let mut window = 0;
-        let mut closed = 0;
         let mut warmup = true;
+        // windows which ended before the connections were established
+        let mut ramp_windows = 0;
+        let mut passed = true;
+        let mut truncated = false;
+        let mut interrupted = false;
+        let mut interrupted_at = None;
+        let mut consistent = true;
+        let mut connections = HashMap::new();
+        // a single workload has nothing to interleave with
+        let mut longest = if self.workloads.len() > 1 {
+            Some(Longest::default())
+        } else {
+            None
+        };
+        let mut compression_totals = compression::Totals::default();
+        // requests the interleave policy held back which were dropped, so the mix wasn't met
+        let mut interleave_dropped = 0;
+        let mut experiment = ab.map(|option| {
+            Experiment::new(option,
+                            &samples.histogram,
+                            histogram_max,
+                            percentiles.0.clone())
+        });
+        // offered rate, achieved rate and p99 of each window of a ramp
+        let mut ramp_steps = Vec::new();
+        // a single server has nothing to break down
+        let mut servers: Vec<Breakdown> = if self.servers.len() > 1 {
+            self.servers
+                .iter()
+                .map(|s| Breakdown::new(s.clone(), histogram_max))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut sequences: Vec<SequenceStats> = self.sequences
+                                                    .iter()
+                                                    .enumerate()
+                                                    .filter(|&(_, ops)| !ops.is_empty())
+                                                    .map(|(i, ops)| {
+                                                        SequenceStats::new(i,
+                                                                           &self.workloads[i],
+                                                                           ops,
+                                                                           histogram_max)
+                                                    })
+                                                    .collect();
 
         let server = start_listener(listen);
 
@@ -298,59 +1423,138 @@ impl Receiver {
         loop {
             match self.queue.try_recv() {
                 Ok(result) => {
-                    match result.status {
-                        Status::Ok => {
-                            window_counters.increment(Counter::Ok);
-                        }
-                        Status::Hit => {
-                            window_counters.increment(Counter::Ok);
-                            window_counters.increment(Counter::Hit);
+                    samples.record(&result);
+                    if slow_log {
+                        if let Some(threshold) = slow_threshold {
+                            if result.is_slow(threshold) {
+                                info!("slow response: {} ns status: {} server: {}",
+                                      result.stop - result.start,
+                                      result.status,
+                                      self.servers
+                                          .get(result.server)
+                                          .map_or("", |s| s.as_str()));
+                            }
                         }
-                        Status::Miss => {
-                            window_counters.increment(Counter::Ok);
-                            window_counters.increment(Counter::Miss);
+                    }
+                    if let Some(s) = servers.get_mut(result.server) {
+                        s.record(&result);
+                    }
+                    if let Some(w) = result.workload {
+                        if let Some(s) = sequences.iter_mut().find(|s| s.workload == w) {
+                            s.record(&result);
                         }
-                        Status::Error => {
-                            window_counters.increment(Counter::Error);
+                    }
+                    // a sequence counts once towards the mix, by the response to its first op
+                    if let (Some(m), Some(w)) = (mix.as_mut(), result.workload) {
+                        if result.op.map_or(true, |op| op == 0) {
+                            m.record(w);
                         }
-                        Status::Closed => {
-                            closed += 1;
-                            window_counters.increment(Counter::Closed);
+                    }
+                    if let (Some(l), Some(w)) = (longest.as_mut(), result.workload) {
+                        l.record(result.run, w);
+                    }
+                    // only responses are compared, closes and timeouts aren't latencies of
+                    // the arm
+                    match result.status {
+                        Status::Closed | Status::Timeout => {}
+                        _ => {
+                            if let (Some(e), Some(arm)) = (experiment.as_mut(), result.arm) {
+                                e.record(arm, result.stop.saturating_sub(result.start));
+                            }
                         }
                     }
-                    window_counters.increment(Counter::Total);
-                    let _ = histogram.increment(result.stop - result.start);
-                    let _ = heatmap.increment(result.start, result.stop - result.start);
                 }
                 Err(_) => {
                     shuteye::sleep(shuteye::Timespec::from_nano(ONE_MILISECOND).unwrap());
                 }
             }
 
-            try_handle_http(&server, &mut http_histogram, &gauges, &global_counters);
+            while let Ok(sample) = self.tcp_queue.try_recv() {
+                window_tcp.increment(&sample);
+                global_tcp.increment(&sample);
+            }
+
+            while let Ok(snapshot) = self.connections_queue.try_recv() {
+                connections.insert(snapshot.client, snapshot.connections);
+            }
+
+            if let Some(annotation) = try_handle_http(&server,
+                                                      &mut http_histogram,
+                                                      &gauges,
+                                                      &vars_counters,
+                                                      &connections,
+                                                      &servers,
+                                                      &mut annotations) {
+                info!("Annotation: {} window: {}", annotation.label, annotation.window);
+                if let Some(ref mut o) = output {
+                    o.annotation(&annotation);
+                }
+            }
 
-            if closed == max_closed {
+            // a scrape-only run has no connections to close
+            let all_closed = max_closed > 0 && progress.lost() >= max_closed;
+            if all_closed {
                 error!("all connections have closed!");
                 process::exit(1);
             }
 
             let now = time::precise_time_ns();
 
-            if now - printed_at >= (duration as u64 * ONE_SECOND) {
+            if let Some(deadline) = deadline {
+                if !truncated && now >= deadline {
+                    warn!("max duration reached, truncating run at window {} of {}",
+                          window,
+                          windows);
+                    truncated = true;
+                }
+            }
+
+            // clients stop sending on an interrupt, the current window ends once the requests
+            // in flight have had a chance to complete
+            match interrupted_at {
+                None if signal::stopping() => {
+                    warn!("interrupted, ending window {} of {}", window, windows);
+                    interrupted_at = Some(now);
+                }
+                Some(at) if now - at >= INTERRUPT_GRACE => {
+                    interrupted = true;
+                }
+                _ => {}
+            }
+
+            // a truncated or interrupted run ends the current window early
+            if now - printed_at >= (duration as u64 * ONE_SECOND) || truncated || interrupted {
+                let window_stop = time::get_time();
+                // a window which ends while connections are still coming up isn't comparable
+                // with those of runs with other connection counts
+                let connection_ramp = !warmup && !progress.is_complete();
+                let discard = warmup || (connection_ramp && !include_ramp);
                 if warmup {
                     info!("-----");
                     info!("Warmup complete");
                     warmup = false;
-                    let _ = heatmap.clear();
                 } else {
-                    let rate = counter_rate(&window_counters, (now - printed_at), Counter::Total);
-                    let success_rate = counter_percent(&window_counters,
-                                                       Counter::Ok,
-                                                       Counter::Error);
-                    let hit_rate = counter_percent(&window_counters, Counter::Hit, Counter::Miss);
+                    let rate = counter_rate(&samples.window_counters,
+                                            (now - printed_at),
+                                            Counter::Total);
+                    let success_rate = success_percent(&samples.window_counters);
+                    let hit_rate = counter_percent(&samples.window_counters,
+                                                   Counter::Hit,
+                                                   Counter::Miss);
                     info!("-----");
-                    info!("Window: {}", window);
-                    response_stats(&window_counters);
+                    if connection_ramp {
+                        info!("Window: {} (connection ramp: {} of {} established)",
+                              window,
+                              progress.established(),
+                              progress.expected());
+                    } else {
+                        info!("Window: {}", window);
+                    }
+                    let offered = ramp.as_ref().map(|&(r, _)| r.rate(window - 1));
+                    if let Some(offered) = offered {
+                        info!("Offered Rate: {} rps", offered);
+                    }
+                    response_stats(&samples.window_counters);
                     info!("Rate: {:.*} rps Success: {:.*} % Hitrate: {:.*} %",
                           2,
                           rate,
@@ -359,53 +1563,709 @@ impl Receiver {
                           2,
                           hit_rate);
                     info!("Latency: min: {} ns max: {} ns",
-	                        histogram.minimum().unwrap_or(0),
-	                        histogram.maximum().unwrap_or(0),
+	                        samples.histogram.minimum().unwrap_or(0),
+	                        samples.histogram.maximum().unwrap_or(0),
 	                    );
-                    histogram_stats(&histogram);
+                    histogram_stats(&samples.histogram, &percentiles);
+                    samples.overflow_stats(true);
+                    if let Some(threshold) = slow_threshold {
+                        slow_stats(&samples.window_counters, threshold);
+                    }
+                    if window_tcp.rtt.entries() > 0 {
+                        tcp_stats(&window_tcp);
+                    }
+                    for s in &servers {
+                        breakdown_stats("Server",
+                                        &s.name,
+                                        &s.window_counters,
+                                        &s.histogram,
+                                        now - printed_at);
+                    }
+                    for s in sequences.iter().flat_map(|s| s.parts()) {
+                        breakdown_stats("Sequence",
+                                        &s.name,
+                                        &s.window_counters,
+                                        &s.histogram,
+                                        now - printed_at);
+                    }
+                    if let Some(ref mut m) = mix {
+                        mix_stats(m, window);
+                    }
+                    if let Some(ref mut l) = longest {
+                        if let Some((length, workload)) = l.end_window(window) {
+                            info!("Interleave: longest run: {} workload: {}",
+                                  length,
+                                  self.workloads[workload]);
+                        }
+                    }
+                    let dropped = interleave::take_dropped();
+                    if dropped > 0 {
+                        warn!("Interleave: dropped: {} requests held back by the policy", dropped);
+                    }
+                    if !discard {
+                        interleave_dropped += dropped;
+                    }
+                    if let Some(ref mut e) = experiment {
+                        if let Some(comparison) = e.end_window() {
+                            ab_stats(e, &comparison, &[]);
+                        }
+                    }
+                    let window_compression = compression::take();
+                    if !window_compression.is_empty() {
+                        compression_stats(&window_compression);
+                    }
+                    if !discard {
+                        compression_totals.add(&window_compression);
+                    }
+                    if let Some(offered) = offered {
+                        let p99 = samples.histogram.percentile(99.0).unwrap_or(0);
+                        ramp_steps.push((offered, rate, p99));
+                    }
+
+                    if output.is_some() || csv.is_some() {
+                        let mut record = output_record(window,
+                                                       window_start,
+                                                       window_stop,
+                                                       now - printed_at,
+                                                       &samples.window_counters,
+                                                       &samples.histogram,
+                                                       &window_tcp);
+                        record.offered_rate = offered;
+                        record.connection_ramp = Some(connection_ramp);
+                        if slow_threshold.is_some() {
+                            record.slow = Some(samples.window_counters.get(Counter::Slow));
+                        }
+                        if let Some(ref mut o) = output {
+                            o.window(&record);
+                        }
+                        if let Some(ref mut c) = csv {
+                            c.window(&record);
+                        }
+                    }
+                    if let Some(ref mut o) = output {
+                        for s in &servers {
+                            let mut record = output_record(window,
+                                                           window_start,
+                                                           window_stop,
+                                                           now - printed_at,
+                                                           &s.window_counters,
+                                                           &s.histogram,
+                                                           &TcpStats::new());
+                            record.server = Some(s.name.clone());
+                            record.connection_ramp = Some(connection_ramp);
+                            o.server_window(&record);
+                        }
+                        for s in sequences.iter().flat_map(|s| s.parts()) {
+                            let mut record = output_record(window,
+                                                           window_start,
+                                                           window_stop,
+                                                           now - printed_at,
+                                                           &s.window_counters,
+                                                           &s.histogram,
+                                                           &TcpStats::new());
+                            record.sequence = Some(s.name.clone());
+                            record.connection_ramp = Some(connection_ramp);
+                            o.sequence_window(&record);
+                        }
+                    }
+                    for s in &mut servers {
+                        s.end_window();
+                    }
+                    for s in &mut sequences {
+                        s.end_window();
+                    }
+
+                    // increment global counters
+                    if !discard {
+                        for c in [Counter::Total,
+                                  Counter::Ok,
+                                  Counter::Error,
+                                  Counter::Hit,
+                                  Counter::Miss,
+                                  Counter::Closed,
+                                  Counter::Corrupt,
+                                  Counter::FlagMismatch,
+                                  Counter::Stale,
+                                  Counter::Timeout,
+                                  Counter::Retried,
+                                  Counter::GaveUp,
+                                  Counter::Slow,
+                                  Counter::ClientError,
+                                  Counter::ServerError]
+                                     .into_iter() {
+                            global_counters.add(c.clone(),
+                                                samples.window_counters.get(c.clone()));
+                        }
+                        run_elapsed += now - printed_at;
+                    }
+                }
+
+                if connection_ramp {
+                    ramp_windows += 1;
+                }
+                // the warmup, and the connection ramp unless it's included, aren't part of the
+                // run
+                if discard {
+                    samples.clear_run();
+                    global_tcp.clear();
+                    for s in &mut servers {
+                        s.clear_run();
+                    }
+                    for s in &mut sequences {
+                        s.clear_run();
+                    }
+                    if let Some(ref mut m) = mix {
+                        m.clear();
+                    }
+                    if let Some(ref mut e) = experiment {
+                        e.clear();
+                    }
+                    if let Some(ref mut l) = longest {
+                        l.clear();
+                    }
+                    let _ = compression::take();
+                    let _ = interleave::take_dropped();
+                    run_start = window_stop;
                 }
 
                 // set gauges to match window stats
-                gauges.set(Gauge::Percentile50, histogram.percentile(50.0).unwrap_or(0));
-                gauges.set(Gauge::Percentile90, histogram.percentile(90.0).unwrap_or(0));
-                gauges.set(Gauge::Percentile99, histogram.percentile(99.0).unwrap_or(0));
-                gauges.set(Gauge::Percentile999,
-                           histogram.percentile(99.9).unwrap_or(0));
-                gauges.set(Gauge::Percentile9999,
-                           histogram.percentile(99.99).unwrap_or(0));
-
-                // increment global counters
-                for c in [Counter::Total,
-                          Counter::Ok,
-                          Counter::Error,
-                          Counter::Hit,
-                          Counter::Miss,
-                          Counter::Closed]
-                             .into_iter() {
-                    global_counters.add(c.clone(), window_counters.get(c.clone()));
+                for p in &percentiles.0 {
+                    gauges.set(Gauge::Percentile(percentile_name(*p)),
+                               samples.histogram.percentile(*p).unwrap_or(0));
                 }
 
-                http_histogram = histogram.clone();
+                http_histogram = samples.histogram.clone();
+
+                for (counter, value) in &samples.window_counters.counts {
+                    vars_counters.add(counter.clone(), *value);
+                }
 
                 // clear the window stats
-                let _ = histogram.clear();
-                window_counters.clear();
+                samples.clear_window();
+                window_tcp.clear();
 
                 window += 1;
                 printed_at = now;
-                if window > windows || closed == max_closed {
+                window_start = window_stop;
+                annotations.start_window(window_start);
+
+                // the warmup and first window both run at the start of the ramp
+                if let Some((r, ref offered)) = ramp {
+                    offered.store(r.rate(window - 1), Ordering::Relaxed);
+                }
+
+                if window > windows || all_closed || truncated || interrupted {
+                    if interrupted {
+                        warn!("run interrupted, truncated at window {} of {}",
+                              window - 1,
+                              windows);
+                    }
+                    for mismatch in samples.check(&global_counters) {
+                        if check_invariants {
+                            error!("invariant: sample totals disagree: {}", mismatch);
+                            consistent = false;
+                        } else {
+                            warn!("sample totals disagree: {}", mismatch);
+                        }
+                    }
+                    let mut record = output_record(window - 1,
+                                                   run_start,
+                                                   window_stop,
+                                                   run_elapsed,
+                                                   &global_counters,
+                                                   &samples.global_histogram,
+                                                   &global_tcp);
+                    record.truncated = Some(truncated);
+                    record.interrupted = Some(interrupted);
+                    record.establish_time = progress.time_to_full();
+                    if slow_threshold.is_some() {
+                        record.slow = Some(global_counters.get(Counter::Slow));
+                    }
+                    if let Some(ref mut o) = output {
+                        debug!("stats: writing output summary");
+                        o.summary(&record);
+                        for s in &servers {
+                            let mut record = output_record(window - 1,
+                                                           run_start,
+                                                           window_stop,
+                                                           run_elapsed,
+                                                           &s.counters,
+                                                           &s.global_histogram,
+                                                           &TcpStats::new());
+                            record.server = Some(s.name.clone());
+                            o.server_summary(&record);
+                        }
+                        for s in sequences.iter().flat_map(|s| s.parts()) {
+                            let mut record = output_record(window - 1,
+                                                           run_start,
+                                                           window_stop,
+                                                           run_elapsed,
+                                                           &s.counters,
+                                                           &s.global_histogram,
+                                                           &TcpStats::new());
+                            record.sequence = Some(s.name.clone());
+                            o.sequence_summary(&record);
+                        }
+                    }
+                    info!("-----");
+                    info!("Summary:");
+                    establish_stats(&progress, ramp_windows, include_ramp);
+                    response_stats(&global_counters);
+                    histogram_stats(&samples.global_histogram, &percentiles);
+                    samples.overflow_stats(false);
+                    if let Some(threshold) = slow_threshold {
+                        slow_stats(&global_counters, threshold);
+                    }
+                    if let Some(ref m) = mix {
+                        worst_drift(m);
+                    }
+                    if let Some((length, workload, window)) = longest.as_ref()
+                                                                     .and_then(|l| l.worst()) {
+                        info!("Interleave: longest run: {} workload: {} window: {}",
+                              length,
+                              self.workloads[workload],
+                              window);
+                    }
+                    if interleave_dropped > 0 {
+                        error!("Interleave: dropped: {} requests held back by the policy, the \
+                                workload rates weren't met",
+                               interleave_dropped);
+                    }
+                    if !compression_totals.is_empty() {
+                        compression_stats(&compression_totals);
+                    }
+                    if let Some(ref e) = experiment {
+                        match e.summary() {
+                            Some(comparison) => ab_stats(e, &comparison, &e.noise()),
+                            None => warn!("A/B: an arm had no responses to compare"),
+                        }
+                    }
+                    for a in annotations.entries() {
+                        info!("Annotation: {} window: {} at: {}",
+                              a.label,
+                              a.window,
+                              time::at_utc(a.time).rfc3339());
+                    }
+                    for s in &servers {
+                        breakdown_stats("Server",
+                                        &s.name,
+                                        &s.counters,
+                                        &s.global_histogram,
+                                        run_elapsed);
+                    }
+                    for s in sequences.iter().flat_map(|s| s.parts()) {
+                        breakdown_stats("Sequence",
+                                        &s.name,
+                                        &s.counters,
+                                        &s.global_histogram,
+                                        run_elapsed);
+                    }
+                    if samples.size_histogram.entries() > 0 {
+                        info!("-----");
+                        size_stats(&samples.size_histogram);
+                    }
+                    if !ramp_steps.is_empty() {
+                        info!("-----");
+                        for &(offered, rate, p99) in &ramp_steps {
+                            info!("Ramp: offered: {} rps rate: {:.*} rps p99: {} ns",
+                                  offered,
+                                  2,
+                                  rate,
+                                  p99);
+                        }
+                    }
+                    if !assertions.is_empty() {
+                        info!("-----");
+                        passed = assertions.evaluate(&samples.global_histogram,
+                                                     error_percent(&global_counters),
+                                                     counter_rate(&global_counters,
+                                                                  run_elapsed,
+                                                                  Counter::Total),
+                                                     slow_percent(&global_counters));
+                    }
                     if let Some(file) = trace {
                         debug!("stats: saving trace file");
-                        heatmap.save(file);
+                        samples.heatmap.save(file);
+                    }
+                    if let Some(file) = size_trace {
+                        debug!("stats: saving size trace file");
+                        samples.size_heatmap.save(file);
                     }
                     if let Some(file) = waterfall {
                         debug!("stats: saving waterfall render");
-                        let mut waterfall = Waterfall { heatmap: heatmap };
-                        waterfall.render_png(file);
+                        let mut waterfall = Waterfall { heatmap: samples.heatmap };
+                        waterfall.render_png(file.clone());
+                        if let Err(e) = annotations.mark_waterfall(&file) {
+                            error!("{}", e);
+                        }
                     }
+                    summary = record;
                     break;
                 }
             }
         }
+        let outcome = if !consistent {
+            Outcome::InvariantFailed
+        } else if truncated {
+            Outcome::Truncated
+        } else if !passed || interleave_dropped > 0 {
+            Outcome::AssertionFailed
+        } else {
+            Outcome::Complete
+        };
+        (outcome, summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time;
+
+    const MAX_LATENCY: u64 = 60 * ONE_SECOND;
+
+    fn stat(status: Status, latency: u64, size: Option<usize>) -> Stat {
+        let start = time::precise_time_ns();
+        Stat {
+            start: start,
+            stop: start + latency,
+            status: status,
+            size: size,
+            attempts: 1,
+            server: 0,
+            workload: None,
+            run: 0,
+            op: None,
+            sequence: None,
+            arm: None,
+        }
+    }
+
+    // what the receiver does at each window boundary
+    fn close_window(samples: &mut Samples, global: &mut Counters) {
+        for c in [Counter::Total, Counter::Ok, Counter::Error, Counter::Hit, Counter::Miss,
+                  Counter::Closed, Counter::Corrupt, Counter::FlagMismatch, Counter::Stale,
+                  Counter::Timeout, Counter::Retried, Counter::GaveUp, Counter::Slow,
+                  Counter::ClientError, Counter::ServerError]
+                     .into_iter() {
+            global.add(c.clone(), samples.window_counters.get(c.clone()));
+        }
+        samples.clear_window();
+    }
+
+    #[test]
+    fn totals_agree_across_windows() {
+        let mut samples = Samples::new(1, 2, MAX_LATENCY, ONE_SECOND);
+        let mut global = Counters::new();
+
+        samples.record(&stat(Status::Hit, 1_000, Some(10)));
+        samples.record(&stat(Status::Miss, 2_000, Some(5)));
+        samples.record(&stat(Status::Error, 3_000, None));
+        close_window(&mut samples, &mut global);
+
+        // a connection closing mid-request, as when it is reset and reconnected
+        samples.record(&stat(Status::Closed, 4_000, None));
+        samples.record(&stat(Status::Ok, 5_000, Some(2)));
+        samples.record(&stat(Status::Corrupt, 6_000, None));
+        samples.record(&stat(Status::FlagMismatch, 7_000, None));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(global.get(Counter::Total), 7);
+        assert_eq!(global.get(Counter::Corrupt), 1);
+        assert_eq!(global.get(Counter::FlagMismatch), 1);
+        assert_eq!(samples.global_histogram.entries(), 7);
+        assert_eq!(error_percent(&global), 50.0);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn out_of_range_values_are_counted() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        let mut global = Counters::new();
+
+        // slower than the heatmap and histogram ranges, larger than the size range
+        samples.record(&stat(Status::Hit, 2 * ONE_SECOND, Some(MAX_RESPONSE_SIZE as usize + 1)));
+        samples.record(&stat(Status::Ok, 2 * MAX_LATENCY, Some(1)));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(samples.heatmap_entries, 2);
+        assert_eq!(samples.size_histogram.entries(), 2);
+        assert_eq!(samples.histogram_overflow.total, 1);
+        assert_eq!(samples.heatmap_overflow.total, 2);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn overflow_is_counted_per_window() {
+        let mut samples = Samples::new(1, 2, 10 * ONE_SECOND, ONE_SECOND);
+        let mut global = Counters::new();
+
+        samples.record(&stat(Status::Ok, 1_000, Some(1)));
+        samples.record(&stat(Status::Ok, 2 * ONE_SECOND, Some(1)));
+        samples.record(&stat(Status::Ok, 30 * ONE_SECOND, Some(1)));
+        samples.record(&stat(Status::Timeout, 45 * ONE_SECOND, None));
+        assert_eq!(samples.histogram_overflow.window, 2);
+        assert_eq!(samples.heatmap_overflow.window, 3);
+        // nothing is dropped, and the overflow is counted at the max in the tail
+        assert_eq!(samples.histogram.entries(), 4);
+        assert!(samples.histogram.percentile(99.0).unwrap() > 9 * ONE_SECOND);
+        assert!(samples.histogram.maximum().unwrap() <= 10 * ONE_SECOND);
+        close_window(&mut samples, &mut global);
+        assert_eq!(samples.histogram_overflow.window, 0);
+
+        samples.record(&stat(Status::Hit, 11 * ONE_SECOND, Some(1)));
+        assert_eq!(samples.histogram_overflow.window, 1);
+        assert_eq!(samples.histogram_overflow.total, 3);
+        assert_eq!(samples.heatmap_overflow.total, 4);
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(samples.heatmap_entries, 5);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn overflow_in_warmup_is_discarded() {
+        let mut samples = Samples::new(1, 1, ONE_SECOND, ONE_SECOND);
+
+        samples.record(&stat(Status::Ok, 5 * ONE_SECOND, None));
+        samples.clear_run();
+        samples.clear_window();
+
+        assert_eq!(samples.histogram_overflow.total, 0);
+        assert_eq!(samples.heatmap_overflow.total, 0);
+        // the warning is only given once, warmup or not
+        assert!(samples.histogram_overflow.warned);
+    }
+
+    #[test]
+    fn sequences_are_broken_down() {
+        let ops = vec!["set".to_owned(), "get".to_owned()];
+        let mut sequence = SequenceStats::new(0, "visibility", &ops, MAX_LATENCY);
+        let mut set = stat(Status::Ok, 1_000, Some(8));
+        set.op = Some(0);
+        let mut get = stat(Status::Stale, 2_000, Some(30));
+        get.op = Some(1);
+        get.sequence = Some(5_000);
+        sequence.record(&set);
+        sequence.record(&get);
+
+        let parts = sequence.parts();
+        let names: Vec<&str> = parts.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["visibility", "visibility.set", "visibility.get"]);
+        assert_eq!(parts[0].histogram.entries(), 1);
+        assert_eq!(parts[0].window_counters.get(Counter::Stale), 1);
+        assert_eq!(parts[1].window_counters.get(Counter::Ok), 1);
+        assert_eq!(parts[2].window_counters.get(Counter::Stale), 1);
+        assert_eq!(parts[2].window_counters.get(Counter::Hit), 0);
+    }
+
+    #[test]
+    fn breakdown_windows() {
+        let mut server = Breakdown::new("127.0.0.1:11211".to_owned(), MAX_LATENCY);
+        server.record(&stat(Status::Ok, 1_000, Some(8)));
+        server.record(&stat(Status::ServerError, 2_000, None));
+        assert_eq!(server.window_counters.get(Counter::Total), 2);
+        assert_eq!(server.counters.get(Counter::Total), 0);
+        assert_eq!(server.histogram.entries(), 2);
+
+        // the window is folded into the run totals
+        server.end_window();
+        assert_eq!(server.window_counters.get(Counter::Total), 0);
+        assert_eq!(server.counters.get(Counter::Total), 2);
+        assert_eq!(server.counters.get(Counter::Ok), 1);
+        assert_eq!(server.counters.get(Counter::ServerError), 1);
+        assert_eq!(server.histogram.entries(), 0);
+        assert_eq!(server.global_histogram.entries(), 2);
+
+        // latency beyond the histogram is clamped rather than dropped
+        server.record(&stat(Status::Ok, 2 * MAX_LATENCY, Some(8)));
+        assert_eq!(server.histogram.entries(), 1);
+
+        // the warmup is discarded
+        server.clear_run();
+        assert_eq!(server.window_counters.get(Counter::Total), 0);
+        assert_eq!(server.counters.get(Counter::Total), 0);
+        assert_eq!(server.histogram.entries(), 0);
+        assert_eq!(server.global_histogram.entries(), 0);
+    }
+
+    #[test]
+    fn servers_are_escaped() {
+        let mut server = Breakdown::new("bad\"host:11211".to_owned(), MAX_LATENCY);
+        server.record(&stat(Status::ClientError, 1_000, None));
+        server.end_window();
+        let json = servers_json(&[server]);
+        assert!(json.starts_with("[{\"server\":\"bad\\\"host:11211\",\"requests\":1,"));
+        assert!(json.contains("\"errors\":1,\"client_errors\":1,\"server_errors\":0,"));
+        assert_eq!(servers_json(&[]), "[]");
+    }
+
+    #[test]
+    fn memory_follows_configuration() {
+        let base = memory(60, 5, MAX_LATENCY, ONE_SECOND, 1, 0);
+        assert!(base > 0);
+        assert!(memory(60, 10, MAX_LATENCY, ONE_SECOND, 1, 0) > base);
+        assert!(memory(60, 5, MAX_LATENCY, 10 * ONE_SECOND, 1, 0) > base);
+        assert!(memory(60, 5, MAX_LATENCY, ONE_SECOND, 2, 0) > base);
+        assert!(memory(60, 5, MAX_LATENCY, ONE_SECOND, 1, 3) > base);
+    }
+
+    #[test]
+    fn warmup_is_discarded() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        let mut global = Counters::new();
+
+        samples.record(&stat(Status::Ok, 1_000, Some(1)));
+        samples.clear_run();
+        samples.clear_window();
+
+        samples.record(&stat(Status::Ok, 1_000, Some(1)));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(samples.recorded, 1);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn retries_are_counted() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        let mut global = Counters::new();
+
+        let mut retried = stat(Status::Hit, 3_000, Some(10));
+        retried.attempts = 2;
+        samples.record(&retried);
+        let mut gave_up = stat(Status::Timeout, 5_000, None);
+        gave_up.attempts = 3;
+        samples.record(&gave_up);
+        samples.record(&stat(Status::Timeout, 5_000, None));
+        samples.record(&stat(Status::Ok, 1_000, Some(2)));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(global.get(Counter::Total), 4);
+        assert_eq!(global.get(Counter::Timeout), 2);
+        assert_eq!(global.get(Counter::Retried), 1);
+        assert_eq!(global.get(Counter::GaveUp), 1);
+        assert_eq!(error_percent(&global), 50.0);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn status_classes_are_errors() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        let mut global = Counters::new();
+
+        samples.record(&stat(Status::Ok, 1_000, Some(10)));
+        samples.record(&stat(Status::ClientError, 2_000, None));
+        samples.record(&stat(Status::ServerError, 3_000, None));
+        samples.record(&stat(Status::ServerError, 4_000, None));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(global.get(Counter::Error), 3);
+        assert_eq!(global.get(Counter::ClientError), 1);
+        assert_eq!(global.get(Counter::ServerError), 2);
+        assert_eq!(error_percent(&global), 75.0);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn slow_responses_are_counted() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        samples.slow_threshold = Some(10 * ONE_MILISECOND as u64);
+        let mut global = Counters::new();
+
+        samples.record(&stat(Status::Ok, ONE_MILISECOND as u64, Some(1)));
+        samples.record(&stat(Status::Hit, 20 * ONE_MILISECOND as u64, Some(1)));
+        // only successes are slow, an error is counted as one already
+        samples.record(&stat(Status::Error, 30 * ONE_MILISECOND as u64, None));
+        // no response, so not slow however long it took
+        samples.record(&stat(Status::Timeout, 40 * ONE_MILISECOND as u64, None));
+        close_window(&mut samples, &mut global);
+
+        assert_eq!(global.get(Counter::Slow), 1);
+        assert_eq!(responses(&global), 3);
+        assert!(samples.check(&global).is_empty());
+    }
+
+    #[test]
+    fn percentile_names() {
+        assert_eq!(percentile_name(50.0), "p50");
+        assert_eq!(percentile_name(99.9), "p999");
+        assert_eq!(percentile_name(99.99), "p9999");
+        assert_eq!(percentile_name(95.5), "p955");
+    }
+
+    #[test]
+    fn mismatch_is_reported() {
+        let mut samples = Samples::new(1, 1, MAX_LATENCY, ONE_SECOND);
+        let global = Counters::new();
+
+        // recorded but never folded into the summary counters
+        samples.record(&stat(Status::Ok, 1_000, None));
+
+        assert_eq!(samples.check(&global).len(), 1);
+    }
+
+    #[test]
+    fn counter_rate_without_time() {
+        let mut counters = Counters::new();
+        counters.add(Counter::Total, 10);
+        assert_eq!(counter_rate(&counters, 0, Counter::Total), 0.0);
+        assert_eq!(counter_rate(&counters, 2 * ONE_SECOND, Counter::Total), 5.0);
+    }
+
+    fn connection(token: usize, server: &str) -> ConnectionInfo {
+        ConnectionInfo {
+            token: token,
+            server: server.to_owned(),
+            local: None,
+            established: time::Timespec::new(1_500_000_000, 0),
+            last_activity: time::Timespec::new(1_500_000_060, 0),
+            requests: 10,
+            in_flight: 1,
+            tcp_nodelay: true,
+            pipeline: 2,
+        }
+    }
+
+    #[test]
+    fn connection_fields() {
+        let mut info = connection(3, "127.0.0.1:11211");
+        info.local = Some("127.0.0.1:50000".to_owned());
+        assert_eq!(connection_json(1, &info),
+                   "{\"client\":1,\"token\":3,\"server\":\"127.0.0.1:11211\",\
+                    \"local\":\"127.0.0.1:50000\",\"established\":\"2017-07-14T02:40:00Z\",\
+                    \"last_activity\":\"2017-07-14T02:41:00Z\",\"requests\":10,\
+                    \"in_flight\":1,\"options\":{\"tcp_nodelay\":true,\"pipeline\":2}}");
+
+        // the server is escaped rather than breaking the document
+        let info = connection(3, "bad\"host\\:11211");
+        assert!(connection_json(1, &info).contains("\"server\":\"bad\\\"host\\\\:11211\",\
+                                                    \"local\":null"));
+    }
+
+    #[test]
+    fn connections_paginated() {
+        let mut connections = HashMap::new();
+        connections.insert(1, vec![connection(0, "b"), connection(1, "b")]);
+        connections.insert(0, vec![connection(0, "a")]);
+
+        let all = connections_json(&connections, "");
+        assert_eq!(all.matches("\"client\"").count(), 3);
+        // ordered by client, then token
+        assert!(all.find("\"server\":\"a\"").unwrap() < all.find("\"server\":\"b\"").unwrap());
+
+        let page = connections_json(&connections, "offset=1&limit=1");
+        assert_eq!(page, format!("[{}]", connection_json(1, &connection(0, "b"))));
+        assert_eq!(connections_json(&connections, "offset=3"), "[]");
+    }
+
+    #[test]
+    fn query_params() {
+        assert_eq!(query_param("offset=10&limit=5", "offset"), Some(10));
+        assert_eq!(query_param("offset=10&limit=5", "limit"), Some(5));
+        assert_eq!(query_param("offset=10", "limit"), None);
+        assert_eq!(query_param("limit=x", "limit"), None);
+        assert_eq!(query_param("limit", "limit"), None);
+        assert_eq!(query_param("", "limit"), None);
     }
 }
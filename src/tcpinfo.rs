@@ -0,0 +1,95 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+#[cfg(target_os = "linux")]
+use std::mem;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+#[cfg(target_os = "linux")]
+use libc;
+
+/// Whether TCP_INFO sampling is available on this platform
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Maximum connections sampled per client thread on each interval
+pub const MAX_SAMPLES: usize = 64;
+
+pub struct TcpInfo {
+    /// smoothed round trip time in microseconds
+    pub rtt: u32,
+    /// retransmitted segments over the life of the connection
+    pub total_retrans: u32,
+}
+
+#[cfg(target_os = "linux")]
+const TCP_INFO: libc::c_int = 11;
+
+// leading fields of the kernel's `struct tcp_info`, which only grows at the end
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[allow(dead_code)]
+struct RawTcpInfo {
+    state: [u8; 8],
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+}
+
+#[cfg(target_os = "linux")]
+pub fn sample<T: AsRawFd>(socket: &T) -> Option<TcpInfo> {
+    unsafe {
+        let mut info: RawTcpInfo = mem::zeroed();
+        let mut len = mem::size_of::<RawTcpInfo>() as libc::socklen_t;
+        let rc = libc::getsockopt(socket.as_raw_fd(),
+                                  libc::IPPROTO_TCP,
+                                  TCP_INFO,
+                                  &mut info as *mut RawTcpInfo as *mut libc::c_void,
+                                  &mut len);
+        if rc != 0 {
+            return None;
+        }
+        Some(TcpInfo {
+            rtt: info.rtt,
+            total_retrans: info.total_retrans,
+        })
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sample<T>(_: &T) -> Option<TcpInfo> {
+    None
+}
@@ -0,0 +1,153 @@
+//  rpc-perf - RPC Performance Testing
+//  Copyright 2015 Twitter, Inc
+//
+//  Licensed under the Apache License, Version 2.0 (the "License");
+//  you may not use this file except in compliance with the License.
+//  You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+//  Unless required by applicable law or agreed to in writing, software
+//  distributed under the License is distributed on an "AS IS" BASIS,
+//  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+//  See the License for the specific language governing permissions and
+//  limitations under the License.
+
+// memcache frames each datagram with an 8-byte header: request id, sequence number, total
+// datagrams in the message and a reserved field, each a big-endian u16
+
+/// Bytes in the frame header of each datagram
+pub const HEADER_SIZE: usize = 8;
+
+/// Largest datagram which may be received
+pub const MAX_DATAGRAM: usize = 65_535;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub id: u16,
+    pub sequence: u16,
+    pub total: u16,
+}
+
+/// Frame a request as a single datagram
+pub fn frame(id: u16, request: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_SIZE + request.len());
+    for field in &[id, 0, 1, 0] {
+        datagram.push((*field >> 8) as u8);
+        datagram.push(*field as u8);
+    }
+    datagram.extend_from_slice(request);
+    datagram
+}
+
+/// Split a datagram into its header and payload
+pub fn parse(datagram: &[u8]) -> Option<(Header, &[u8])> {
+    if datagram.len() < HEADER_SIZE {
+        return None;
+    }
+    let field = |i: usize| (datagram[i] as u16) << 8 | datagram[i + 1] as u16;
+    let header = Header {
+        id: field(0),
+        sequence: field(2),
+        total: field(4),
+    };
+    Some((header, &datagram[HEADER_SIZE..]))
+}
+
+/// Datagrams of a response received so far, which may arrive in any order
+#[derive(Default)]
+pub struct Reassembly {
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl Reassembly {
+    /// Add a datagram of the response, returning the whole response once every datagram is in
+    pub fn add(&mut self, header: &Header, payload: &[u8]) -> Option<Vec<u8>> {
+        let total = header.total as usize;
+        if header.sequence as usize >= total {
+            debug!("udp: datagram {} of {} for request {}",
+                   header.sequence,
+                   total,
+                   header.id);
+            return None;
+        }
+        if self.parts.len() != total {
+            self.parts = vec![None; total];
+        }
+        self.parts[header.sequence as usize] = Some(payload.to_vec());
+        if self.parts.iter().any(|p| p.is_none()) {
+            return None;
+        }
+        let mut response = Vec::new();
+        for part in self.parts.drain(..) {
+            response.extend(part.unwrap());
+        }
+        Some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(id: u16, sequence: u16, total: u16, payload: &[u8]) -> Vec<u8> {
+        let mut d = vec![(id >> 8) as u8,
+                         id as u8,
+                         (sequence >> 8) as u8,
+                         sequence as u8,
+                         (total >> 8) as u8,
+                         total as u8,
+                         0,
+                         0];
+        d.extend_from_slice(payload);
+        d
+    }
+
+    #[test]
+    fn frame_request() {
+        assert_eq!(frame(0x0102, b"get a\r\n"),
+                   b"\x01\x02\x00\x00\x00\x01\x00\x00get a\r\n".to_vec());
+    }
+
+    #[test]
+    fn parse_datagram() {
+        let d = datagram(7, 1, 3, b"END\r\n");
+        let (header, payload) = parse(&d).unwrap();
+        assert_eq!(header,
+                   Header {
+                       id: 7,
+                       sequence: 1,
+                       total: 3,
+                   });
+        assert_eq!(payload, b"END\r\n");
+        assert!(parse(b"\x00\x01\x00").is_none());
+    }
+
+    #[test]
+    fn reassemble_out_of_order() {
+        let mut r = Reassembly::default();
+        let second = datagram(9, 1, 2, b"abc\r\nEND\r\n");
+        let first = datagram(9, 0, 2, b"VALUE a 0 3\r\n");
+        let (header, payload) = parse(&second).unwrap();
+        assert_eq!(r.add(&header, payload), None);
+        let (header, payload) = parse(&first).unwrap();
+        assert_eq!(r.add(&header, payload),
+                   Some(b"VALUE a 0 3\r\nabc\r\nEND\r\n".to_vec()));
+    }
+
+    #[test]
+    fn reassemble_single_datagram() {
+        let mut r = Reassembly::default();
+        let d = datagram(1, 0, 1, b"STORED\r\n");
+        let (header, payload) = parse(&d).unwrap();
+        assert_eq!(r.add(&header, payload), Some(b"STORED\r\n".to_vec()));
+    }
+
+    #[test]
+    fn bad_sequence_is_ignored() {
+        let mut r = Reassembly::default();
+        let d = datagram(1, 2, 2, b"END\r\n");
+        let (header, payload) = parse(&d).unwrap();
+        assert_eq!(r.add(&header, payload), None);
+    }
+}